@@ -0,0 +1,216 @@
+//! Names of one's own for currencies and pairs, from the config file: `home = "PLN"` under
+//! `[aliases]` works anywhere a currency code does, and `trip = "EUR/PLN"` under `[pairs]`
+//! is converted with `fav trip 250`.
+
+use crate::cli::FILLER_WORDS;
+use crate::codes::validate_currency_code;
+use crate::config::Config;
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+/// The `[aliases]` table, checked, keyed by lower-cased name.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Aliases(BTreeMap<String, String>);
+
+impl Aliases {
+    /// Checks the `[aliases]` of a config file. Names are letters only, so they read as
+    /// codes on the command line, and may not be a currency code, which they would hide,
+    /// or a filler word such as `to`. Every alias has to lead to a currency code.
+    pub fn new(table: &BTreeMap<String, String>) -> Result<Aliases, String> {
+        let mut aliases = BTreeMap::new();
+        for (name, target) in table {
+            let name = name.trim().to_lowercase();
+            check_alias_name(&name)?;
+            if aliases
+                .insert(name.clone(), target.trim().to_string())
+                .is_some()
+            {
+                return Err(format!("alias '{}' is defined twice", name));
+            }
+        }
+        let aliases = Aliases(aliases);
+        for name in aliases.0.keys() {
+            aliases.resolve(name)?;
+        }
+        Ok(aliases)
+    }
+
+    /// The currency `token` stands for, in any case: the code an alias leads to, through
+    /// any aliases it names in turn, or `token` itself upper-cased when it is no alias. An
+    /// alias that ends anywhere but at a currency code, or comes back to itself, is an error.
+    pub fn resolve(&self, token: &str) -> Result<String, String> {
+        let mut chain: Vec<&str> = Vec::new();
+        let mut current = token.trim();
+        while let Some((name, target)) = self.0.get_key_value(&current.to_lowercase()) {
+            if chain.contains(&name.as_str()) {
+                chain.push(name);
+                return Err(format!(
+                    "alias '{}' loops back on itself: {}",
+                    chain[0],
+                    chain.join(" -> ")
+                ));
+            }
+            chain.push(name);
+            current = target;
+        }
+        if let Some(first) = chain.first() {
+            if validate_currency_code(current).is_err() {
+                return Err(format!(
+                    "alias '{}' leads to '{}', which is neither a currency code nor an alias",
+                    first, current
+                ));
+            }
+        }
+        Ok(current.to_uppercase())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(&name.trim().to_lowercase()).map(String::as_str)
+    }
+
+    /// The aliases by name, as `(home, PLN)`.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, target)| (name.as_str(), target.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Points `name` at `target`, a currency code or another alias, replacing any alias of
+    /// that name. The aliases are only changed if they stay valid.
+    pub fn set(&mut self, name: &str, target: &str) -> Result<(), String> {
+        let name = name.trim().to_lowercase();
+        let target = target.trim();
+        let target = if self.0.contains_key(&target.to_lowercase()) {
+            target.to_lowercase()
+        } else {
+            target.to_uppercase()
+        };
+        let mut table = self.0.clone();
+        table.insert(name, target);
+        *self = Aliases::new(&table)?;
+        Ok(())
+    }
+
+    /// Drops the alias `name`, returning whether there was one. An alias that led through
+    /// it then fails to resolve, so the aliases are only changed if they stay valid.
+    pub fn remove(&mut self, name: &str) -> Result<bool, String> {
+        let mut table = self.0.clone();
+        if table.remove(&name.trim().to_lowercase()).is_none() {
+            return Ok(false);
+        }
+        *self = Aliases::new(&table)?;
+        Ok(true)
+    }
+}
+
+fn check_alias_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!(
+            "invalid alias name '{}', expected letters only, e.g. home",
+            name
+        ));
+    }
+    if validate_currency_code(name).is_ok() {
+        return Err(format!(
+            "alias '{}' collides with the currency code {}; pick another name",
+            name,
+            name.to_uppercase()
+        ));
+    }
+    if FILLER_WORDS.contains(&name) {
+        return Err(format!(
+            "alias '{}' would be read as a filler word, as in `100 USD to EUR`; pick another name",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a pair such as `EUR/PLN` or `eur/home`, a `[pairs]` entry or a `history --pair`,
+/// into its source and target codes.
+pub fn parse_pair(pair: &str, aliases: &Aliases) -> Result<(String, String), String> {
+    match pair.split_once('/') {
+        Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+            Ok((aliases.resolve(from)?, aliases.resolve(to)?))
+        }
+        _ => Err(format!(
+            "invalid pair '{}', expected FROM/TO, e.g. EUR/PLN",
+            pair
+        )),
+    }
+}
+
+/// `contents` of a config file with its `[aliases]` table replaced by `aliases`, added at
+/// the end if there is none and removed if `aliases` is empty. The rest of the file,
+/// comments included, is left as it is. Aliases written some other way, e.g. as an inline
+/// table, can't be edited like this and are an error.
+pub fn with_aliases(contents: &str, aliases: &Aliases) -> Result<String, String> {
+    let lines: Vec<&str> = contents.lines().collect();
+    let is_header = |line: &str| line.trim_start().starts_with('[');
+    let start = lines
+        .iter()
+        .position(|line| line.split('#').next().unwrap_or_default().trim() == "[aliases]");
+    let (before, after) = match start {
+        Some(start) => {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|line| is_header(line))
+                .map_or(lines.len(), |end| start + 1 + end);
+            (&lines[..start], &lines[end..])
+        }
+        None => (&lines[..], &[][..]),
+    };
+    let mut edited: Vec<String> = before.iter().map(|line| line.to_string()).collect();
+    if !aliases.is_empty() {
+        if start.is_none() && edited.last().is_some_and(|line| !line.trim().is_empty()) {
+            edited.push(String::new());
+        }
+        edited.push("[aliases]".to_string());
+        for (name, target) in aliases.iter() {
+            edited.push(format!("{} = {}", name, toml::Value::from(target)));
+        }
+        if !after.is_empty() {
+            edited.push(String::new());
+        }
+    }
+    edited.extend(after.iter().map(|line| line.to_string()));
+    let mut edited = edited.join("\n");
+    if !edited.is_empty() {
+        edited.push('\n');
+    }
+    let written = Config::parse(&edited)
+        .ok()
+        .map(|config| config.aliases.unwrap_or_default());
+    match written.map(|table| Aliases::new(&table)) {
+        Some(Ok(written)) if written == *aliases => Ok(edited),
+        _ => Err("the aliases are not written as an [aliases] table".to_string()),
+    }
+}
+
+/// The aliases in the config file at `path`, none if it doesn't exist yet.
+pub fn load_aliases(path: &Path) -> Result<(String, Aliases), String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+    };
+    let invalid = |e: String| format!("invalid config {}: {}", path.display(), e);
+    let config = Config::parse(&contents).map_err(|e| invalid(e.to_string()))?;
+    let aliases = Aliases::new(&config.aliases.unwrap_or_default()).map_err(invalid)?;
+    Ok((contents, aliases))
+}
+
+/// Writes `aliases` into the config file at `path`, whose `contents` were read with
+/// [`load_aliases`], creating the file and its directory if need be.
+pub fn save_aliases(path: &Path, contents: &str, aliases: &Aliases) -> Result<(), String> {
+    let edited = with_aliases(contents, aliases)
+        .map_err(|e| format!("cannot edit {}: {}; edit it by hand", path.display(), e))?;
+    path.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(path, edited))
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))
+}
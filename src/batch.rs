@@ -0,0 +1,117 @@
+use crate::aliases::Aliases;
+use crate::models::parse_amount;
+use rust_decimal::Decimal;
+
+#[derive(Debug)]
+pub struct BatchRow {
+    pub from: String,
+    pub to: String,
+    pub amount: Decimal,
+}
+
+impl BatchRow {
+    /// The row with any alias among its currencies replaced by the code it stands for.
+    pub fn resolve(self, aliases: &Aliases) -> Result<BatchRow, String> {
+        Ok(BatchRow {
+            from: aliases.resolve(&self.from)?,
+            to: aliases.resolve(&self.to)?,
+            amount: self.amount,
+        })
+    }
+}
+
+/// What a field of a CSV batch line holds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Column {
+    From,
+    To,
+    Amount,
+    /// Any other field, such as a date or a description.
+    Ignored,
+}
+
+pub const DEFAULT_COLUMNS: [Column; 3] = [Column::From, Column::To, Column::Amount];
+
+/// Parses `--columns`: one name per CSV field, where `from`, `to` and `amount` must each
+/// appear once and any other name (or an empty one) marks a field to ignore.
+pub fn parse_columns(spec: &str) -> Result<Vec<Column>, String> {
+    let columns: Vec<Column> = spec
+        .split(',')
+        .map(|name| match name.trim().to_lowercase().as_str() {
+            "from" => Column::From,
+            "to" => Column::To,
+            "amount" => Column::Amount,
+            _ => Column::Ignored,
+        })
+        .collect();
+    for (column, name) in [
+        (Column::From, "from"),
+        (Column::To, "to"),
+        (Column::Amount, "amount"),
+    ] {
+        if columns.iter().filter(|c| **c == column).count() != 1 {
+            return Err(format!(
+                "invalid columns '{}': '{}' must appear exactly once",
+                spec, name
+            ));
+        }
+    }
+    Ok(columns)
+}
+
+/// Parses one `FROM,TO,AMOUNT` line of a batch file.
+pub fn parse_batch_line(line: &str) -> Result<BatchRow, String> {
+    parse_batch_line_with(line, &DEFAULT_COLUMNS)
+}
+
+/// Parses one batch line: CSV fields laid out as `columns`, or, for a line without commas,
+/// `AMOUNT FROM TO` separated by whitespace.
+pub fn parse_batch_line_with(line: &str, columns: &[Column]) -> Result<BatchRow, String> {
+    let malformed = || {
+        let layout: Vec<&str> = columns
+            .iter()
+            .map(|column| match column {
+                Column::From => "FROM",
+                Column::To => "TO",
+                Column::Amount => "AMOUNT",
+                Column::Ignored => "_",
+            })
+            .collect();
+        format!(
+            "expected {} or AMOUNT FROM TO but got '{}'",
+            layout.join(","),
+            line
+        )
+    };
+    let (mut from, mut to, mut amount) = ("", "", "");
+    if line.contains(',') {
+        let fields: Vec<&str> = line
+            .split(',')
+            .map(|field| field.trim().trim_matches('"').trim())
+            .collect();
+        if fields.len() != columns.len() {
+            return Err(malformed());
+        }
+        for (column, field) in columns.iter().zip(fields) {
+            match column {
+                Column::From => from = field,
+                Column::To => to = field,
+                Column::Amount => amount = field,
+                Column::Ignored => {}
+            }
+        }
+    } else {
+        match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [a, f, t] => (amount, from, to) = (a, f, t),
+            _ => return Err(malformed()),
+        }
+    }
+    if from.is_empty() || to.is_empty() {
+        return Err(malformed());
+    }
+    Ok(BatchRow {
+        from: from.to_uppercase(),
+        to: to.to_uppercase(),
+        amount: parse_amount(amount)?,
+    })
+}
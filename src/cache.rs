@@ -1,26 +1,500 @@
-use crate::config::CACHE_FILE;
+use crate::config::{
+    CACHE_FILE, CACHE_IMPORT_CLOCK_SKEW, CACHE_LOCK_TIMEOUT, DEFAULT_PROVIDER, LEGACY_CACHE_FILE,
+};
+use crate::error::CurrencyError;
 use crate::models::CacheItem;
-use serde_json;
+use directories::ProjectDirs;
+use log::{debug, trace, warn};
+use serde_json::Value;
 use std::{
     collections::HashMap,
-    fs::File,
+    env,
+    fs::{self, File, OpenOptions, TryLockError},
     io,
-    io::{BufReader, BufWriter},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-pub fn save_cache(cache: &HashMap<String, CacheItem>) -> Result<(), io::Error> {
-    let file = File::create(CACHE_FILE)?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer(writer, cache)?;
-    Ok(())
+/// Merges `cache` into the file at [`cache_path`], creating its directory if needed; see
+/// [`save_cache_to`].
+pub fn save_cache(cache: &HashMap<String, CacheItem>) -> Result<(), CurrencyError> {
+    let path = cache_path();
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    save_cache_to(&path, cache)
+}
+
+/// Reads the cache from [`cache_path`]; see [`load_cache_at`].
+pub fn load_cache() -> Result<HashMap<String, CacheItem>, CurrencyError> {
+    load_cache_at(&cache_path())
+}
+
+/// Reads the cache from `path`; see [`load_cache_from`]. When that file doesn't exist yet,
+/// a legacy `cache.json` in the working directory is read and copied over once.
+pub fn load_cache_at(path: &Path) -> Result<HashMap<String, CacheItem>, CurrencyError> {
+    let legacy = Path::new(LEGACY_CACHE_FILE);
+    if !path.exists() && legacy.exists() && path != legacy {
+        debug!(
+            "migrating legacy cache {} to {}",
+            legacy.display(),
+            path.display()
+        );
+        let cache = load_cache_from(legacy)?;
+        let copied = match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            Some(dir) => fs::create_dir_all(dir).map_err(CurrencyError::from),
+            None => Ok(()),
+        }
+        .and_then(|()| save_cache_to(path, &cache));
+        if let Err(e) = copied {
+            warn!("could not migrate cache to {}: {}", path.display(), e);
+        }
+        return Ok(cache);
+    }
+    load_cache_from(path)
+}
+
+/// The cache file: `CURRENCY_CLI_CACHE_FILE` when set, otherwise `rates_cache.json` in the
+/// platform cache directory (e.g. `~/.cache/currency_cli` on Linux), or in the working
+/// directory when there is no home directory.
+pub fn cache_path() -> PathBuf {
+    if let Some(path) = env::var_os("CURRENCY_CLI_CACHE_FILE").filter(|path| !path.is_empty()) {
+        return PathBuf::from(path);
+    }
+    match ProjectDirs::from("", "", "currency_cli") {
+        Some(dirs) => dirs.cache_dir().join(CACHE_FILE),
+        None => PathBuf::from(CACHE_FILE),
+    }
+}
+
+/// Summary of a cache file for `cache info`.
+#[derive(Debug, PartialEq)]
+pub struct CacheStats {
+    pub path: PathBuf,
+    /// File size in bytes; zero when the file doesn't exist.
+    pub size: u64,
+    /// Distinct base currencies with cached rates.
+    pub bases: usize,
+    /// Cached rate sets, counting each historical date separately.
+    pub entries: usize,
+    pub oldest: Option<SystemTime>,
+    pub newest: Option<SystemTime>,
+}
+
+pub fn cache_stats(path: &Path) -> Result<CacheStats, CurrencyError> {
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e.into()),
+    };
+    let cache = load_cache_from(path)?;
+    let mut bases: Vec<&str> = cache
+        .values()
+        .filter(|item| !item.is_code_list())
+        .map(|item| item.base.as_str())
+        .collect();
+    bases.sort_unstable();
+    bases.dedup();
+    Ok(CacheStats {
+        path: path.to_path_buf(),
+        size,
+        bases: bases.len(),
+        entries: cache.len(),
+        oldest: cache.values().map(|item| item.timestamp).min(),
+        newest: cache.values().map(|item| item.timestamp).max(),
+    })
+}
+
+/// Drops every entry quoted against `base`, latest and historical, returning how many went.
+pub fn remove_entry(cache: &mut HashMap<String, CacheItem>, base: &str) -> usize {
+    let before = cache.len();
+    cache.retain(|_, item| item.base != base);
+    before - cache.len()
+}
+
+/// Removes `base` from the cache file at `path` under its lock, returning how many entries
+/// went. Unlike loading, editing and saving, this can't be undone by the merge on save.
+pub fn remove_cached_base(path: &Path, base: &str) -> Result<usize, CurrencyError> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    update_cache_at(path, |cache| remove_entry(cache, base))
+}
+
+/// Deletes the cache file at `path`, returning whether there was one.
+pub fn remove_cache_file(path: &Path) -> Result<bool, CurrencyError> {
+    let _lock = lock_cache(path)?;
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Merges `cache` into the file at `path`, so entries saved by other runs since this one
+/// loaded the cache are kept. For a key both sides have, the newer entry wins.
+pub fn save_cache_to(path: &Path, cache: &HashMap<String, CacheItem>) -> Result<(), CurrencyError> {
+    update_cache_at(path, |stored| merge_cache(stored, cache))
+}
+
+/// Re-reads the cache at `path` while holding its lock, applies `update` and writes the
+/// result back, so concurrent runs can't interleave their read-modify-write cycles.
+pub fn update_cache_at<T>(
+    path: &Path,
+    update: impl FnOnce(&mut HashMap<String, CacheItem>) -> T,
+) -> Result<T, CurrencyError> {
+    let _lock = lock_cache(path)?;
+    // Under the lock no other run reads the file, so a corrupt one can be moved aside to
+    // `<path>.corrupt` for inspection before it is replaced.
+    let mut cache = read_cache(path).unwrap_or_else(|e| {
+        let corrupt_path = corrupt_path_for(path);
+        match fs::rename(path, &corrupt_path) {
+            Ok(()) => warn!(
+                "ignoring corrupt cache file {} (moved to {}): {}",
+                path.display(),
+                corrupt_path.display(),
+                e
+            ),
+            Err(_) => warn!("ignoring corrupt cache file {}: {}", path.display(), e),
+        }
+        HashMap::new()
+    });
+    let result = update(&mut cache);
+    write_cache(path, &cache)?;
+    debug!("saved {} cache entries to {}", cache.len(), path.display());
+    Ok(result)
+}
+
+/// `cache export`: the entries of `cache`, or only those quoted against a base in `only`,
+/// as pretty-printed JSON in the cache file's own format.
+pub fn export_cache(cache: &HashMap<String, CacheItem>, only: &[String]) -> String {
+    let exported: HashMap<&String, &CacheItem> = cache
+        .iter()
+        .filter(|(_, item)| only.is_empty() || only.contains(&item.base))
+        .collect();
+    serde_json::to_string_pretty(&exported).expect("cache entries serialize")
 }
 
-pub fn load_cache() -> Result<HashMap<String, CacheItem>, io::Error> {
-    if let Ok(file) = File::open(CACHE_FILE) {
-        let reader = BufReader::new(file);
-        let cache = serde_json::from_reader(reader)?;
-        Ok(cache)
-    } else {
-        Ok(HashMap::new())
+/// Reads a file written by [`export_cache`] for `cache import`, checking every entry before
+/// any is used. A malformed entry, a rate that isn't a positive number or a timestamp
+/// ahead of `now` (by more than [`CACHE_IMPORT_CLOCK_SKEW`]) is an error naming the entry.
+pub fn parse_cache_export(
+    contents: &str,
+    now: SystemTime,
+) -> Result<HashMap<String, CacheItem>, String> {
+    let document: Value =
+        serde_json::from_str(contents).map_err(|e| format!("not a JSON cache export: {}", e))?;
+    let Value::Object(entries) = document else {
+        return Err(
+            "expected an object of cache entries keyed like \"exchangerate-api:USD:latest\""
+                .to_string(),
+        );
+    };
+    let mut cache = HashMap::new();
+    for (key, entry) in entries {
+        let rates = match entry.get("rates") {
+            Some(Value::Object(rates)) => rates,
+            Some(_) => return Err(format!("entry '{}': rates must be an object", key)),
+            None if entry.is_object() => return Err(format!("entry '{}' has no rates", key)),
+            None => return Err(format!("entry '{}' is not an object", key)),
+        };
+        if let Some((code, _)) = rates
+            .iter()
+            .find(|(_, rate)| !rate.as_f64().is_some_and(|rate| rate > 0.0))
+        {
+            return Err(format!(
+                "entry '{}': the {} rate is not a positive number",
+                key, code
+            ));
+        }
+        let item: CacheItem = serde_json::from_value(entry)
+            .map_err(|e| format!("entry '{}' doesn't match the cache format: {}", key, e))?;
+        if item.timestamp > now + CACHE_IMPORT_CLOCK_SKEW {
+            let timestamp = chrono::DateTime::<chrono::Utc>::from(item.timestamp);
+            return Err(format!(
+                "entry '{}' is timestamped {} UTC, in the future",
+                key,
+                timestamp.format("%Y-%m-%d %H:%M:%S")
+            ));
+        }
+        cache.insert(key, item);
     }
+    Ok(migrate_cache(cache))
+}
+
+/// How `cache import` combines the imported entries with the cache.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    /// Keeps the newer of the two entries for a key, like a save.
+    Merge,
+    /// Drops the cache and keeps only the imported entries.
+    Replace,
+}
+
+/// What [`import_cache`] did: how many entries it took, and how many it passed over
+/// because the cache had a newer one.
+#[derive(Debug, PartialEq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+/// Writes `imported` into the cache file at `path` under its lock, as `mode` says.
+pub fn import_cache(
+    path: &Path,
+    imported: HashMap<String, CacheItem>,
+    mode: ImportMode,
+) -> Result<ImportSummary, CurrencyError> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    update_cache_at(path, |stored| match mode {
+        ImportMode::Replace => {
+            let imported_count = imported.len();
+            *stored = imported;
+            ImportSummary {
+                imported: imported_count,
+                skipped: 0,
+            }
+        }
+        ImportMode::Merge => {
+            let skipped = imported
+                .iter()
+                .filter(|(key, item)| {
+                    stored
+                        .get(*key)
+                        .is_some_and(|existing| existing.timestamp > item.timestamp)
+                })
+                .count();
+            merge_cache(stored, &imported);
+            ImportSummary {
+                imported: imported.len() - skipped,
+                skipped,
+            }
+        }
+    })
+}
+
+/// Which entries a save keeps, so the cache can't grow without bound. Date-keyed entries
+/// never go stale, so only their number is capped; the currency lists are always kept. A
+/// limit of 0 is no limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrunePolicy {
+    /// Latest rates written longer ago than this are dropped; `None` keeps them.
+    pub max_age: Option<Duration>,
+    /// Latest-rate entries kept, one per provider and base.
+    pub max_entries: usize,
+    /// Entries of rates on a past date kept.
+    pub max_historical_entries: usize,
+}
+
+impl PrunePolicy {
+    /// Keeps everything.
+    pub const NONE: PrunePolicy = PrunePolicy {
+        max_age: None,
+        max_entries: 0,
+        max_historical_entries: 0,
+    };
+}
+
+/// The keys of the entries [`prune_cache`] removed, each list sorted.
+#[derive(Debug, Default, PartialEq)]
+pub struct PruneReport {
+    /// Latest rates past the policy's `max_age`.
+    pub expired: Vec<String>,
+    /// Entries evicted to stay within the caps: latest rates, then historical ones, each
+    /// least recently written first.
+    pub evicted: Vec<String>,
+}
+
+impl PruneReport {
+    pub fn is_empty(&self) -> bool {
+        self.expired.is_empty() && self.evicted.is_empty()
+    }
+}
+
+/// Drops what `policy` doesn't keep from `cache` as of `now`: expired latest rates first,
+/// then the least recently written entries beyond each cap.
+pub fn prune_cache(
+    cache: &mut HashMap<String, CacheItem>,
+    policy: &PrunePolicy,
+    now: SystemTime,
+) -> PruneReport {
+    let mut report = PruneReport::default();
+    if let Some(max_age) = policy.max_age {
+        cache.retain(|key, item| {
+            let expired = item.date.is_none()
+                && !item.is_code_list()
+                && now.duration_since(item.timestamp).unwrap_or_default() > max_age;
+            if expired {
+                report.expired.push(key.clone());
+            }
+            !expired
+        });
+    }
+    for (historical, cap) in [
+        (false, policy.max_entries),
+        (true, policy.max_historical_entries),
+    ] {
+        let mut entries: Vec<(&String, SystemTime)> = cache
+            .iter()
+            .filter(|(_, item)| !item.is_code_list() && item.date.is_some() == historical)
+            .map(|(key, item)| (key, item.timestamp))
+            .collect();
+        if cap == 0 || entries.len() <= cap {
+            continue;
+        }
+        // Equal timestamps fall back to the key, so the same file prunes the same way.
+        entries.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+        let excess = entries.len() - cap;
+        let evicted: Vec<String> = entries[..excess]
+            .iter()
+            .map(|(key, _)| (*key).clone())
+            .collect();
+        for key in &evicted {
+            cache.remove(key);
+        }
+        report.evicted.extend(evicted);
+    }
+    report.expired.sort();
+    report
+}
+
+/// [`save_cache`], pruning the merged cache by `policy` before it is written.
+pub fn save_pruned_cache(
+    cache: &HashMap<String, CacheItem>,
+    policy: &PrunePolicy,
+) -> Result<PruneReport, CurrencyError> {
+    let path = cache_path();
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    save_pruned_cache_to(&path, cache, policy)
+}
+
+/// [`save_cache_to`], pruning the merged cache by `policy` before it is written. An empty
+/// `cache` only prunes the file, as `cache prune` does.
+pub fn save_pruned_cache_to(
+    path: &Path,
+    cache: &HashMap<String, CacheItem>,
+    policy: &PrunePolicy,
+) -> Result<PruneReport, CurrencyError> {
+    update_cache_at(path, |stored| {
+        merge_cache(stored, cache);
+        prune_cache(stored, policy, SystemTime::now())
+    })
+}
+
+/// Copies entries from `updates` into `stored`, unless `stored` already has a newer one.
+pub fn merge_cache(stored: &mut HashMap<String, CacheItem>, updates: &HashMap<String, CacheItem>) {
+    for (key, item) in updates {
+        match stored.get(key) {
+            Some(existing) if existing.timestamp > item.timestamp => {}
+            _ => {
+                stored.insert(key.clone(), item.clone());
+            }
+        }
+    }
+}
+
+/// Takes an exclusive advisory lock on `<path>.lock`, retrying until [`CACHE_LOCK_TIMEOUT`]
+/// so a stuck run can't block the others forever. The lock is released when the returned
+/// file is dropped. Readers don't lock: writes are atomic renames, so they never see a
+/// partial file.
+fn lock_cache(path: &Path) -> Result<File, io::Error> {
+    let lock_path = sibling_path(path, ".lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)?;
+    let deadline = Instant::now() + CACHE_LOCK_TIMEOUT;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(file),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(20))
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("timed out waiting for cache lock {}", lock_path.display()),
+                ))
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+    }
+}
+
+/// Writes the cache to a temporary file beside `path` and renames it into place, so readers
+/// see either the previous or the new cache but never a partial one.
+fn write_cache(path: &Path, cache: &HashMap<String, CacheItem>) -> Result<(), io::Error> {
+    let temp_path = temp_path_for(path);
+    let result = (|| {
+        let file = File::create(&temp_path)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, cache)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Reads the cache at `path`. A missing file is an empty cache, and so is a corrupt one,
+/// which is reported as a warning rather than an error and left in place; the next write
+/// moves it aside.
+pub fn load_cache_from(path: &Path) -> Result<HashMap<String, CacheItem>, CurrencyError> {
+    Ok(read_cache(path).unwrap_or_else(|e| {
+        warn!("ignoring corrupt cache file {}: {}", path.display(), e);
+        HashMap::new()
+    }))
+}
+
+/// The entries of the file at `path`, none when there is no such file.
+fn read_cache(path: &Path) -> Result<HashMap<String, CacheItem>, serde_json::Error> {
+    let Ok(file) = File::open(path) else {
+        trace!("no cache file at {}", path.display());
+        return Ok(HashMap::new());
+    };
+    let cache: HashMap<String, CacheItem> = serde_json::from_reader(BufReader::new(file))?;
+    trace!("read {} cache entries from {}", cache.len(), path.display());
+    Ok(migrate_cache(cache))
+}
+
+fn corrupt_path_for(path: &Path) -> PathBuf {
+    sibling_path(path, ".corrupt")
+}
+
+/// A per-process temporary name in the same directory, so the rename stays on one filesystem.
+fn temp_path_for(path: &Path) -> PathBuf {
+    sibling_path(path, &format!(".{}.tmp", process::id()))
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Re-keys entries from the old flat format, where the map key was the bare base currency
+/// and items carried no base or provider, as latest rates from the default provider.
+pub fn migrate_cache(cache: HashMap<String, CacheItem>) -> HashMap<String, CacheItem> {
+    cache
+        .into_iter()
+        .map(|(key, mut item)| {
+            if item.base.is_empty() {
+                item.base = key;
+                item.provider = DEFAULT_PROVIDER.to_string();
+                item.date = None;
+            }
+            let key = CacheItem::key(&item.provider, &item.base, item.date.as_deref());
+            (key, item)
+        })
+        .collect()
 }
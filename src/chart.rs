@@ -0,0 +1,173 @@
+//! Terminal charts of a `timeseries`, drawn by `timeseries --chart`.
+
+use crate::output::format_rate;
+use chrono::NaiveDate;
+use std::str::FromStr;
+
+/// Bars of a sparkline, lowest to highest.
+pub const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Narrowest chart area drawn; in a narrower terminal the plain table is printed instead.
+pub const MIN_CHART_WIDTH: usize = 10;
+
+/// Rows of a `--chart full` plot.
+pub const PLOT_HEIGHT: usize = 8;
+
+/// How `timeseries --chart` draws the series.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChartStyle {
+    /// A one-line sparkline between the first and last dates.
+    Spark,
+    /// A plot [`PLOT_HEIGHT`] rows high with the range on its axis.
+    Full,
+}
+
+impl FromStr for ChartStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "spark" => Ok(ChartStyle::Spark),
+            "full" => Ok(ChartStyle::Full),
+            _ => Err(format!("unknown chart style '{}'", s)),
+        }
+    }
+}
+
+/// `series` drawn in `style` within `width` columns, followed by a line of statistics.
+/// `None` when the series is empty or `width` leaves less than [`MIN_CHART_WIDTH`]
+/// columns for the chart itself.
+pub fn render_chart(
+    series: &[(NaiveDate, f64)],
+    style: ChartStyle,
+    width: usize,
+) -> Option<String> {
+    let (first, last) = (series.first()?, series.last()?);
+    let values: Vec<f64> = series.iter().map(|(_, rate)| *rate).collect();
+    let chart = match style {
+        ChartStyle::Spark => {
+            // The dates take their own width plus a space on either side of the line.
+            let dates = first.0.to_string().len() + last.0.to_string().len() + 2;
+            let columns = width.checked_sub(dates).filter(|c| *c >= MIN_CHART_WIDTH)?;
+            format!(
+                "{} {} {}",
+                first.0,
+                sparkline(&resample(&values, columns)),
+                last.0
+            )
+        }
+        ChartStyle::Full => plot(first.0, last.0, &values, width)?,
+    };
+    Some(format!("{}\n{}", chart, summary(&values)))
+}
+
+/// `values` as a line of [`SPARK_LEVELS`], one bar each.
+pub fn sparkline(values: &[f64]) -> String {
+    let (min, max) = bounds(values);
+    values
+        .iter()
+        .map(|value| SPARK_LEVELS[level(*value, min, max, SPARK_LEVELS.len())])
+        .collect()
+}
+
+/// `min 4.2981  max 4.377  avg 4.3312  4.3395 → 4.3123 (-0.63%)`. The percentage is left
+/// out when the first value is zero.
+pub fn summary(values: &[f64]) -> String {
+    let (min, max) = bounds(values);
+    let avg = values.iter().sum::<f64>() / values.len() as f64;
+    let (first, last) = (values[0], values[values.len() - 1]);
+    let mut line = format!(
+        "min {}  max {}  avg {}  {} → {}",
+        format_rate(min, 0),
+        format_rate(max, 0),
+        format_rate(avg, 0),
+        format_rate(first, 0),
+        format_rate(last, 0)
+    );
+    if first != 0.0 {
+        line.push_str(&format!(" ({:+.2}%)", (last - first) / first * 100.0));
+    }
+    line
+}
+
+/// A plot of `values` with the maximum and minimum labelled on the axis and the first
+/// and last dates beneath, or `None` when `width` is too narrow for it.
+fn plot(first: NaiveDate, last: NaiveDate, values: &[f64], width: usize) -> Option<String> {
+    let (min, max) = bounds(values);
+    let (top, bottom) = (format_rate(max, 0), format_rate(min, 0));
+    let label_width = top.len().max(bottom.len());
+    // Each row is the label, a space and the axis before the points.
+    let columns = width
+        .checked_sub(label_width + 2)
+        .filter(|c| *c >= MIN_CHART_WIDTH)?;
+    let values = resample(values, columns);
+    let levels: Vec<usize> = values
+        .iter()
+        .map(|value| level(*value, min, max, PLOT_HEIGHT))
+        .collect();
+
+    let mut lines = Vec::with_capacity(PLOT_HEIGHT + 2);
+    for row in (0..PLOT_HEIGHT).rev() {
+        let label = if row == PLOT_HEIGHT - 1 {
+            top.as_str()
+        } else if row == 0 {
+            bottom.as_str()
+        } else {
+            ""
+        };
+        let points: String = levels
+            .iter()
+            .map(|level| if *level == row { '*' } else { ' ' })
+            .collect();
+        lines.push(format!("{:>label_width$} |{}", label, points.trim_end()));
+    }
+    lines.push(format!(
+        "{:>label_width$} +{}",
+        "",
+        "-".repeat(levels.len())
+    ));
+    let (first, last) = (first.to_string(), last.to_string());
+    let gap = levels.len().saturating_sub(first.len() + last.len());
+    let dates = if gap > 0 && first != last {
+        format!("{}{}{}", first, " ".repeat(gap), last)
+    } else {
+        first
+    };
+    lines.push(format!("{:>label_width$}  {}", "", dates));
+    Some(lines.join("\n"))
+}
+
+/// The smallest and largest of `values`.
+fn bounds(values: &[f64]) -> (f64, f64) {
+    values
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+            (min.min(*value), max.max(*value))
+        })
+}
+
+/// Which of `levels` steps between `min` and `max` `value` falls on. A constant series,
+/// where `min` and `max` are equal, sits on the middle step.
+fn level(value: f64, min: f64, max: f64, levels: usize) -> usize {
+    if max <= min {
+        return levels / 2;
+    }
+    let scaled = (value - min) / (max - min) * (levels - 1) as f64;
+    (scaled.round() as usize).min(levels - 1)
+}
+
+/// `values` squeezed into at most `columns` points, each the average of an even share of
+/// the originals. Series that already fit are returned as they are.
+fn resample(values: &[f64], columns: usize) -> Vec<f64> {
+    if values.len() <= columns {
+        return values.to_vec();
+    }
+    (0..columns)
+        .map(|column| {
+            let start = column * values.len() / columns;
+            let end = (column + 1) * values.len() / columns;
+            let bucket = &values[start..end];
+            bucket.iter().sum::<f64>() / bucket.len() as f64
+        })
+        .collect()
+}
@@ -0,0 +1,1009 @@
+use crate::codes::{CRYPTO_ASSETS, CURRENCY_CODES};
+use crate::config::{parse_duration, DEFAULT_SERVE_PORT};
+use crate::models::{parse_amount, ROUND_MODES};
+use crate::server::ENDPOINTS;
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::{Arg, ArgAction, Command};
+use clap_complete::Shell;
+use rust_decimal::Decimal;
+
+/// Words that may sit between the parts of a conversion, as in `100 usd to eur`.
+pub const FILLER_WORDS: [&str; 6] = ["to", "in", "as", "into", "->", "="];
+
+pub const CONVERSION_FORMS: &str = "expected a conversion such as `USD EUR 100`, \
+    `100 USD to EUR`, `USD 100 EUR`, or `EUR 100` with a default base currency";
+
+/// The positional arguments of a conversion, understood.
+#[derive(Debug, PartialEq)]
+pub struct ConversionArgs {
+    pub from: String,
+    /// One target, or several separated by commas.
+    pub to: String,
+    /// One amount, or several given as `10,50,100` or with `--amount`.
+    pub amounts: Vec<Decimal>,
+    /// `from` is the default base because only one currency was given.
+    pub from_default: bool,
+}
+
+/// Makes sense of the conversion arguments in any order: one amount (a number or an
+/// expression) and the source and target currencies, in that order, with optional filler
+/// words like `to` or `->` in between. With a single currency it is the target, converted
+/// from `default_base`. Codes are upper-cased but not validated. An argument is split at
+/// its spaces unless it is a whole amount, such as a quoted `"1 000,50"`. Several amounts,
+/// as in `10,50,100`, need a single target: there is no table of amounts by targets.
+pub fn parse_conversion_args(
+    args: &[&str],
+    default_base: Option<&str>,
+) -> Result<ConversionArgs, String> {
+    parse_conversion_args_with(args, default_base, &[])
+}
+
+/// Like [`parse_conversion_args`], but with the `given` amounts of `--amount` in place of
+/// the one in `args`, which then must not have one.
+pub fn parse_conversion_args_with(
+    args: &[&str],
+    default_base: Option<&str>,
+    given: &[Decimal],
+) -> Result<ConversionArgs, String> {
+    let words: Vec<&str> = args
+        .iter()
+        .flat_map(|arg| match parse_amount(arg) {
+            Ok(_) => vec![arg.trim()],
+            Err(_) => arg.split_whitespace().collect(),
+        })
+        .filter(|word| {
+            !FILLER_WORDS
+                .iter()
+                .any(|filler| word.eq_ignore_ascii_case(filler))
+        })
+        .collect();
+    let (codes, amounts): (Vec<&str>, Vec<&str>) = words.iter().partition(|word| is_code(word));
+    let amounts = match amounts.as_slice() {
+        [] if !given.is_empty() => given.to_vec(),
+        [amount] if !given.is_empty() => {
+            return Err(format!(
+                "an amount ({}) and --amount given; pass the amounts one way or the other",
+                amount
+            ))
+        }
+        [amount] => parse_amount_list(amount)?,
+        // The classic FROM TO AMOUNT order with a mistyped amount.
+        [] if words.len() == 3 => vec![parse_amount(words[2])?],
+        [] => return Err(format!("no amount given; {}", CONVERSION_FORMS)),
+        _ => {
+            return Err(format!(
+                "more than one amount ({}); {}",
+                amounts.join(", "),
+                CONVERSION_FORMS
+            ))
+        }
+    };
+    let (from, to, from_default) = match codes.as_slice() {
+        [from, to] => (from.to_string(), to.to_string(), false),
+        [to] => match default_base {
+            Some(base) => (base.to_string(), to.to_string(), true),
+            None => {
+                return Err(
+                    "no default source currency is configured for the two-argument form; \
+                     pass FROM_CURRENCY or set --base / base_currency in the config file"
+                        .to_string(),
+                )
+            }
+        },
+        [] => return Err(format!("no currency given; {}", CONVERSION_FORMS)),
+        _ => {
+            return Err(format!(
+                "too many currencies ({}); {}",
+                codes.join(", "),
+                CONVERSION_FORMS
+            ))
+        }
+    };
+    if amounts.len() > 1 && to.contains(',') {
+        return Err(
+            "several amounts need a single target currency; convert them to one currency at a time"
+                .to_string(),
+        );
+    }
+    Ok(ConversionArgs {
+        from: from.to_uppercase(),
+        to: to.to_uppercase(),
+        amounts,
+        from_default,
+    })
+}
+
+/// One amount, or several separated by commas as in `10,50,100`. A word that reads as a
+/// single amount is one, so `10,50` is ten and a half, not two amounts.
+fn parse_amount_list(word: &str) -> Result<Vec<Decimal>, String> {
+    match parse_amount(word) {
+        Ok(amount) => Ok(vec![amount]),
+        Err(e) if !word.contains(',') => Err(e),
+        Err(_) => word.split(',').map(parse_amount).collect(),
+    }
+}
+
+/// Currency codes are letters, with commas between several targets.
+fn is_code(word: &str) -> bool {
+    word.chars().any(|c| c.is_ascii_alphabetic())
+        && word.chars().all(|c| c.is_ascii_alphabetic() || c == ',')
+}
+
+/// The exit statuses listed at the end of `--help`, matching the `EXIT_*` constants.
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  success
+  1  failure, e.g. some lines of a batch could not be converted
+  2  invalid arguments, amount, date or config
+  3  unknown currency code
+  4  network or provider error
+  5  API key missing or refused, or request quota used up
+  6  --offline and the rate is not cached
+  7  --strict and only an expired cached rate was available";
+
+/// The command line of the `currency` binary.
+pub fn build_cli() -> Command {
+    Command::new("Currency Converter")
+        .version("1.0")
+        .author("Michal Zagalski")
+        .about("Converts currencies and lists exchange rates")
+        .after_help(EXIT_CODES_HELP)
+        .arg_required_else_help(true)
+        .arg(
+            Arg::new("CONVERSION")
+                .help("What to convert, in any order: FROM_CURRENCY TO_CURRENCY AMOUNT, e.g. USD EUR 100 or 100 usd to eur (several targets are separated by commas, and FROM_CURRENCY defaults to --base)")
+                .value_name("ARGS")
+                .num_args(1..)
+                .allow_negative_numbers(true),
+        )
+        .arg(
+            Arg::new("BASE")
+                .long("base")
+                .value_name("CURRENCY")
+                .help("Default source currency for `TO_CURRENCY AMOUNT` and default base for `list`, over DEFAULT_BASE_CURRENCY and the config file (PLN when none is set)")
+                .global(true),
+        )
+        .arg(
+            Arg::new("PRECISION")
+                .long("precision")
+                .help("Decimal places of the converted amount (rates show at least as many)")
+                .value_parser(clap::value_parser!(usize))
+                .global(true),
+        )
+        .arg(
+            Arg::new("ROUND_MODE")
+                .long("round-mode")
+                .visible_alias("rounding")
+                .value_name("MODE")
+                .help("How the converted amount is rounded to --precision decimals: half-up (nearest, the default), half-even, up (ceil) or down (floor)")
+                .value_parser(ROUND_MODES)
+                .hide_possible_values(true)
+                .global(true),
+        )
+        .arg(
+            Arg::new("FEE")
+                .long("fee")
+                .value_name("PERCENT")
+                .help("Takes a bank's or card's fee off the rate and shows both results, e.g. --fee 2.5")
+                .allow_negative_numbers(true)
+                .global(true),
+        )
+        .arg(
+            Arg::new("LOCALE")
+                .long("locale")
+                .value_name("LOCALE")
+                .help("Number style of amounts, e.g. en-US (1,234.56), de-DE (1.234,56) or pl-PL (1 234,56); defaults to LC_NUMERIC")
+                .global(true),
+        )
+        .arg(
+            Arg::new("WITH_NAMES")
+                .long("with-names")
+                .visible_alias("names")
+                .help("Shows currency symbols in conversions and names and symbols in `list`")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("RAW")
+                .long("raw")
+                .value_name("WHAT")
+                .help("Prints only the number, unrounded and unformatted: the converted amount (after any --fee), or the rate with --raw=rate")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("amount")
+                .value_parser(["amount", "rate"])
+                .conflicts_with_all(["JSON", "WATCH", "COMPARE_CACHED"]),
+        )
+        .arg(
+            Arg::new("EXACT")
+                .long("exact")
+                .help("Uses only rates quoted against the source currency, fetching them rather than deriving them from cached rates for another base")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("OFFLINE")
+                .global(true),
+        )
+        .arg(
+            Arg::new("STRICT")
+                .long("strict")
+                .help("Fails rather than fall back to an expired cached rate when the provider can't be reached")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("OFFLINE")
+                .global(true),
+        )
+        .arg(
+            Arg::new("NO_INVERSE")
+                .long("no-inverse")
+                .help("Leaves out the line with the rate the other way round, e.g. 1 PLN = 0.251256 USD")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("COMPARE_CACHED")
+                .long("compare-cached")
+                .help("Fetches a live rate and reports its change since the cached one")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["WATCH", "DATE", "OFFLINE"]),
+        )
+        .arg(
+            Arg::new("SHOW_AGE")
+                .long("show-age")
+                .help("Reports how old the rate is and whether it came from the cache")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("INVERT")
+                .long("invert")
+                .visible_alias("target-amount")
+                .help("Treats the amount as what you want to end up with in the target currency and works out how much of the source currency that takes")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("WATCH"),
+        )
+        .arg(
+            Arg::new("TO_BASE")
+                .long("to-base")
+                .help("With a single currency, as in `EUR 100`, converts from it into --base instead")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("AMOUNT")
+                .long("amount")
+                .help("An amount to convert instead of one in ARGS; repeat it to convert several")
+                .action(ArgAction::Append)
+                .allow_negative_numbers(true),
+        )
+        .arg(
+            Arg::new("WATCH")
+                .long("watch")
+                .value_name("SECONDS")
+                .help("Refetches the rate every SECONDS until Ctrl-C, showing the change since the previous fetch")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .conflicts_with_all(["JSON", "DATE", "OFFLINE"]),
+        )
+        .arg(
+            Arg::new("DATE")
+                .long("date")
+                .value_name("YYYY-MM-DD")
+                .help("Uses the historical rates of that day instead of the latest ones")
+                .global(true),
+        )
+        .arg(
+            Arg::new("OFFLINE")
+                .long("offline")
+                .help("Answers from the cache only, however old, without any network request")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("REFRESH")
+                .long("refresh")
+                .help("Ignores cached rates but still saves the freshly fetched ones")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("OFFLINE")
+                .global(true),
+        )
+        .arg(
+            Arg::new("NO_CACHE")
+                .long("no-cache")
+                .help("Neither reads nor writes the cache file")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("OFFLINE")
+                .global(true),
+        )
+        .arg(
+            Arg::new("CACHE_TTL")
+                .long("cache-ttl")
+                .value_name("DURATION")
+                .help("How long cached rates stay fresh, e.g. 30s, 15m, 6h, 1d (0 always fetches; overrides CACHE_TTL)")
+                .value_parser(parse_duration)
+                .conflicts_with("MAX_AGE")
+                .global(true),
+        )
+        .arg(
+            Arg::new("MAX_AGE")
+                .long("max-age")
+                .help("Seconds a cached rate stays fresh (0 always fetches; overrides CACHE_DURATION)")
+                .value_parser(clap::value_parser!(u64))
+                .global(true),
+        )
+        .arg(
+            Arg::new("PROXY")
+                .long("proxy")
+                .value_name("URL")
+                .help("Proxy for all requests: http://, https:// or socks5:// with optional user:pass@ (overrides HTTPS_PROXY/HTTP_PROXY)")
+                .global(true),
+        )
+        .arg(
+            Arg::new("API_KEY")
+                .long("api-key")
+                .value_name("KEY")
+                .help("ExchangeRate-API key, over the API_KEY env var and api_key in the config file")
+                .global(true),
+        )
+        .arg(
+            Arg::new("PROVIDER")
+                .long("provider")
+                .value_name("NAME")
+                .help("Rate provider to ask first: exchangerate-api (default), open-er-api or frankfurter; the others are tried if it fails")
+                .global(true),
+        )
+        .arg(
+            Arg::new("TIMEOUT")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("Gives up on a request after SECONDS, e.g. 5 or 30s (default 10; overrides REQUEST_TIMEOUT)")
+                .value_parser(parse_duration)
+                .global(true),
+        )
+        .arg(
+            Arg::new("RETRIES")
+                .long("retries")
+                .value_name("N")
+                .help("Retries a request up to N times after a connection error, timeout or 5xx response (default 3)")
+                .value_parser(clap::value_parser!(u32))
+                .global(true),
+        )
+        .arg(
+            Arg::new("CACHE_FILE")
+                .long("cache-file")
+                .value_name("PATH")
+                .help("Cache file to use instead of the platform cache directory (overrides CURRENCY_CLI_CACHE_FILE)")
+                .global(true),
+        )
+        .arg(
+            Arg::new("CONFIG")
+                .long("config")
+                .value_name("PATH")
+                .help("Config file to read instead of the platform one (~/.config/currency_cli/config.toml on Linux)")
+                .global(true),
+        )
+        .arg(
+            Arg::new("JSON")
+                .long("json")
+                .help("Prints results as JSON on stdout and errors as JSON on stderr")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("OUTPUT")
+                .long("output")
+                .value_name("FORMAT")
+                .help("Prints results as plain text, json (like --json) or csv, for conversions, list, timeseries and batch")
+                .value_parser(["plain", "json", "csv"])
+                .conflicts_with("JSON")
+                .global(true),
+        )
+        .arg(
+            Arg::new("NO_HEADER")
+                .long("no-header")
+                .help("Leaves out the header row of --output csv, for appending to an existing file")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("VERBOSE")
+                .short('v')
+                .long("verbose")
+                .help("Logs cache and network activity to stderr (-vv for more detail)")
+                .action(ArgAction::Count)
+                .global(true),
+        )
+        .arg(
+            Arg::new("COLOR")
+                .long("color")
+                .value_name("WHEN")
+                .help("Colors output: auto (on a terminal, unless NO_COLOR is set), always or never")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto")
+                .global(true),
+        )
+        .arg(
+            Arg::new("QUIET")
+                .short('q')
+                .long("quiet")
+                .help("Logs nothing but errors, dropping warnings such as stale-rate notices")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("VERBOSE")
+                .global(true),
+        )
+        .arg(
+            Arg::new("ALLOW_ZERO")
+                .long("allow-zero")
+                .help("Converts an amount of 0 instead of rejecting it")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("NO_HISTORY")
+                .long("no-history")
+                .help("Records nothing in the conversion history, whatever the config file says")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("NO_VALIDATE")
+                .long("allow-unknown")
+                .visible_alias("no-validate")
+                .help("Allows currency codes outside the bundled ISO 4217 list, such as XAU or BTC")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Lists exchange rates for one or more base currencies")
+                .arg(
+                    Arg::new("BASE_CURRENCY")
+                        .help("One or more base currency codes (defaults to the configured base, or PLN)")
+                        .num_args(1..),
+                )
+                .arg(
+                    Arg::new("SORT")
+                        .long("sort")
+                        .help("Orders by currency code (the default), by rate or by the size of the --change")
+                        .value_parser([
+                            PossibleValue::new("code"),
+                            PossibleValue::new("rate"),
+                            PossibleValue::new("change"),
+                            // The name `rate` had before `--order` existed.
+                            PossibleValue::new("value").hide(true),
+                        ]),
+                )
+                .arg(
+                    Arg::new("CHANGE")
+                        .long("change")
+                        .value_name("PERIOD")
+                        .help("Adds how each rate moved over the last day or week")
+                        .value_parser(["24h", "7d"]),
+                )
+                .arg(
+                    Arg::new("ORDER")
+                        .long("order")
+                        .help("Sort direction (default: asc for codes, desc for rates and changes)")
+                        .value_parser(["asc", "desc"]),
+                )
+                .arg(
+                    Arg::new("ONLY")
+                        .long("only")
+                        .help("Comma-separated currency codes to show, in that order, e.g. USD,EUR,GBP")
+                        .value_delimiter(','),
+                )
+                .arg(
+                    Arg::new("EXCLUDE")
+                        .long("exclude")
+                        .help("Comma-separated currency codes to leave out")
+                        .value_delimiter(','),
+                )
+                .arg(
+                    Arg::new("ALL")
+                        .long("all")
+                        .help("Shows every currency, not just the favorites from the config file")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("ONLY"),
+                )
+                .arg(
+                    Arg::new("PAIRS")
+                        .long("pairs")
+                        .help("Shows only the favorites from the config file, failing if there are none")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["ONLY", "ALL"]),
+                )
+                .arg(
+                    Arg::new("LIMIT")
+                        .long("limit")
+                        .value_name("N")
+                        .help("Shows at most N currencies per base; 0 shows all")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("TOP")
+                        .long("top")
+                        .value_name("N")
+                        .help("Shows the N strongest currencies against the base, or the weakest with --order desc")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .conflicts_with_all(["SORT", "LIMIT"]),
+                )
+                .arg(
+                    Arg::new("BY")
+                        .long("by")
+                        .help("What --top ranks by")
+                        .value_parser(["rate", "code"])
+                        .default_value("rate")
+                        .requires("TOP"),
+                )
+                .arg(
+                    Arg::new("COLUMNS")
+                        .long("columns")
+                        .help("Comma-separated columns of the table shown on a terminal (default: code,name,rate,inverse)")
+                        .value_parser(["code", "name", "rate", "inverse"])
+                        .value_delimiter(','),
+                ),
+        )
+        .subcommand(
+            Command::new("currencies")
+                .about("Lists the supported currency codes and their names")
+                .arg(
+                    Arg::new("FILTER")
+                        .help("Shows only codes or names containing this text, e.g. franc"),
+                ),
+        )
+        .subcommand(
+            Command::new("matrix")
+                .about("Prints the cross rates between several currencies as a grid")
+                .arg(
+                    Arg::new("CURRENCIES")
+                        .help("The currency codes, e.g. EUR USD GBP PLN; each row is one unit of its currency in the column currencies")
+                        .num_args(2..)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("quote")
+                .about("Compares the rate of a currency pair across every provider")
+                .arg(
+                    Arg::new("FROM_CURRENCY")
+                        .help("The source currency code")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("TO_CURRENCY")
+                        .help("The target currency code")
+                        .required(true),
+                )
+                .arg(Arg::new("AMOUNT").help("An amount to convert at each provider's rate"))
+                .arg(
+                    Arg::new("PROVIDER_TIMEOUT")
+                        .long("provider-timeout")
+                        .value_name("SECONDS")
+                        .help("Gives up on a provider after SECONDS, retries included, e.g. 5 or 30s (default 15)")
+                        .value_parser(parse_duration),
+                ),
+        )
+        .subcommand(
+            Command::new("timeseries")
+                .about("Prints the rate of a currency pair for each day of a date range")
+                .arg(
+                    Arg::new("FROM_CURRENCY")
+                        .help("The source currency code")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("TO_CURRENCY")
+                        .help("The target currency code")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("START_DATE")
+                        .long("from")
+                        .value_name("YYYY-MM-DD")
+                        .help("First day of the range")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("END_DATE")
+                        .long("to")
+                        .value_name("YYYY-MM-DD")
+                        .help("Last day of the range (defaults to today)"),
+                )
+                .arg(
+                    Arg::new("INTERVAL")
+                        .long("interval")
+                        .help("Spacing between the listed days")
+                        .value_parser(["daily", "weekly", "monthly"])
+                        .default_value("daily"),
+                )
+                .arg(
+                    Arg::new("CHART")
+                        .long("chart")
+                        .value_name("STYLE")
+                        .help("Draws the rates as a sparkline, or as a plot with `full`, when printing to a terminal")
+                        .value_parser(["spark", "full"])
+                        .num_args(0..=1)
+                        .default_missing_value("spark"),
+                ),
+        )
+        .subcommand(
+            Command::new("change")
+                .about("Shows how the rate of a currency pair moved between two days")
+                .arg(
+                    Arg::new("FROM_CURRENCY")
+                        .help("The source currency code")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("TO_CURRENCY")
+                        .help("The target currency code")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("START_DATE")
+                        .long("from")
+                        .value_name("YYYY-MM-DD")
+                        .help("Day to measure from")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("END_DATE")
+                        .long("to")
+                        .value_name("YYYY-MM-DD")
+                        .help("Day to measure to (defaults to today)"),
+                ),
+        )
+        .subcommand(
+            Command::new("alert")
+                .about("Polls a currency pair until its rate crosses a threshold, then exits with status 0")
+                .arg(
+                    Arg::new("FROM_CURRENCY")
+                        .help("The source currency code")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("TO_CURRENCY")
+                        .help("The target currency code")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("BELOW")
+                        .long("below")
+                        .value_name("RATE")
+                        .help("Fires once the rate drops below RATE")
+                        .value_parser(clap::value_parser!(f64)),
+                )
+                .arg(
+                    Arg::new("ABOVE")
+                        .long("above")
+                        .value_name("RATE")
+                        .help("Fires once the rate rises above RATE")
+                        .value_parser(clap::value_parser!(f64)),
+                )
+                .arg(
+                    Arg::new("INTERVAL")
+                        .long("interval")
+                        .value_name("SECONDS")
+                        .help("Seconds between checks; each one fetches a live rate")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .default_value("300"),
+                )
+                .arg(
+                    Arg::new("EXEC")
+                        .long("exec")
+                        .value_name("CMD")
+                        .help("Shell command run when the alert fires, with {rate} replaced by the rate"),
+                ),
+        )
+        .subcommand(
+            Command::new("batch")
+                .about("Converts every FROM,TO,AMOUNT or AMOUNT FROM TO line of a file or stdin")
+                .arg(
+                    Arg::new("FILE")
+                        .help("Path to the file of conversions (reads stdin when omitted)")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("FILE_PATH")
+                        .long("file")
+                        .value_name("PATH")
+                        .help("Path to the file of conversions, like FILE")
+                        .conflicts_with("FILE"),
+                )
+                .arg(
+                    Arg::new("COLUMNS")
+                        .long("columns")
+                        .value_name("LIST")
+                        .help("What each CSV field holds, e.g. date,amount,from,to; other names are ignored")
+                        .default_value("from,to,amount"),
+                )
+                .arg(
+                    Arg::new("HEADER")
+                        .long("header")
+                        .help("Skips the first line, a CSV header")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("STRICT")
+                        .long("strict")
+                        .help("Stops at the first line that fails instead of skipping it")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("serve")
+                .about("Serves conversions and rates as JSON over HTTP on localhost, sharing this tool's cache and API key")
+                .after_help(format!("Endpoints: {}", ENDPOINTS))
+                .arg(
+                    Arg::new("PORT")
+                        .long("port")
+                        .value_name("PORT")
+                        .help(format!("Port to listen on (default {})", DEFAULT_SERVE_PORT))
+                        .value_parser(clap::value_parser!(u16)),
+                ),
+        )
+        .subcommand(
+            Command::new("interactive")
+                .about("Reads conversions such as `100 USD EUR` line by line until quit; type help for more"),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Prints a completion script for SHELL, e.g. `currency completions bash > ~/.local/share/bash-completion/completions/currency`")
+                .arg(
+                    Arg::new("SHELL")
+                        .help("The shell to complete for")
+                        .required(true)
+                        .value_parser(clap::value_parser!(Shell)),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Manages the config file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("init")
+                        .about("Writes a commented template to --config or the default config path")
+                        .arg(
+                            Arg::new("FORCE")
+                                .long("force")
+                                .help("Overwrites an existing config file")
+                                .action(ArgAction::SetTrue),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("rate")
+                .about("Pins, lists or removes manual rates that conversions use instead of market rates")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("set")
+                        .about("Pins a pair at a fixed rate; its inverse uses 1/RATE unless pinned itself")
+                        .arg(
+                            Arg::new("FROM_CURRENCY")
+                                .help("The source currency code")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("TO_CURRENCY")
+                                .help("The target currency code")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("RATE")
+                                .help("Units of the target currency per unit of the source")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("EXPIRES")
+                                .long("expires")
+                                .value_name("YYYY-MM-DD")
+                                .help("Last day the rate applies (defaults to until it is unset)"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("list").about("Lists the pinned rates and when they expire"),
+                )
+                .subcommand(
+                    Command::new("unset")
+                        .about("Removes the pinned rate of a pair")
+                        .arg(
+                            Arg::new("FROM_CURRENCY")
+                                .help("The source currency code")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("TO_CURRENCY")
+                                .help("The target currency code")
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("alias")
+                .about("Lists, sets or removes the currency aliases of the config file")
+                .subcommand_required(true)
+                .subcommand(Command::new("list").about("Lists the aliases and the codes they stand for"))
+                .subcommand(
+                    Command::new("set")
+                        .about("Makes NAME stand for a currency code, or for another alias")
+                        .arg(
+                            Arg::new("NAME")
+                                .help("The alias, letters only, e.g. home")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("CODE")
+                                .help("The currency code or alias it stands for, e.g. PLN")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Removes an alias")
+                        .arg(Arg::new("NAME").help("The alias to remove").required(true)),
+                ),
+        )
+        .subcommand(
+            Command::new("fav")
+                .about("Converts with a pair saved under [pairs] in the config file")
+                .arg(
+                    Arg::new("NAME")
+                        .help("The saved pair, e.g. trip for trip = \"EUR/PLN\"")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("AMOUNT")
+                        .help("The amount to convert, or several separated by commas")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("Shows past conversions with the rates they used, oldest first")
+                .arg(
+                    Arg::new("LAST")
+                        .long("last")
+                        .value_name("N")
+                        .help("Shows only the N most recent conversions")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("PAIR")
+                        .long("pair")
+                        .value_name("FROM/TO")
+                        .help("Shows only conversions of one pair, in that direction, e.g. USD/PLN"),
+                )
+                .arg(
+                    Arg::new("SINCE")
+                        .long("since")
+                        .value_name("YYYY-MM-DD")
+                        .help("Shows only conversions on or after the day"),
+                )
+                .subcommand(Command::new("clear").about("Deletes the conversion history")),
+        )
+        .subcommand(
+            Command::new("cache")
+                .about("Inspects, clears, exports or imports the local exchange rate cache")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("info")
+                        .about("Shows the cache file's path, size and the age of its entries"),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Shows the cached rates of every base or a single one, with their age")
+                        .arg(
+                            Arg::new("BASE_CURRENCY")
+                                .help("The base currency code to show")
+                                .required(false),
+                        ),
+                )
+                .subcommand(
+                    Command::new("clear")
+                        .about("Deletes the cache file, or only the entries of one base currency")
+                        .arg(
+                            Arg::new("BASE_CURRENCY")
+                                .help("The base currency code to remove")
+                                .required(false),
+                        ),
+                )
+                .subcommand(
+                    Command::new("prune")
+                        .about("Drops long-expired rates and the oldest entries beyond the size caps, as every save does"),
+                )
+                .subcommand(
+                    Command::new("export")
+                        .about("Writes the cached rates as JSON, to carry them to another machine")
+                        .arg(
+                            Arg::new("PATH")
+                                .help("File to write; stdout when left out")
+                                .required(false),
+                        )
+                        .arg(
+                            Arg::new("ONLY")
+                                .long("only")
+                                .help("Comma-separated base currency codes to export, e.g. USD,EUR")
+                                .value_delimiter(','),
+                        ),
+                )
+                .subcommand(
+                    Command::new("import")
+                        .about("Reads rates written by `cache export` into the cache")
+                        .arg(
+                            Arg::new("PATH")
+                                .help("File written by `cache export`, or - for stdin")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("MERGE")
+                                .long("merge")
+                                .help("Keeps the newer entry where both have one (the default)")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(
+                            Arg::new("REPLACE")
+                                .long("replace")
+                                .help("Drops the current cache and keeps only the imported rates")
+                                .action(ArgAction::SetTrue)
+                                .conflicts_with("MERGE"),
+                        ),
+                ),
+        )
+}
+
+/// The completion script for `shell`. Beyond subcommands and flags it offers every known
+/// currency code and crypto ticker wherever a currency goes, so `currency US<TAB>` offers USD.
+pub fn completion_script(shell: Shell) -> String {
+    let mut cli = with_currency_values(build_cli());
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut cli, env!("CARGO_PKG_NAME"), &mut script);
+    String::from_utf8(script).expect("completion scripts are UTF-8")
+}
+
+/// `cli` with the currency codes as the possible values of its currency arguments. Only
+/// the completion scripts see these; the real parser still takes amounts and codes it
+/// doesn't know.
+fn with_currency_values(cli: Command) -> Command {
+    let codes = || {
+        let tickers = CRYPTO_ASSETS.iter().map(|(ticker, _)| ticker);
+        PossibleValuesParser::new(
+            CURRENCY_CODES
+                .iter()
+                .chain(tickers)
+                .map(|code| PossibleValue::new(*code)),
+        )
+    };
+    cli.mut_arg("CONVERSION", |arg| arg.value_parser(codes()))
+        .mut_arg("BASE", |arg| arg.value_parser(codes()))
+        .mut_subcommand("list", |list| {
+            list.mut_arg("BASE_CURRENCY", |arg| arg.value_parser(codes()))
+        })
+        .mut_subcommand("timeseries", |timeseries| {
+            timeseries
+                .mut_arg("FROM_CURRENCY", |arg| arg.value_parser(codes()))
+                .mut_arg("TO_CURRENCY", |arg| arg.value_parser(codes()))
+        })
+        .mut_subcommand("change", |change| {
+            change
+                .mut_arg("FROM_CURRENCY", |arg| arg.value_parser(codes()))
+                .mut_arg("TO_CURRENCY", |arg| arg.value_parser(codes()))
+        })
+        .mut_subcommand("alert", |alert| {
+            alert
+                .mut_arg("FROM_CURRENCY", |arg| arg.value_parser(codes()))
+                .mut_arg("TO_CURRENCY", |arg| arg.value_parser(codes()))
+        })
+        .mut_subcommand("rate", |rate| {
+            rate.mut_subcommand("set", |set| {
+                set.mut_arg("FROM_CURRENCY", |arg| arg.value_parser(codes()))
+                    .mut_arg("TO_CURRENCY", |arg| arg.value_parser(codes()))
+            })
+            .mut_subcommand("unset", |unset| {
+                unset
+                    .mut_arg("FROM_CURRENCY", |arg| arg.value_parser(codes()))
+                    .mut_arg("TO_CURRENCY", |arg| arg.value_parser(codes()))
+            })
+        })
+        .mut_subcommand("cache", |cache| {
+            cache
+                .mut_subcommand("show", |show| {
+                    show.mut_arg("BASE_CURRENCY", |arg| arg.value_parser(codes()))
+                })
+                .mut_subcommand("clear", |clear| {
+                    clear.mut_arg("BASE_CURRENCY", |arg| arg.value_parser(codes()))
+                })
+        })
+}
@@ -0,0 +1,135 @@
+//! ISO 4217 currency codes and the supported crypto assets: validation before any request
+//! is made, and suggestions for codes that look like typos.
+
+use crate::error::CurrencyError;
+
+/// Active ISO 4217 currency codes accepted without `--allow-unknown`.
+pub const CURRENCY_CODES: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT",
+    "BGN", "BHD", "BIF", "BMD", "BND", "BOB", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD",
+    "CDF", "CHF", "CLP", "CNY", "COP", "CRC", "CUP", "CVE", "CZK", "DJF", "DKK", "DOP", "DZD",
+    "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ",
+    "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS", "INR", "IQD", "IRR", "ISK", "JMD", "JOD",
+    "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW", "KWD", "KYD", "KZT", "LAK", "LBP", "LKR",
+    "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD", "MMK", "MNT", "MOP", "MRU", "MUR", "MVR",
+    "MWK", "MXN", "MYR", "MZN", "NAD", "NGN", "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN",
+    "PGK", "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD", "RUB", "RWF", "SAR", "SBD", "SCR",
+    "SDG", "SEK", "SGD", "SHP", "SLE", "SLL", "SOS", "SRD", "SSP", "STN", "SYP", "SZL", "THB",
+    "TJS", "TMT", "TND", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "UYU", "UZS",
+    "VES", "VND", "VUV", "WST", "XAF", "XCD", "XOF", "XPF", "YER", "ZAR", "ZMW", "ZWL",
+];
+
+/// Crypto assets priced by the crypto provider, by ticker, with the id the provider knows
+/// each one by.
+pub const CRYPTO_ASSETS: &[(&str, &str)] = &[
+    ("ADA", "cardano"),
+    ("BCH", "bitcoin-cash"),
+    ("BNB", "binancecoin"),
+    ("BTC", "bitcoin"),
+    ("DOGE", "dogecoin"),
+    ("DOT", "polkadot"),
+    ("ETH", "ethereum"),
+    ("LINK", "chainlink"),
+    ("LTC", "litecoin"),
+    ("SOL", "solana"),
+    ("TRX", "tron"),
+    ("USDC", "usd-coin"),
+    ("USDT", "tether"),
+    ("XLM", "stellar"),
+    ("XMR", "monero"),
+    ("XRP", "ripple"),
+];
+
+/// Codes ranked first among equally close suggestions.
+const COMMON_CODES: &[&str] = &[
+    "AUD", "BRL", "CAD", "CHF", "CNY", "CZK", "DKK", "EUR", "GBP", "HKD", "HUF", "INR", "JPY",
+    "KRW", "MXN", "NOK", "NZD", "PLN", "SEK", "SGD", "TRY", "UAH", "USD", "ZAR",
+];
+
+/// Suggestions are at most this many edits away from what was typed.
+const MAX_SUGGESTION_DISTANCE: usize = 1;
+
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Accepts `code`, in any case, if it is in [`CURRENCY_CODES`] or a crypto ticker.
+pub fn validate_currency_code(code: &str) -> Result<(), CurrencyError> {
+    if CURRENCY_CODES.contains(&code.to_uppercase().as_str()) || is_crypto(code) {
+        Ok(())
+    } else {
+        Err(CurrencyError::UnknownCurrency(code.to_string()))
+    }
+}
+
+/// The crypto provider's id for the ticker `code`, in any case.
+pub fn crypto_id(code: &str) -> Option<&'static str> {
+    let code = code.to_uppercase();
+    CRYPTO_ASSETS
+        .iter()
+        .find(|(ticker, _)| *ticker == code)
+        .map(|(_, id)| *id)
+}
+
+/// Whether `code` is the ticker of a supported crypto asset rather than a currency.
+pub fn is_crypto(code: &str) -> bool {
+    crypto_id(code).is_some()
+}
+
+/// Known codes one typo away from `code` (a wrong, missing or extra letter, or two letters
+/// swapped), commonly used currencies first.
+pub fn suggestions(code: &str) -> Vec<&'static str> {
+    let code = code.to_uppercase();
+    let mut close: Vec<(usize, bool, &str)> = CURRENCY_CODES
+        .iter()
+        .chain(CRYPTO_ASSETS.iter().map(|(ticker, _)| ticker))
+        .map(|known| {
+            let common = COMMON_CODES.contains(known);
+            (edit_distance(&code, known), !common, *known)
+        })
+        .filter(|(distance, _, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .collect();
+    close.sort();
+    close
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, _, known)| known)
+        .collect()
+}
+
+/// `EEUR is not a known currency code, did you mean EUR?`
+pub fn describe_unknown(code: &str) -> String {
+    match suggestions(code).as_slice() {
+        [] => format!("{} is not a known currency code", code),
+        close => format!(
+            "{} is not a known currency code, did you mean {}?",
+            code,
+            close.join(" or ")
+        ),
+    }
+}
+
+/// Edits (insertions, deletions, substitutions and swaps of neighbouring letters) needed
+/// to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // distances[i][j] is the distance between the first i letters of a and the first j of b.
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in distances[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
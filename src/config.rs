@@ -1,4 +1,445 @@
-use std::time::Duration;
+use crate::aliases::{parse_pair, Aliases};
+use crate::cache::PrunePolicy;
+use crate::models::{parse_fee, RoundMode};
+use crate::provider::provider_chain;
+use directories::ProjectDirs;
+use log::warn;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 pub static CACHE_DURATION: Duration = Duration::new(3600, 0); // 1 hour
-pub const CACHE_FILE: &str = "cache.json";
+pub const CACHE_FILE: &str = "rates_cache.json";
+/// The conversion history, in the platform data directory.
+pub const HISTORY_FILE: &str = "history.jsonl";
+/// Rates pinned with `rate set`, beside the cache file.
+pub const OVERRIDES_FILE: &str = "rate_overrides.json";
+/// Where older versions kept the cache, relative to the working directory.
+pub const LEGACY_CACHE_FILE: &str = "cache.json";
+pub const DEFAULT_BASE_CURRENCY: &str = "PLN";
+pub const DEFAULT_PRECISION: usize = 2;
+pub const DEFAULT_PROVIDER: &str = "exchangerate-api";
+/// Where each provider is reached; `API_BASE_URL` overrides the first.
+pub const EXCHANGERATE_API_URL: &str = "https://api.exchangerate-api.com/v4";
+/// ExchangeRate-API's keyed v6 API, which has the history and code list; `API_BASE_URL`
+/// overrides it too.
+pub const EXCHANGERATE_API_V6_URL: &str = "https://v6.exchangerate-api.com/v6";
+pub const OPEN_ER_API_URL: &str = "https://open.er-api.com/v6";
+pub const FRANKFURTER_URL: &str = "https://api.frankfurter.app";
+/// CoinGecko, which prices crypto assets; no key needed.
+pub const COINGECKO_URL: &str = "https://api.coingecko.com/api/v3";
+/// Crypto prices move by the minute, so their cache entries expire much sooner than fiat ones.
+pub const CRYPTO_CACHE_DURATION: Duration = Duration::from_secs(60);
+/// A provider's list of supported codes rarely changes, so it is cached for a week.
+pub const CODE_LIST_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// How often `serve` writes newly fetched rates to the cache file.
+pub const CACHE_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+/// Port `serve` listens on unless `--port` says otherwise.
+pub const DEFAULT_SERVE_PORT: u16 = 8989;
+/// Decimal places amounts in a crypto asset are shown with at least, down to one satoshi.
+pub const CRYPTO_PRECISION: usize = 8;
+/// Sent with every request so providers can tell who is calling.
+pub const USER_AGENT: &str = concat!("currency_cli/", env!("CARGO_PKG_VERSION"));
+/// Upper bound on requests in flight at once, e.g. for `timeseries`.
+pub const MAX_CONCURRENT_REQUESTS: usize = 8;
+/// Most currencies `matrix` takes, each costing a request for its rates.
+pub const MAX_MATRIX_CURRENCIES: usize = 10;
+/// Earliest day the provider has historical rates for.
+pub const EARLIEST_HISTORICAL_DATE: &str = "1990-01-01";
+/// Retries after a connection error, timeout or 5xx response, unless `--retries` says otherwise.
+pub const DEFAULT_RETRIES: u32 = 3;
+/// Wait before the first retry; each further one waits twice as long, plus jitter.
+pub const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+/// Limit on connecting and on each whole request, unless `--timeout` or `REQUEST_TIMEOUT`
+/// say otherwise.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest `quote` waits for one provider, retries included, unless `--provider-timeout`
+/// says otherwise.
+pub const DEFAULT_QUOTE_TIMEOUT: Duration = Duration::from_secs(15);
+/// Longest `Retry-After` delay that is waited out automatically; longer ones are reported.
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(10);
+/// How long a run waits for another one to release the cache lock before giving up.
+pub const CACHE_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Latest-rate entries the cache keeps, the least recently written going first beyond it.
+pub const DEFAULT_CACHE_MAX_ENTRIES: usize = 200;
+/// Entries of rates on a past date the cache keeps, the least recently written going first.
+pub const DEFAULT_MAX_HISTORICAL_ENTRIES: usize = 5000;
+/// Latest rates older than this many cache TTLs are dropped on save: 30 days at the
+/// default hour.
+pub const DEFAULT_CACHE_PRUNE_TTLS: u32 = 720;
+/// How far ahead of this machine's clock an imported cache entry may be, for clocks that
+/// are slightly out of step.
+pub const CACHE_IMPORT_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// User preferences read from `config.toml`. Every key is optional; see [`Settings`] for
+/// how they rank against flags and env vars. Unknown keys are kept in `unknown` and
+/// only warned about, so a config written for a newer version still loads.
+#[derive(Deserialize, Default, Debug, PartialEq)]
+pub struct Config {
+    pub api_key: Option<String>,
+    pub provider: Option<String>,
+    #[serde(alias = "base_currency")]
+    pub default_base: Option<String>,
+    /// Cache freshness window, a duration such as `15m`.
+    pub cache_ttl: Option<String>,
+    /// Cache freshness window in seconds, the older form of `cache_ttl`.
+    pub max_age: Option<u64>,
+    /// Latest-rate entries the cache keeps; 0 for no limit.
+    pub cache_max_entries: Option<usize>,
+    /// Entries of rates on a past date the cache keeps; 0 for no limit.
+    pub max_historical_entries: Option<usize>,
+    /// How many `cache_ttl` periods latest rates are kept for; 0 keeps them.
+    pub cache_prune_ttls: Option<u32>,
+    pub precision: Option<usize>,
+    /// How converted amounts are rounded, like `--round-mode`.
+    #[serde(alias = "rounding")]
+    pub round_mode: Option<String>,
+    /// Percentage taken off every rate, like `--fee`.
+    #[serde(alias = "default_fee")]
+    pub fee: Option<f64>,
+    /// Codes `list` shows unless given `--only` or `--all`.
+    pub favorites: Option<Vec<String>>,
+    /// Whether conversions are recorded for `history`; on unless set to `false`.
+    pub history: Option<bool>,
+    /// The `[aliases]` table: names such as `home` for currency codes.
+    pub aliases: Option<BTreeMap<String, String>>,
+    /// The `[pairs]` table: names for conversions `fav` runs, such as `trip = "EUR/PLN"`.
+    pub pairs: Option<BTreeMap<String, String>>,
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, toml::Value>,
+}
+
+/// Written by `config init`: every key, commented out, with what it does.
+pub const CONFIG_TEMPLATE: &str = r##"# currency_cli configuration. Every key is optional; remove the leading "# " to set one.
+# Command-line flags take precedence over these, and so do environment variables where
+# one exists (API_KEY, DEFAULT_BASE_CURRENCY, CACHE_TTL).
+
+# ExchangeRate-API key.
+# api_key = "your-key"
+
+# Provider asked first: exchangerate-api, open-er-api or frankfurter.
+# provider = "exchangerate-api"
+
+# Base currency of `list` and of conversions given only a target and an amount.
+# default_base = "PLN"
+
+# How long cached rates stay fresh, e.g. 30s, 15m, 6h or 1d.
+# cache_ttl = "1h"
+
+# Latest rates kept in the cache, one per base and provider, and rates on past dates kept;
+# the least recently written go first. 0 is no limit.
+# cache_max_entries = 200
+# max_historical_entries = 5000
+
+# Latest rates are dropped once this many cache_ttl periods old; 0 keeps them.
+# cache_prune_ttls = 720
+
+# Decimal places of converted amounts.
+# precision = 2
+
+# How converted amounts are rounded: half-up, half-even, up or down.
+# round_mode = "half-up"
+
+# Percentage taken off every rate, as by a bank or card.
+# fee = 1.5
+
+# Currencies `list` shows unless given --only or --all.
+# favorites = ["USD", "EUR", "GBP"]
+
+# Record each conversion for `currency history`; set to false to keep no record.
+# history = true
+
+# Names usable anywhere a currency code is, in any case; `currency alias set` edits them.
+# [aliases]
+# home = "PLN"
+# us = "USD"
+
+# Pairs converted with `currency fav NAME AMOUNT`; either side may be an alias.
+# [pairs]
+# trip = "EUR/home"
+"##;
+
+impl Config {
+    /// Loads `path`, or [`default_config_path`] when no path is given. A missing default
+    /// file yields an empty config; a missing explicit path is an error.
+    pub fn load(path: Option<&str>) -> Result<Config, String> {
+        let (path, explicit) = match path {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => {
+                return Ok(Config::default())
+            }
+            Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+        };
+        let invalid = |e: String| format!("invalid config {}: {}", path.display(), e);
+        let config = Config::parse(&contents).map_err(|e| invalid(e.to_string()))?;
+        for key in config.unknown.keys() {
+            warn!("{}: ignoring unknown key '{}'", path.display(), key);
+        }
+        if let Some(provider) = &config.provider {
+            provider_chain(provider).map_err(invalid)?;
+        }
+        if let Some(ttl) = &config.cache_ttl {
+            parse_duration(ttl).map_err(|e| invalid(format!("cache_ttl: {}", e)))?;
+        }
+        if let Some(fee) = config.fee {
+            parse_fee(&fee.to_string()).map_err(|e| invalid(format!("fee: {}", e)))?;
+        }
+        Ok(config)
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(contents)
+    }
+}
+
+/// The platform's config location, e.g. `~/.config/currency_cli/config.toml` on Linux.
+pub fn default_config_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "currency_cli").map(|dirs| dirs.config_dir().join("config.toml"))
+}
+
+/// Writes [`CONFIG_TEMPLATE`] to `path`, creating its directory. An existing file is only
+/// replaced when `force` is set.
+pub fn write_config_template(path: &Path, force: bool) -> Result<(), String> {
+    if path.exists() && !force {
+        return Err(format!(
+            "{} already exists (pass --force to overwrite it)",
+            path.display()
+        ));
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+    }
+    fs::write(path, CONFIG_TEMPLATE).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+}
+
+/// Options given on the command line, the first place [`Settings::resolve`] looks.
+#[derive(Debug, Default)]
+pub struct Flags {
+    pub api_key: Option<String>,
+    pub provider: Option<String>,
+    pub base: Option<String>,
+    /// `--cache-ttl`, or `--max-age` in seconds.
+    pub cache_ttl: Option<Duration>,
+    pub precision: Option<usize>,
+    pub round_mode: Option<RoundMode>,
+    pub fee: Option<String>,
+    pub timeout: Option<Duration>,
+    /// `--no-history`.
+    pub no_history: bool,
+}
+
+/// The preferences of one run. Each is taken from the first of its command-line flag, env
+/// var, config key and built-in default that is set; not every preference has all four.
+#[derive(Debug, PartialEq)]
+pub struct Settings {
+    /// `--api-key`, then `API_KEY`, then `api_key`.
+    pub api_key: Option<String>,
+    /// `--provider`, then `provider`, then [`DEFAULT_PROVIDER`].
+    pub provider: String,
+    /// `--base`, then `DEFAULT_BASE_CURRENCY`, then `default_base`, then
+    /// [`DEFAULT_BASE_CURRENCY`]. Upper-cased but not validated.
+    pub default_base: String,
+    /// Where `default_base` came from, for error messages.
+    pub default_base_source: &'static str,
+    /// `--cache-ttl` or `--max-age`, then `CACHE_TTL` or `CACHE_DURATION` (seconds), then
+    /// `cache_ttl` or `max_age`, then [`CACHE_DURATION`].
+    pub cache_ttl: Duration,
+    /// What saving the cache drops: latest rates older than `cache_prune_ttls` (or
+    /// [`DEFAULT_CACHE_PRUNE_TTLS`]) times the `cache_ttl` of the environment or config
+    /// file, whatever the flags say, and the oldest entries beyond `cache_max_entries` and
+    /// `max_historical_entries` (or their defaults).
+    pub prune: PrunePolicy,
+    /// `--precision`, then `precision`, then [`DEFAULT_PRECISION`].
+    pub precision: usize,
+    /// `--round-mode`, then `round_mode`, then half-up.
+    pub round_mode: RoundMode,
+    /// `--fee`, then `fee`, then none.
+    pub fee: Decimal,
+    /// `favorites`, upper-cased.
+    pub favorites: Vec<String>,
+    /// `--timeout`, then `REQUEST_TIMEOUT` (seconds or a duration), then
+    /// [`DEFAULT_REQUEST_TIMEOUT`].
+    pub timeout: Duration,
+    /// Whether to record conversions: off with `--no-history`, then `history`, then on.
+    pub history: bool,
+    /// `aliases`, checked.
+    pub aliases: Aliases,
+    /// `pairs` by lower-cased name, their aliases resolved to `(from, to)`.
+    pub pairs: BTreeMap<String, (String, String)>,
+}
+
+impl Settings {
+    /// Resolves every preference from `flags`, the env vars looked up through `env` (empty
+    /// ones count as unset) and `config`.
+    pub fn resolve(
+        flags: &Flags,
+        config: &Config,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Settings, String> {
+        let env = |name: &str| {
+            env(name)
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+
+        let provider = flags
+            .provider
+            .clone()
+            .or_else(|| config.provider.clone())
+            .unwrap_or_else(|| DEFAULT_PROVIDER.to_string());
+        provider_chain(&provider)?;
+
+        let env_base = env("DEFAULT_BASE_CURRENCY");
+        let (default_base, default_base_source) = [
+            (flags.base.as_deref(), "--base"),
+            (env_base.as_deref(), "DEFAULT_BASE_CURRENCY"),
+            (
+                config.default_base.as_deref(),
+                "default_base in the config file",
+            ),
+        ]
+        .into_iter()
+        .find_map(|(base, source)| {
+            base.map(str::trim)
+                .filter(|base| !base.is_empty())
+                .map(|base| (base, source))
+        })
+        .unwrap_or((DEFAULT_BASE_CURRENCY, "the built-in default"));
+
+        let configured_ttl = match (env("CACHE_TTL"), env("CACHE_DURATION")) {
+            (Some(ttl), _) => parse_duration(&ttl).map_err(|e| format!("CACHE_TTL: {}", e))?,
+            (None, Some(secs)) => secs
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(|_| format!("CACHE_DURATION '{}' is not a number of seconds", secs))?,
+            (None, None) => match (&config.cache_ttl, config.max_age) {
+                (Some(ttl), _) => parse_duration(ttl).map_err(|e| format!("cache_ttl: {}", e))?,
+                (None, Some(secs)) => Duration::from_secs(secs),
+                (None, None) => CACHE_DURATION,
+            },
+        };
+        let cache_ttl = flags.cache_ttl.unwrap_or(configured_ttl);
+
+        // The flags only change what one run treats as fresh, not how long the shared cache
+        // keeps rates for everyone else.
+        let prune_ttls = config.cache_prune_ttls.unwrap_or(DEFAULT_CACHE_PRUNE_TTLS);
+        let prune = PrunePolicy {
+            max_age: Some(configured_ttl.saturating_mul(prune_ttls))
+                .filter(|max_age| !max_age.is_zero()),
+            max_entries: config
+                .cache_max_entries
+                .unwrap_or(DEFAULT_CACHE_MAX_ENTRIES),
+            max_historical_entries: config
+                .max_historical_entries
+                .unwrap_or(DEFAULT_MAX_HISTORICAL_ENTRIES),
+        };
+
+        let round_mode = match (flags.round_mode, &config.round_mode) {
+            (Some(mode), _) => mode,
+            (None, Some(mode)) => mode
+                .trim()
+                .parse()
+                .map_err(|e| format!("round_mode: {}", e))?,
+            (None, None) => RoundMode::default(),
+        };
+
+        let fee = match (&flags.fee, config.fee) {
+            (Some(fee), _) => parse_fee(fee)?,
+            (None, Some(fee)) => parse_fee(&fee.to_string()).map_err(|e| format!("fee: {}", e))?,
+            (None, None) => Decimal::ZERO,
+        };
+
+        let timeout = match (flags.timeout, env("REQUEST_TIMEOUT")) {
+            (Some(timeout), _) => timeout,
+            (None, Some(value)) => {
+                parse_duration(&value).map_err(|e| format!("REQUEST_TIMEOUT: {}", e))?
+            }
+            (None, None) => DEFAULT_REQUEST_TIMEOUT,
+        };
+        if timeout.is_zero() {
+            return Err("the request timeout must be greater than zero".to_string());
+        }
+
+        let aliases = Aliases::new(&config.aliases.clone().unwrap_or_default())
+            .map_err(|e| format!("aliases: {}", e))?;
+        let mut pairs = BTreeMap::new();
+        for (name, pair) in config.pairs.iter().flatten() {
+            let codes =
+                parse_pair(pair, &aliases).map_err(|e| format!("pairs: {}: {}", name, e))?;
+            pairs.insert(name.trim().to_lowercase(), codes);
+        }
+
+        Ok(Settings {
+            api_key: flags
+                .api_key
+                .clone()
+                .or_else(|| env("API_KEY"))
+                .or_else(|| config.api_key.clone()),
+            provider,
+            default_base: default_base.to_uppercase(),
+            default_base_source,
+            cache_ttl,
+            prune,
+            precision: flags
+                .precision
+                .or(config.precision)
+                .unwrap_or(DEFAULT_PRECISION),
+            round_mode,
+            fee,
+            favorites: config
+                .favorites
+                .iter()
+                .flatten()
+                .map(|code| code.trim().to_uppercase())
+                .collect(),
+            timeout,
+            history: !flags.no_history && config.history.unwrap_or(true),
+            aliases,
+            pairs,
+        })
+    }
+}
+
+/// Parses a duration such as `30s`, `15m`, `6h` or `1d`; a bare number is seconds and `0`
+/// means cached rates are never fresh.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let trimmed = input.trim();
+    let (number, unit_secs) = match trimmed.char_indices().last() {
+        Some((index, 's')) => (&trimmed[..index], 1),
+        Some((index, 'm')) => (&trimmed[..index], 60),
+        Some((index, 'h')) => (&trimmed[..index], 3600),
+        Some((index, 'd')) => (&trimmed[..index], 86400),
+        _ => (trimmed, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit_secs))
+        .map(Duration::from_secs)
+        .ok_or_else(|| {
+            format!(
+                "invalid duration '{}', expected a number with an optional s, m, h or d suffix (e.g. 15m)",
+                input
+            )
+        })
+}
+
+/// Fixed base currency for providers that only quote rates against one base (`PROVIDER_BASE`).
+pub fn provider_base() -> Option<String> {
+    env::var("PROVIDER_BASE")
+        .ok()
+        .map(|base| base.trim().to_uppercase())
+        .filter(|base| !base.is_empty())
+}
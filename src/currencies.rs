@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+/// Display details for a currency code.
+#[derive(Debug, PartialEq)]
+pub struct CurrencyInfo {
+    pub code: &'static str,
+    pub name: &'static str,
+    pub symbol: &'static str,
+}
+
+const fn info(code: &'static str, name: &'static str, symbol: &'static str) -> CurrencyInfo {
+    CurrencyInfo { code, name, symbol }
+}
+
+/// Names and symbols for every code in [`CURRENCY_CODES`](crate::codes::CURRENCY_CODES).
+/// Other codes, such as metals or crypto allowed with `--allow-unknown`, are shown bare.
+pub static CURRENCIES: &[CurrencyInfo] = &[
+    info("AED", "UAE Dirham", "د.إ"),
+    info("AFN", "Afghan Afghani", "؋"),
+    info("ALL", "Albanian Lek", "L"),
+    info("AMD", "Armenian Dram", "֏"),
+    info("ANG", "Netherlands Antillean Guilder", "ƒ"),
+    info("AOA", "Angolan Kwanza", "Kz"),
+    info("ARS", "Argentine Peso", "AR$"),
+    info("AUD", "Australian Dollar", "A$"),
+    info("AWG", "Aruban Florin", "ƒ"),
+    info("AZN", "Azerbaijani Manat", "₼"),
+    info("BAM", "Bosnia-Herzegovina Convertible Mark", "KM"),
+    info("BBD", "Barbadian Dollar", "Bds$"),
+    info("BDT", "Bangladeshi Taka", "৳"),
+    info("BGN", "Bulgarian Lev", "лв"),
+    info("BHD", "Bahraini Dinar", "BD"),
+    info("BIF", "Burundian Franc", "FBu"),
+    info("BMD", "Bermudian Dollar", "BD$"),
+    info("BND", "Brunei Dollar", "B$"),
+    info("BOB", "Bolivian Boliviano", "Bs"),
+    info("BRL", "Brazilian Real", "R$"),
+    info("BSD", "Bahamian Dollar", "B$"),
+    info("BTN", "Bhutanese Ngultrum", "Nu."),
+    info("BWP", "Botswana Pula", "P"),
+    info("BYN", "Belarusian Ruble", "Br"),
+    info("BZD", "Belize Dollar", "BZ$"),
+    info("CAD", "Canadian Dollar", "C$"),
+    info("CDF", "Congolese Franc", "FC"),
+    info("CHF", "Swiss Franc", "CHF"),
+    info("CLP", "Chilean Peso", "CL$"),
+    info("CNY", "Chinese Yuan", "¥"),
+    info("COP", "Colombian Peso", "COL$"),
+    info("CRC", "Costa Rican Colón", "₡"),
+    info("CUP", "Cuban Peso", "$MN"),
+    info("CVE", "Cape Verdean Escudo", "Esc"),
+    info("CZK", "Czech Koruna", "Kč"),
+    info("DJF", "Djiboutian Franc", "Fdj"),
+    info("DKK", "Danish Krone", "kr"),
+    info("DOP", "Dominican Peso", "RD$"),
+    info("DZD", "Algerian Dinar", "DA"),
+    info("EGP", "Egyptian Pound", "E£"),
+    info("ERN", "Eritrean Nakfa", "Nfk"),
+    info("ETB", "Ethiopian Birr", "Br"),
+    info("EUR", "Euro", "€"),
+    info("FJD", "Fijian Dollar", "FJ$"),
+    info("FKP", "Falkland Islands Pound", "FK£"),
+    info("GBP", "Pound Sterling", "£"),
+    info("GEL", "Georgian Lari", "₾"),
+    info("GHS", "Ghanaian Cedi", "GH₵"),
+    info("GIP", "Gibraltar Pound", "£"),
+    info("GMD", "Gambian Dalasi", "D"),
+    info("GNF", "Guinean Franc", "FG"),
+    info("GTQ", "Guatemalan Quetzal", "Q"),
+    info("GYD", "Guyanese Dollar", "GY$"),
+    info("HKD", "Hong Kong Dollar", "HK$"),
+    info("HNL", "Honduran Lempira", "L"),
+    info("HTG", "Haitian Gourde", "G"),
+    info("HUF", "Hungarian Forint", "Ft"),
+    info("IDR", "Indonesian Rupiah", "Rp"),
+    info("ILS", "Israeli New Shekel", "₪"),
+    info("INR", "Indian Rupee", "₹"),
+    info("IQD", "Iraqi Dinar", "ع.د"),
+    info("IRR", "Iranian Rial", "﷼"),
+    info("ISK", "Icelandic Króna", "kr"),
+    info("JMD", "Jamaican Dollar", "J$"),
+    info("JOD", "Jordanian Dinar", "JD"),
+    info("JPY", "Japanese Yen", "¥"),
+    info("KES", "Kenyan Shilling", "KSh"),
+    info("KGS", "Kyrgyzstani Som", "сом"),
+    info("KHR", "Cambodian Riel", "៛"),
+    info("KMF", "Comorian Franc", "CF"),
+    info("KPW", "North Korean Won", "₩"),
+    info("KRW", "South Korean Won", "₩"),
+    info("KWD", "Kuwaiti Dinar", "KD"),
+    info("KYD", "Cayman Islands Dollar", "CI$"),
+    info("KZT", "Kazakhstani Tenge", "₸"),
+    info("LAK", "Lao Kip", "₭"),
+    info("LBP", "Lebanese Pound", "L£"),
+    info("LKR", "Sri Lankan Rupee", "Rs"),
+    info("LRD", "Liberian Dollar", "L$"),
+    info("LSL", "Lesotho Loti", "L"),
+    info("LYD", "Libyan Dinar", "LD"),
+    info("MAD", "Moroccan Dirham", "DH"),
+    info("MDL", "Moldovan Leu", "L"),
+    info("MGA", "Malagasy Ariary", "Ar"),
+    info("MKD", "Macedonian Denar", "ден"),
+    info("MMK", "Myanmar Kyat", "K"),
+    info("MNT", "Mongolian Tögrög", "₮"),
+    info("MOP", "Macanese Pataca", "MOP$"),
+    info("MRU", "Mauritanian Ouguiya", "UM"),
+    info("MUR", "Mauritian Rupee", "Rs"),
+    info("MVR", "Maldivian Rufiyaa", "Rf"),
+    info("MWK", "Malawian Kwacha", "MK"),
+    info("MXN", "Mexican Peso", "MX$"),
+    info("MYR", "Malaysian Ringgit", "RM"),
+    info("MZN", "Mozambican Metical", "MT"),
+    info("NAD", "Namibian Dollar", "N$"),
+    info("NGN", "Nigerian Naira", "₦"),
+    info("NIO", "Nicaraguan Córdoba", "C$"),
+    info("NOK", "Norwegian Krone", "kr"),
+    info("NPR", "Nepalese Rupee", "Rs"),
+    info("NZD", "New Zealand Dollar", "NZ$"),
+    info("OMR", "Omani Rial", "RO"),
+    info("PAB", "Panamanian Balboa", "B/."),
+    info("PEN", "Peruvian Sol", "S/"),
+    info("PGK", "Papua New Guinean Kina", "K"),
+    info("PHP", "Philippine Peso", "₱"),
+    info("PKR", "Pakistani Rupee", "Rs"),
+    info("PLN", "Polish Złoty", "zł"),
+    info("PYG", "Paraguayan Guaraní", "₲"),
+    info("QAR", "Qatari Riyal", "QR"),
+    info("RON", "Romanian Leu", "lei"),
+    info("RSD", "Serbian Dinar", "дин"),
+    info("RUB", "Russian Ruble", "₽"),
+    info("RWF", "Rwandan Franc", "FRw"),
+    info("SAR", "Saudi Riyal", "SR"),
+    info("SBD", "Solomon Islands Dollar", "SI$"),
+    info("SCR", "Seychellois Rupee", "SR"),
+    info("SDG", "Sudanese Pound", "£SD"),
+    info("SEK", "Swedish Krona", "kr"),
+    info("SGD", "Singapore Dollar", "S$"),
+    info("SHP", "Saint Helena Pound", "£"),
+    info("SLE", "Sierra Leonean Leone", "Le"),
+    info("SLL", "Sierra Leonean Leone (old)", "Le"),
+    info("SOS", "Somali Shilling", "Sh.So."),
+    info("SRD", "Surinamese Dollar", "SR$"),
+    info("SSP", "South Sudanese Pound", "SS£"),
+    info("STN", "São Tomé and Príncipe Dobra", "Db"),
+    info("SYP", "Syrian Pound", "£S"),
+    info("SZL", "Swazi Lilangeni", "E"),
+    info("THB", "Thai Baht", "฿"),
+    info("TJS", "Tajikistani Somoni", "SM"),
+    info("TMT", "Turkmenistani Manat", "m"),
+    info("TND", "Tunisian Dinar", "DT"),
+    info("TOP", "Tongan Paʻanga", "T$"),
+    info("TRY", "Turkish Lira", "₺"),
+    info("TTD", "Trinidad and Tobago Dollar", "TT$"),
+    info("TWD", "New Taiwan Dollar", "NT$"),
+    info("TZS", "Tanzanian Shilling", "TSh"),
+    info("UAH", "Ukrainian Hryvnia", "₴"),
+    info("UGX", "Ugandan Shilling", "USh"),
+    info("USD", "US Dollar", "$"),
+    info("UYU", "Uruguayan Peso", "$U"),
+    info("UZS", "Uzbekistani Som", "soʻm"),
+    info("VES", "Venezuelan Bolívar", "Bs.S"),
+    info("VND", "Vietnamese Đồng", "₫"),
+    info("VUV", "Vanuatu Vatu", "VT"),
+    info("WST", "Samoan Tālā", "WS$"),
+    info("XAF", "Central African CFA Franc", "FCFA"),
+    info("XCD", "East Caribbean Dollar", "EC$"),
+    info("XOF", "West African CFA Franc", "CFA"),
+    info("XPF", "CFP Franc", "₣"),
+    info("YER", "Yemeni Rial", "﷼"),
+    info("ZAR", "South African Rand", "R"),
+    info("ZMW", "Zambian Kwacha", "ZK"),
+    info("ZWL", "Zimbabwean Dollar", "Z$"),
+];
+
+/// Every code in [`CURRENCIES`] with its name, as `currencies` lists them when no provider
+/// can.
+pub fn iso_names() -> BTreeMap<String, String> {
+    CURRENCIES
+        .iter()
+        .map(|info| (info.code.to_string(), info.name.to_string()))
+        .collect()
+}
+
+/// The codes of `names` whose code or name contains `filter`, ignoring case, in code order.
+pub fn matching<'a>(names: &'a BTreeMap<String, String>, filter: &str) -> Vec<(&'a str, &'a str)> {
+    let filter = filter.to_lowercase();
+    names
+        .iter()
+        .filter(|(code, name)| {
+            code.to_lowercase().contains(&filter) || name.to_lowercase().contains(&filter)
+        })
+        .map(|(code, name)| (code.as_str(), name.as_str()))
+        .collect()
+}
+
+pub fn lookup(code: &str) -> Option<&'static CurrencyInfo> {
+    CURRENCIES.iter().find(|info| info.code == code)
+}
+
+/// `USD ($)` for known currencies, the bare code otherwise.
+pub fn with_symbol(code: &str) -> String {
+    match lookup(code) {
+        Some(info) => format!("{} ({})", code, info.symbol),
+        None => code.to_string(),
+    }
+}
@@ -0,0 +1,192 @@
+use crate::codes::describe_unknown;
+use crate::models::{describe_age, RateNotFound};
+use reqwest::StatusCode;
+use std::{error::Error, fmt, io, time::Duration};
+
+/// Process exit statuses, so scripts can tell failures apart.
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_INVALID_INPUT: i32 = 2;
+pub const EXIT_UNKNOWN_CURRENCY: i32 = 3;
+pub const EXIT_NETWORK: i32 = 4;
+pub const EXIT_API_ACCESS: i32 = 5;
+pub const EXIT_NOT_CACHED: i32 = 6;
+pub const EXIT_STALE: i32 = 7;
+
+/// Stable name for an exit status, used as the `kind` of `--json` errors.
+pub fn error_kind(exit_code: i32) -> &'static str {
+    match exit_code {
+        EXIT_INVALID_INPUT => "invalid_input",
+        EXIT_UNKNOWN_CURRENCY => "unknown_currency",
+        EXIT_NETWORK => "network",
+        EXIT_API_ACCESS => "api_access",
+        EXIT_NOT_CACHED => "not_cached",
+        EXIT_STALE => "stale",
+        _ => "failure",
+    }
+}
+
+/// Why fetching, resolving or caching an exchange rate failed. New variants may be added,
+/// so matches need a wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CurrencyError {
+    /// `API_KEY` is not set in the environment or the config file.
+    MissingApiKey,
+    /// The provider refused the request because the plan's quota is used up.
+    RateLimited,
+    /// The provider answered 429 Too Many Requests, with the wait it asked for if any.
+    TooManyRequests(Option<Duration>),
+    /// The provider answered with an unexpected HTTP status.
+    Status(StatusCode),
+    /// The provider rejected the API key, or the account behind it is inactive.
+    InvalidApiKey,
+    /// The provider does not quote rates for the currency code.
+    UnsupportedCode(String),
+    /// The provider could not make sense of the request.
+    MalformedRequest,
+    /// The provider only answers the request on a paid plan, as with ExchangeRate-API's
+    /// history on a free key.
+    PlanUpgradeRequired,
+    /// The provider rejected the request with the status and an error message of its own.
+    Api { status: StatusCode, message: String },
+    /// The provider answered, but reported an error or sent no rates.
+    Provider(String),
+    /// The rates were fetched but have no entry for a requested currency.
+    RateNotFound(RateNotFound),
+    /// No complete response arrived within the request timeout.
+    Timeout(Duration),
+    /// The request could not be sent or its body could not be read.
+    Http(reqwest::Error),
+    /// `--offline` was given but the cache holds nothing for the named rate.
+    NotAvailableOffline(String),
+    /// The code isn't in the bundled ISO 4217 list.
+    UnknownCurrency(String),
+    /// The crypto provider has no price for the ticker.
+    UnsupportedCrypto(String),
+    /// The fetch failed with `error` and, under `--strict`, the cached rate from `age` ago
+    /// wasn't used in its place.
+    StaleRate {
+        error: Box<CurrencyError>,
+        age: Duration,
+    },
+    /// The cache file could not be read, locked or written.
+    Cache(io::Error),
+    /// The provider's response body is not the JSON we expect.
+    Parse(serde_json::Error),
+}
+
+impl CurrencyError {
+    /// The exit status for this error: a currency without a rate is reported as unknown,
+    /// a missing key, refused key, used-up quota or request beyond the plan as an API
+    /// access problem, an `--offline` miss and a refused stale rate on their own, a cache
+    /// problem as a plain failure and anything else as a network or API failure.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CurrencyError::RateNotFound(_)
+            | CurrencyError::UnknownCurrency(_)
+            | CurrencyError::UnsupportedCrypto(_)
+            | CurrencyError::UnsupportedCode(_) => EXIT_UNKNOWN_CURRENCY,
+            CurrencyError::MissingApiKey
+            | CurrencyError::InvalidApiKey
+            | CurrencyError::RateLimited
+            | CurrencyError::PlanUpgradeRequired
+            | CurrencyError::TooManyRequests(_) => EXIT_API_ACCESS,
+            CurrencyError::Status(status) | CurrencyError::Api { status, .. }
+                if *status == StatusCode::UNAUTHORIZED || *status == StatusCode::FORBIDDEN =>
+            {
+                EXIT_API_ACCESS
+            }
+            CurrencyError::NotAvailableOffline(_) => EXIT_NOT_CACHED,
+            CurrencyError::StaleRate { .. } => EXIT_STALE,
+            CurrencyError::Cache(_) => EXIT_FAILURE,
+            _ => EXIT_NETWORK,
+        }
+    }
+}
+
+impl fmt::Display for CurrencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CurrencyError::MissingApiKey => write!(f, "API_KEY is not set"),
+            CurrencyError::RateLimited => write!(f, "API request limit exceeded"),
+            CurrencyError::TooManyRequests(Some(wait)) => {
+                write!(f, "too many requests, try again in {}s", wait.as_secs())
+            }
+            CurrencyError::TooManyRequests(None) => {
+                write!(f, "too many requests, try again later")
+            }
+            CurrencyError::Status(status) => {
+                write!(f, "Error fetching all exchange rates: {}", status)
+            }
+            CurrencyError::InvalidApiKey => write!(f, "the API key was rejected"),
+            CurrencyError::UnsupportedCode(code) => {
+                write!(f, "{} is not supported by the provider", code)
+            }
+            CurrencyError::MalformedRequest => {
+                write!(f, "the provider rejected the request as malformed")
+            }
+            CurrencyError::PlanUpgradeRequired => {
+                write!(f, "the API plan doesn't include this request")
+            }
+            CurrencyError::Api { status, message } => write!(f, "{} ({})", message, status),
+            CurrencyError::Provider(message) => write!(f, "{}", message),
+            CurrencyError::RateNotFound(e) => write!(f, "{}", e),
+            CurrencyError::Timeout(timeout) => {
+                write!(f, "request timed out after {}s", timeout.as_secs_f64())
+            }
+            CurrencyError::Http(e) => write!(f, "{}", e),
+            CurrencyError::NotAvailableOffline(what) => {
+                write!(f, "{} not available offline", what)
+            }
+            CurrencyError::UnknownCurrency(code) => write!(f, "{}", describe_unknown(code)),
+            CurrencyError::UnsupportedCrypto(code) => {
+                write!(f, "{} is not a supported crypto asset", code)
+            }
+            CurrencyError::StaleRate { error, age } => write!(
+                f,
+                "{}; --strict refuses the cached rate from {} ago",
+                error,
+                describe_age(*age)
+            ),
+            CurrencyError::Cache(e) => write!(f, "cache error: {}", e),
+            CurrencyError::Parse(e) => write!(f, "invalid response from the provider: {}", e),
+        }
+    }
+}
+
+impl Error for CurrencyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CurrencyError::RateNotFound(e) => Some(e),
+            CurrencyError::StaleRate { error, .. } => Some(error.as_ref()),
+            CurrencyError::Http(e) => Some(e),
+            CurrencyError::Cache(e) => Some(e),
+            CurrencyError::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<RateNotFound> for CurrencyError {
+    fn from(e: RateNotFound) -> Self {
+        CurrencyError::RateNotFound(e)
+    }
+}
+
+impl From<reqwest::Error> for CurrencyError {
+    fn from(e: reqwest::Error) -> Self {
+        CurrencyError::Http(e)
+    }
+}
+
+impl From<io::Error> for CurrencyError {
+    fn from(e: io::Error) -> Self {
+        CurrencyError::Cache(e)
+    }
+}
+
+impl From<serde_json::Error> for CurrencyError {
+    fn from(e: serde_json::Error) -> Self {
+        CurrencyError::Parse(e)
+    }
+}
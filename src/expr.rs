@@ -0,0 +1,92 @@
+use rust_decimal::Decimal;
+
+/// Evaluates an arithmetic expression of decimal numbers with `+ - * /`, unary minus,
+/// and parentheses, using the usual precedence. The arithmetic is decimal, so `0.1+0.2`
+/// is exactly `0.3`.
+pub fn evaluate(input: &str) -> Result<Decimal, String> {
+    let mut parser = Parser {
+        chars: input.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+    };
+    let value = parser.expression()?;
+    if parser.pos != parser.chars.len() {
+        return Err(format!("unexpected '{}'", parser.chars[parser.pos]));
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expression(&mut self) -> Result<Decimal, String> {
+        let mut value = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = if op == '+' {
+                value.checked_add(rhs)
+            } else {
+                value.checked_sub(rhs)
+            }
+            .ok_or_else(overflow)?;
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<Decimal, String> {
+        let mut value = self.factor()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.factor()?;
+            value = if op == '*' {
+                value.checked_mul(rhs).ok_or_else(overflow)?
+            } else if rhs.is_zero() {
+                return Err("division by zero".to_string());
+            } else {
+                value.checked_div(rhs).ok_or_else(overflow)?
+            };
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<Decimal, String> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(-self.factor()?)
+            }
+            Some('(') => {
+                self.pos += 1;
+                let value = self.expression()?;
+                if self.peek() != Some(')') {
+                    return Err("missing ')'".to_string());
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_digit() || c == '.') {
+                    self.pos += 1;
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                number
+                    .parse()
+                    .map_err(|_| format!("invalid number '{}'", number))
+            }
+            Some(c) => Err(format!("unexpected '{}'", c)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+fn overflow() -> String {
+    "number too large".to_string()
+}
@@ -0,0 +1,185 @@
+//! The conversion history: one JSON record per line, appended after each successful
+//! conversion and read back by `history`.
+
+use crate::config::HISTORY_FILE;
+use crate::models::{apply_fee, RateQuote};
+use crate::output::{amount_precision, OutputFormat};
+use chrono::{DateTime, NaiveDate, SecondsFormat};
+use directories::ProjectDirs;
+use log::{trace, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+/// One recorded conversion.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryRecord {
+    /// When the conversion ran, in RFC 3339 with the local offset.
+    pub timestamp: String,
+    pub from: String,
+    pub to: String,
+    pub amount: Decimal,
+    pub rate: f64,
+    /// The converted amount as printed, after the fee when there was one.
+    pub converted: Decimal,
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub fee_percent: Decimal,
+    pub provider: String,
+    /// The rate came from the cache rather than a live fetch.
+    pub cached: bool,
+    /// The rate was pinned with `rate set`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+}
+
+impl HistoryRecord {
+    /// The record of converting `amount` from `from` to `to` at `quote`, now, rounded
+    /// the way `format` prints it.
+    pub fn new(
+        amount: Decimal,
+        from: &str,
+        to: &str,
+        quote: &RateQuote,
+        format: &OutputFormat,
+    ) -> Self {
+        let precision = amount_precision(to, format.precision);
+        let breakdown = apply_fee(amount, quote.rate, format.fee, precision, format.round_mode);
+        // Padded to the printed places, so 80 PLN is recorded as 80.00.
+        let mut converted = breakdown.after_fee;
+        converted.rescale(precision as u32);
+        HistoryRecord {
+            timestamp: chrono::Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            rate: quote.rate,
+            converted,
+            fee_percent: format.fee,
+            provider: quote.provider.clone(),
+            cached: quote.cached,
+            pinned: quote.pinned,
+        }
+    }
+
+    /// The day of [`HistoryRecord::timestamp`] in the offset it was recorded with, or
+    /// `None` if it doesn't parse.
+    pub fn date(&self) -> Option<NaiveDate> {
+        DateTime::parse_from_rfc3339(&self.timestamp)
+            .ok()
+            .map(|timestamp| timestamp.date_naive())
+    }
+}
+
+/// Which records `history` shows.
+#[derive(Debug, Default, PartialEq)]
+pub struct HistoryFilter {
+    /// Only the most recent this many, after the other filters.
+    pub last: Option<usize>,
+    /// Only conversions from the first code to the second, in that direction.
+    pub pair: Option<(String, String)>,
+    /// Only conversions on or after this day.
+    pub since: Option<NaiveDate>,
+}
+
+impl HistoryFilter {
+    /// The records of `history`, oldest first, that pass the filter.
+    pub fn apply(&self, history: Vec<HistoryRecord>) -> Vec<HistoryRecord> {
+        let mut records: Vec<HistoryRecord> = history
+            .into_iter()
+            .filter(|record| match &self.pair {
+                Some((from, to)) => record.from == *from && record.to == *to,
+                None => true,
+            })
+            .filter(|record| match self.since {
+                Some(since) => record.date().is_some_and(|date| date >= since),
+                None => true,
+            })
+            .collect();
+        if let Some(last) = self.last {
+            records.drain(..records.len().saturating_sub(last));
+        }
+        records
+    }
+}
+
+/// The history file: `CURRENCY_CLI_HISTORY_FILE` when set, otherwise `history.jsonl` in
+/// the platform data directory (e.g. `~/.local/share/currency_cli` on Linux), or in the
+/// working directory when there is no home directory.
+pub fn history_path() -> PathBuf {
+    if let Some(path) = env::var_os("CURRENCY_CLI_HISTORY_FILE").filter(|path| !path.is_empty()) {
+        return PathBuf::from(path);
+    }
+    match ProjectDirs::from("", "", "currency_cli") {
+        Some(dirs) => dirs.data_dir().join(HISTORY_FILE),
+        None => PathBuf::from(HISTORY_FILE),
+    }
+}
+
+/// Appends `records` to the history at `path` as lines of JSON, creating the file and its
+/// directory if needed. Earlier lines are never rewritten.
+pub fn append_history(path: &Path, records: &[HistoryRecord]) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut lines = String::new();
+    for record in records {
+        lines.push_str(&serde_json::to_string(record).expect("history records serialize"));
+        lines.push('\n');
+    }
+    // A single write, so lines from concurrent runs don't interleave.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(lines.as_bytes())?;
+    Ok(())
+}
+
+/// Reads the history at `path`, oldest first. A missing file is an empty history; lines
+/// that don't parse are skipped with a warning.
+pub fn read_history(path: &Path) -> io::Result<Vec<HistoryRecord>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            trace!("no history file at {}", path.display());
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e),
+    };
+    let mut records = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let parsed = line
+            .map_err(|e| e.to_string())
+            .and_then(|line| match line.trim() {
+                "" => Ok(None),
+                line => serde_json::from_str(line)
+                    .map(Some)
+                    .map_err(|e| e.to_string()),
+            });
+        match parsed {
+            Ok(Some(record)) => records.push(record),
+            Ok(None) => {}
+            Err(e) => warn!(
+                "skipping corrupt line {} of {}: {}",
+                index + 1,
+                path.display(),
+                e
+            ),
+        }
+    }
+    Ok(records)
+}
+
+/// Deletes the history at `path`, returning whether there was one.
+pub fn clear_history(path: &Path) -> io::Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,39 @@
+//! Currency conversion backed by the ExchangeRate-API and other rate providers, with a
+//! local rate cache.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), currency::CurrencyError> {
+//! let mut cache = currency::load_cache().unwrap_or_default();
+//! let max_age = currency::config::CACHE_DURATION;
+//! let quote = currency::fetch_exchange_rate("USD", "EUR", &mut cache, max_age, None).await?;
+//! println!("1 USD is {} EUR", quote.rate);
+//! currency::save_cache(&cache).ok();
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod aliases;
+pub mod api;
+pub mod batch;
+pub mod cache;
+pub mod chart;
+pub mod cli;
+pub mod codes;
+pub mod config;
+pub mod currencies;
+pub mod error;
+mod expr;
+pub mod history;
+pub mod models;
+pub mod numbers;
+pub mod output;
+pub mod overrides;
+pub mod provider;
+pub mod repl;
+pub mod server;
+pub mod table;
+
+pub use api::{fetch_all_exchange_rates, fetch_exchange_rate, ApiClient};
+pub use cache::{load_cache, save_cache};
+pub use error::CurrencyError;
+pub use models::{CacheItem, RateQuote, Rates};
@@ -1,214 +1,2584 @@
-mod api;
-mod cache;
-mod config;
-mod models;
-use crate::models::{CacheItem, Rates};
-use api::{fetch_all_exchange_rates, fetch_exchange_rate};
-use cache::{load_cache, save_cache};
-use clap::{Arg, Command};
+use chrono::NaiveDate;
+use clap::ArgMatches;
+use clap_complete::Shell;
+use currency::aliases::{load_aliases, parse_pair, save_aliases, Aliases};
+use currency::api::{
+    cached_code_list, cached_entry, cached_rate, cross_rate, is_transient, offline_rate,
+    offline_rates, rate_matrix, ApiClient,
+};
+use currency::batch::{parse_batch_line_with, parse_columns, BatchRow};
+use currency::cache::{
+    cache_path, cache_stats, export_cache, import_cache, load_cache_at, parse_cache_export,
+    remove_cache_file, remove_cached_base, save_pruned_cache_to, ImportMode, PrunePolicy,
+    PruneReport,
+};
+use currency::chart::render_chart;
+use currency::cli::{build_cli, completion_script, parse_conversion_args_with, ConversionArgs};
+use currency::codes::validate_currency_code;
+use currency::config::{
+    default_config_path, provider_base, write_config_template, Config, Flags, Settings,
+    CODE_LIST_TTL, DEFAULT_QUOTE_TIMEOUT, DEFAULT_SERVE_PORT, MAX_MATRIX_CURRENCIES,
+};
+use currency::currencies;
+use currency::error::{CurrencyError, EXIT_FAILURE, EXIT_INVALID_INPUT};
+use currency::history::{
+    append_history, clear_history, history_path, read_history, HistoryFilter, HistoryRecord,
+};
+use currency::models::{
+    check_nonzero, describe_age, parse_amount, parse_date, required_amount, series_dates,
+    CacheItem, Interval, RateQuote, Rates, SortKey, SortOrder, Threshold,
+};
+use currency::numbers::NumberFormat;
+use currency::output::{
+    alert_line, alert_status_line, alias_lines, aliases_json, amount_precision, amount_table,
+    change_json, change_lines, changes_json, color_enabled, comparison_line, conversion_csv,
+    conversion_csv_header, conversion_json, conversion_line, csv_record, currencies_json,
+    currency_lines, describe_source, error_json, error_line, format_rate, hidden_line,
+    history_line, inverse_line, limit_entries, list_entries, matrix_json, matrix_table,
+    override_lines, overrides_json, quote_json, quote_table, rate_change, rate_changes, rate_lines,
+    rate_table, rates_csv, rates_json, raw_line, raw_required_line, required_json, required_line,
+    sort_by_change, timeseries_csv, watch_line, ColorChoice, ListColumn, OutputFormat, RateChanges,
+    RawValue, LIST_COLUMNS, RATES_CSV_HEADER, TIMESERIES_CSV_HEADER,
+};
+use currency::overrides::{
+    load_overrides, overrides_path, save_overrides, RateOverride, RateOverrides,
+};
+use currency::repl::{parse_repl_line, ReplCommand, REPL_HELP};
+use currency::server::{Server, ENDPOINTS};
 use dotenv::dotenv;
-use std::{collections::HashMap, time::SystemTime};
+use rust_decimal::Decimal;
+use std::{
+    collections::HashMap,
+    env,
+    fmt::{self, Display},
+    fs,
+    io::{self, IsTerminal, Write},
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime},
+};
+use tokio::runtime::Runtime;
+
+/// Whether errors on stderr are colored, settled once the arguments are parsed.
+static STDERR_COLOR: OnceLock<bool> = OnceLock::new();
+/// What saving the cache prunes, from the settings of the run.
+static PRUNE_POLICY: OnceLock<PrunePolicy> = OnceLock::new();
+/// The cache file of the run, `--cache-file` or else [`cache_path`].
+static CACHE_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// `println!` for what the run prints: once stdout is closed, as when it is piped into
+/// `head`, the run ends quietly instead of panicking.
+macro_rules! out {
+    () => {
+        write_stdout(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        write_stdout(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Writes `text` to stdout at once. A closed pipe ends the run with status 0, any other
+/// failure to write with [`EXIT_FAILURE`].
+fn write_stdout(text: fmt::Arguments) {
+    let mut stdout = io::stdout().lock();
+    if let Err(e) = stdout.write_fmt(text).and_then(|()| stdout.flush()) {
+        if e.kind() == io::ErrorKind::BrokenPipe {
+            std::process::exit(0);
+        }
+        fail(
+            false,
+            EXIT_FAILURE,
+            format!("cannot write to stdout: {}", e),
+        );
+    }
+}
+
+/// Rates of one `list` base, with the age of the cached entry when read from the cache.
+type ListedRates = (HashMap<String, f64>, Option<Duration>);
+
+/// The rate cache for one run. Under `--refresh` lookups start from an empty map and the
+/// stored entries are only merged back when saving; under `--no-cache` nothing is read or
+/// written. A run that stored nothing new, such as any run under `--offline`, leaves the
+/// file alone.
+struct RunCache {
+    entries: HashMap<String, CacheItem>,
+    hidden: HashMap<String, CacheItem>,
+    no_cache: bool,
+    /// When each entry read from disk was stored, to tell what the run has changed.
+    loaded_at: HashMap<String, SystemTime>,
+}
+
+impl RunCache {
+    fn load(no_cache: bool, refresh: bool) -> Self {
+        let loaded = if no_cache {
+            HashMap::new()
+        } else {
+            load_cache_at(cache_file()).unwrap_or_else(|e| {
+                log::warn!("{}", error_message(&e));
+                HashMap::new()
+            })
+        };
+        let loaded_at = loaded
+            .iter()
+            .map(|(key, item)| (key.clone(), item.timestamp))
+            .collect();
+        let (entries, hidden) = if refresh {
+            (HashMap::new(), loaded)
+        } else {
+            (loaded, HashMap::new())
+        };
+        RunCache {
+            entries,
+            hidden,
+            no_cache,
+            loaded_at,
+        }
+    }
+
+    /// The entries read from disk, including those `--refresh` hides. Only meaningful
+    /// before anything has been fetched.
+    fn loaded(&self) -> &HashMap<String, CacheItem> {
+        if self.hidden.is_empty() {
+            &self.entries
+        } else {
+            &self.hidden
+        }
+    }
+
+    /// Whether an entry was added or replaced since the cache was loaded.
+    fn changed(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|(key, item)| self.loaded_at.get(key) != Some(&item.timestamp))
+    }
+
+    fn save(mut self) {
+        if self.no_cache || !self.changed() {
+            return;
+        }
+        self.hidden.extend(self.entries);
+        let policy = PRUNE_POLICY.get().unwrap_or(&PrunePolicy::NONE);
+        let path = cache_file();
+        let saved = match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            Some(dir) => fs::create_dir_all(dir).map_err(CurrencyError::from),
+            None => Ok(()),
+        }
+        .and_then(|()| save_pruned_cache_to(path, &self.hidden, policy));
+        match saved {
+            Ok(report) if !report.is_empty() => log::debug!(
+                "pruned {} expired and {} evicted cache entries",
+                report.expired.len(),
+                report.evicted.len()
+            ),
+            Ok(_) => {}
+            Err(e) => log::warn!("{}", error_message(&e)),
+        }
+    }
+}
+
+impl Deref for RunCache {
+    type Target = HashMap<String, CacheItem>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl DerefMut for RunCache {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
+
+/// What the global flags and the config settle for every subcommand.
+struct Context {
+    json: bool,
+    csv: bool,
+    csv_header: bool,
+    settings: Settings,
+    api: ApiClient,
+    /// Codes are checked against ISO 4217 unless `--no-validate`.
+    validate: bool,
+    allow_zero: bool,
+    default_base: String,
+    output_format: OutputFormat,
+    max_age: Duration,
+    offline: bool,
+    no_cache: bool,
+    refresh: bool,
+    /// `--date`, the day the rates are asked for.
+    date: Option<NaiveDate>,
+    /// One runtime serves every request of the run.
+    runtime: Runtime,
+}
+
+impl Context {
+    /// `token` with the aliases from the config file standing in for codes.
+    fn resolve(&self, token: &str) -> String {
+        self.settings
+            .aliases
+            .resolve(token)
+            .unwrap_or_else(|e| fail(self.json, EXIT_INVALID_INPUT, e))
+    }
+
+    /// The code `token` stands for, resolved and, unless `--no-validate`, checked.
+    fn code(&self, token: &str) -> String {
+        let code = self.resolve(token);
+        if self.validate {
+            if let Err(e) = validate_currency_code(&code) {
+                fail(self.json, e.exit_code(), error_message(&e));
+            }
+        }
+        code
+    }
+}
 
 fn main() {
-    match dotenv() {
-        Ok(_) => println!(".env file loaded"),
-        Err(error) => println!("Warning: Failed to load .env file: {}", error),
-    }
-
-    let app = Command::new("Currency Converter")
-        .version("1.0")
-        .author("Michal Zagalski")
-        .about("Converts currencies and lists exchange rates")
-        .arg(
-            Arg::new("FROM_CURRENCY")
-                .help("The source currency code")
-                .required(false)
-                .index(1),
-        )
-        .arg(
-            Arg::new("TO_CURRENCY")
-                .help("The target currency code")
-                .required(false)
-                .index(2),
-        )
-        .arg(
-            Arg::new("AMOUNT")
-                .help("The amount to convert")
-                .required(false)
-                .index(3),
+    let app = build_cli();
+
+    // `->` as in `100 usd -> eur` would otherwise be read as a flag.
+    let matches =
+        app.get_matches_from(env::args_os().map(|arg| if arg == "->" { "to".into() } else { arg }));
+    let output = matches.get_one::<String>("OUTPUT").map(String::as_str);
+    let json = matches.get_flag("JSON") || output == Some("json");
+    let csv = output == Some("csv");
+    let csv_header = !matches.get_flag("NO_HEADER");
+    let color_choice: ColorChoice = matches.get_one::<String>("COLOR").unwrap().parse().unwrap();
+    let _ = STDERR_COLOR.set(color_enabled(color_choice, std::io::stderr().is_terminal()));
+    if let Some(("completions", sub_matches)) = matches.subcommand() {
+        let shell = sub_matches.get_one::<Shell>("SHELL").copied().unwrap();
+        write_stdout(format_args!("{}", completion_script(shell)));
+        return;
+    }
+
+    // Loaded before the logger is set up so that a RUST_LOG in .env applies.
+    let dotenv = dotenv();
+
+    let log_level = match matches.get_count("VERBOSE") {
+        _ if matches.get_flag("QUIET") => log::LevelFilter::Error,
+        // Each request `serve` answers is logged at info level.
+        0 if matches!(matches.subcommand(), Some(("serve", _))) => log::LevelFilter::Info,
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    // Logs go to stderr, so --json and --raw output on stdout stays clean.
+    env_logger::Builder::new()
+        .filter_level(log_level.min(log::LevelFilter::Warn))
+        .filter_module("currency", log_level)
+        .write_style(match color_choice {
+            ColorChoice::Auto => env_logger::WriteStyle::Auto,
+            ColorChoice::Always => env_logger::WriteStyle::Always,
+            ColorChoice::Never => env_logger::WriteStyle::Never,
+        })
+        .parse_default_env()
+        .init();
+    // A .env file is optional, so only -v tells whether one was read.
+    match dotenv {
+        Ok(path) => log::debug!("loaded {}", path.display()),
+        Err(e) => log::debug!("no .env file loaded: {}", e),
+    }
+
+    let config_path = matches.get_one::<String>("CONFIG").map(String::as_str);
+    // The config file `config` and `alias` write to.
+    let config_file = || {
+        config_path
+            .map(PathBuf::from)
+            .or_else(default_config_path)
+            .unwrap_or_else(|| {
+                fail(
+                    json,
+                    EXIT_FAILURE,
+                    "no config directory, pass --config PATH",
+                )
+            })
+    };
+    // `config init` must work even when the existing file doesn't load.
+    if let Some(("config", sub_matches)) = matches.subcommand() {
+        std::process::exit(run_config(json, &config_file(), sub_matches));
+    }
+    // Only the aliases are read, so a bad setting elsewhere in the file doesn't stand in the way.
+    if let Some(("alias", sub_matches)) = matches.subcommand() {
+        std::process::exit(run_alias(json, &config_file(), sub_matches));
+    }
+
+    let config = match Config::load(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            fail(json, EXIT_INVALID_INPUT, e);
+        }
+    };
+    let flags = Flags {
+        api_key: matches.get_one::<String>("API_KEY").cloned(),
+        provider: matches.get_one::<String>("PROVIDER").cloned(),
+        base: matches.get_one::<String>("BASE").cloned(),
+        cache_ttl: matches.get_one::<Duration>("CACHE_TTL").copied().or(matches
+            .get_one::<u64>("MAX_AGE")
+            .map(|secs| Duration::from_secs(*secs))),
+        precision: matches.get_one::<usize>("PRECISION").copied(),
+        round_mode: matches
+            .get_one::<String>("ROUND_MODE")
+            .map(|mode| mode.parse().unwrap()),
+        fee: matches.get_one::<String>("FEE").cloned(),
+        timeout: matches.get_one::<Duration>("TIMEOUT").copied(),
+        no_history: matches.get_flag("NO_HISTORY"),
+    };
+    let settings = Settings::resolve(&flags, &config, |name| env::var(name).ok())
+        .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e));
+    let _ = PRUNE_POLICY.set(settings.prune.clone());
+    if let Some(path) = matches.get_one::<String>("CACHE_FILE") {
+        let _ = CACHE_FILE.set(PathBuf::from(path));
+    }
+
+    let proxy = matches.get_one::<String>("PROXY").map(String::as_str);
+    let mut api = ApiClient::new(proxy, settings.timeout)
+        .and_then(|api| api.provider(&settings.provider))
+        .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e));
+    if let Some(retries) = matches.get_one::<u32>("RETRIES") {
+        api = api.retries(*retries);
+    }
+    if let Some(api_key) = &settings.api_key {
+        api = api.api_key(api_key);
+    }
+    // `batch` has a `--strict` of its own, which hides the global one; there it also
+    // stops at the first line that fails.
+    let strict = match matches.subcommand() {
+        Some(("batch", sub_matches)) => sub_matches.get_flag("STRICT"),
+        _ => matches.get_flag("STRICT"),
+    };
+    api = api.exact(matches.get_flag("EXACT")).strict(strict);
+    // Pinned rates stand in for market rates in conversions only, not while watching one.
+    let converting = matches!(
+        matches.subcommand(),
+        None | Some(("interactive" | "batch", _))
+    );
+    if converting && !matches.contains_id("WATCH") {
+        api = api.overrides(
+            load_overrides(&overrides_path(cache_file()))
+                .unwrap_or_else(|e| fail(json, EXIT_FAILURE, e)),
+        );
+    }
+    let validate = !matches.get_flag("NO_VALIDATE");
+    let allow_zero = matches.get_flag("ALLOW_ZERO");
+    let default_base = settings
+        .aliases
+        .resolve(&settings.default_base)
+        .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e));
+    if validate {
+        if let Err(e) = validate_currency_code(&default_base) {
+            fail(
+                json,
+                e.exit_code(),
+                format!(
+                    "invalid default base currency from {}: {}",
+                    settings.default_base_source,
+                    error_message(&e)
+                ),
+            );
+        }
+    }
+    let output_format = OutputFormat {
+        precision: settings.precision,
+        round_mode: settings.round_mode,
+        with_names: matches.get_flag("WITH_NAMES"),
+        fee: settings.fee,
+        color: color_enabled(color_choice, std::io::stdout().is_terminal()),
+        numbers: match matches.get_one::<String>("LOCALE") {
+            Some(locale) => NumberFormat::for_locale(locale)
+                .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e)),
+            None => NumberFormat::from_env().unwrap_or_default(),
+        },
+    };
+    let max_age = settings.cache_ttl;
+    let offline = matches.get_flag("OFFLINE");
+    // Fail before any work rather than on the first request; the cache, history and pinned
+    // rates need no key, and `currencies` can ask a keyless provider or do without.
+    if !offline
+        && !matches!(
+            matches.subcommand(),
+            Some(("cache" | "currencies" | "history" | "quote" | "rate", _))
         )
-        .subcommand(
-            Command::new("list")
-                .about("Lists exchange rates for a base currency")
-                .arg(
-                    Arg::new("BASE_CURRENCY")
-                        .help("The base currency code")
-                        .default_value("PLN"),
+    {
+        if let Err(e) = api.require_api_key() {
+            fail(json, e.exit_code(), error_message(&e));
+        }
+    }
+    // --no-cache already skips every lookup, so together with --refresh it wins.
+    let no_cache = matches.get_flag("NO_CACHE");
+    let refresh = matches.get_flag("REFRESH") && !no_cache;
+    let date = match matches.get_one::<String>("DATE") {
+        Some(date) => match parse_date(date, chrono::Utc::now().date_naive()) {
+            Ok(date) => Some(date),
+            Err(e) => fail(json, EXIT_INVALID_INPUT, e),
+        },
+        None => None,
+    };
+
+    if csv {
+        if let Some((name, _)) = matches
+            .subcommand()
+            .filter(|(name, _)| !matches!(*name, "list" | "timeseries" | "batch"))
+        {
+            fail(
+                false,
+                EXIT_INVALID_INPUT,
+                format!("`{}` has no --output csv form", name),
+            );
+        }
+    }
+    // clap can't tell the values of --output apart, so what conflicts with one is checked here.
+    if let Some(format @ ("json" | "csv")) = output {
+        let clash = [
+            (matches.contains_id("RAW"), "--raw"),
+            (matches.contains_id("WATCH"), "--watch"),
+            (csv && matches.get_flag("INVERT"), "--invert"),
+        ]
+        .into_iter()
+        .find_map(|(given, flag)| given.then_some(flag));
+        if let Some(flag) = clash {
+            fail(
+                json,
+                EXIT_INVALID_INPUT,
+                format!("--output {} cannot be used with {}", format, flag),
+            );
+        }
+    }
+
+    let ctx = Context {
+        json,
+        csv,
+        csv_header,
+        settings,
+        api,
+        validate,
+        allow_zero,
+        default_base,
+        output_format,
+        max_age,
+        offline,
+        no_cache,
+        refresh,
+        date,
+        runtime: Runtime::new().unwrap(),
+    };
+    let status = match matches.subcommand() {
+        Some(("list", sub_matches)) => run_list(&ctx, sub_matches),
+        Some(("currencies", sub_matches)) => run_currencies(&ctx, sub_matches),
+        Some(("matrix", sub_matches)) => run_matrix(&ctx, sub_matches),
+        Some(("quote", sub_matches)) => run_quote(&ctx, sub_matches),
+        Some(("timeseries", sub_matches)) => run_timeseries(&ctx, sub_matches),
+        Some(("change", sub_matches)) => run_change(&ctx, sub_matches),
+        Some(("alert", sub_matches)) => run_alert(&ctx, sub_matches),
+        Some(("batch", sub_matches)) => run_batch(&ctx, sub_matches),
+        Some(("serve", sub_matches)) => run_serve(ctx, sub_matches),
+        Some(("interactive", _)) => run_interactive(&ctx),
+        Some(("rate", sub_matches)) => run_rate(&ctx, sub_matches),
+        Some(("history", sub_matches)) => run_history(&ctx, sub_matches),
+        Some(("cache", sub_matches)) => run_cache(&ctx, sub_matches),
+        _ => run_convert(&ctx, &matches),
+    };
+    if status != 0 {
+        std::process::exit(status);
+    }
+}
+
+/// `config`: writes a commented template to `path`.
+fn run_config(json: bool, path: &Path, sub_matches: &ArgMatches) -> i32 {
+    match sub_matches.subcommand() {
+        Some(("init", init_matches)) => {
+            if let Err(e) = write_config_template(path, init_matches.get_flag("FORCE")) {
+                fail(json, EXIT_FAILURE, e);
+            }
+            out!("Wrote {}", path.display());
+        }
+        _ => unreachable!("clap requires a config subcommand"),
+    }
+    0
+}
+
+/// `alias`: lists, sets and removes the `[aliases]` of the config file at `path`.
+fn run_alias(json: bool, path: &Path, sub_matches: &ArgMatches) -> i32 {
+    let (contents, mut aliases) =
+        load_aliases(path).unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e));
+    let save = |aliases: &Aliases| {
+        save_aliases(path, &contents, aliases).unwrap_or_else(|e| fail(json, EXIT_FAILURE, e))
+    };
+    match sub_matches.subcommand() {
+        Some(("list", _)) if json => out!("{}", aliases_json(&aliases)),
+        Some(("list", _)) if aliases.is_empty() => out!("No aliases"),
+        Some(("list", _)) => {
+            for line in alias_lines(&aliases) {
+                out!("{}", line);
+            }
+        }
+        Some(("set", set_matches)) => {
+            let name = set_matches.get_one::<String>("NAME").unwrap();
+            let target = set_matches.get_one::<String>("CODE").unwrap();
+            aliases
+                .set(name, target)
+                .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e));
+            save(&aliases);
+            let name = name.trim().to_lowercase();
+            out!(
+                "{} now stands for {}",
+                name,
+                aliases.resolve(&name).unwrap_or_default()
+            );
+        }
+        Some(("remove", remove_matches)) => {
+            let name = remove_matches.get_one::<String>("NAME").unwrap();
+            match aliases.remove(name) {
+                Ok(true) => {
+                    save(&aliases);
+                    out!("Removed the alias {}", name.trim().to_lowercase());
+                }
+                Ok(false) => out!("There is no alias {}", name.trim().to_lowercase()),
+                Err(e) => fail(
+                    json,
+                    EXIT_INVALID_INPUT,
+                    format!("cannot remove {}: {}", name.trim().to_lowercase(), e),
                 ),
-        );
+            }
+        }
+        _ => unreachable!("clap requires an alias subcommand"),
+    }
+    0
+}
 
-    let matches = app.get_matches();
+/// `list`: the rates against one or more bases.
+fn run_list(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context {
+        json,
+        csv,
+        csv_header,
+        ref settings,
+        ref api,
+        ref default_base,
+        ref output_format,
+        max_age,
+        offline,
+        no_cache,
+        refresh,
+        date,
+        ref runtime,
+        ..
+    } = ctx;
+    let base_currencies: Vec<String> = match sub_matches.get_many::<String>("BASE_CURRENCY") {
+        Some(bases) => bases.map(|base| ctx.code(base)).collect(),
+        None => vec![default_base.clone()],
+    };
 
-    if let Some(("list", sub_matches)) = matches.subcommand() {
-        let base_currency = sub_matches.get_one::<String>("BASE_CURRENCY").unwrap();
+    let only: Vec<String> = match sub_matches.get_many::<String>("ONLY") {
+        Some(codes) => codes.map(|code| ctx.resolve(code)).collect(),
+        None if sub_matches.get_flag("ALL") => Vec::new(),
+        None if sub_matches.get_flag("PAIRS") && settings.favorites.is_empty() => fail(
+            json,
+            EXIT_INVALID_INPUT,
+            "--pairs needs favorites in the config file, e.g. favorites = [\"USD\", \"EUR\"]",
+        ),
+        None => settings.favorites.clone(),
+    };
+    let exclude: Vec<String> = sub_matches
+        .get_many::<String>("EXCLUDE")
+        .unwrap_or_default()
+        .map(|code| ctx.resolve(code))
+        .collect();
+    let sort_key: Option<SortKey> = sub_matches
+        .get_one::<String>("SORT")
+        .map(|key| key.parse().unwrap());
+    let order: Option<SortOrder> = sub_matches
+        .get_one::<String>("ORDER")
+        .map(|order| order.parse().unwrap());
+    let top = sub_matches.get_one::<u64>("TOP").map(|top| *top as usize);
+    // `--only` alone keeps the order the codes were asked for. `--top` ranks the
+    // strongest first, which for rates against the base means the lowest.
+    let sort = match (sort_key, order) {
+        _ if top.is_some() => {
+            let by: SortKey = sub_matches
+                .get_one::<String>("BY")
+                .unwrap()
+                .parse()
+                .unwrap();
+            Some((by, order.unwrap_or(SortOrder::Asc)))
+        }
+        (None, None) if !only.is_empty() => None,
+        (key, order) => {
+            let key = key.unwrap_or_default();
+            Some((key, order.unwrap_or(key.default_order())))
+        }
+    };
+    // `--change` compares with the rates a day or a week before the listed ones.
+    let since = sub_matches.get_one::<String>("CHANGE").map(|period| {
+        let days = if period == "7d" { 7 } else { 1 };
+        date.unwrap_or_else(|| chrono::Utc::now().date_naive()) - chrono::Duration::days(days)
+    });
+    if sort_key == Some(SortKey::Change) && since.is_none() {
+        fail(
+            json,
+            EXIT_INVALID_INPUT,
+            "--sort change needs --change 24h or --change 7d",
+        );
+    }
+    let limit = sub_matches.get_one::<usize>("LIMIT").copied().unwrap_or(0);
+    let columns: Vec<ListColumn> = match sub_matches.get_many::<String>("COLUMNS") {
+        Some(columns) => columns.map(|column| column.parse().unwrap()).collect(),
+        None => LIST_COLUMNS.to_vec(),
+    };
+    let only_codes: Vec<&str> = only.iter().map(String::as_str).collect();
+    let exclude_codes: Vec<&str> = exclude.iter().map(String::as_str).collect();
+    // Filtering happens on the rates themselves, before any output format sees them.
+    let select = |base: &str, rates: HashMap<String, f64>| {
+        let mut rates = Rates::from_map(rates);
+        if !only.is_empty() {
+            let missing = rates.missing(&only_codes);
+            if !missing.is_empty() {
+                log::warn!("not found in {} rates: {}", base, missing.join(", "));
+            }
+            rates = rates.filtered(&only_codes);
+        }
+        rates.excluding(&exclude_codes)
+    };
+    // The rows of one base, their changes against its `previous` rates if there are
+    // any, and how many rows the limit hid.
+    let rows =
+        |base: &str, rates: HashMap<String, f64>, previous: Option<&HashMap<String, f64>>| {
+            let rates = select(base, rates);
+            let mut entries = list_entries(&rates, sort, &only);
+            let changes = previous.map(|previous| rate_changes(&entries, previous));
+            if let (Some((SortKey::Change, order)), Some(changes)) = (sort, &changes) {
+                sort_by_change(&mut entries, changes, order);
+            }
+            let hidden = limit_entries(&mut entries, top.unwrap_or(limit));
+            (entries, changes, hidden)
+        };
 
-        let runtime = tokio::runtime::Runtime::new().unwrap();
-        runtime.block_on(async {
-            match fetch_all_exchange_rates(base_currency).await {
-                Ok(api_response) => {
-                    println!("Exchange rates for {}:", base_currency);
-                    for (currency, rate) in api_response.rates.iter() {
-                        println!("{}: {}", currency, rate);
+    let table = std::io::stdout().is_terminal();
+    let color = output_format.color;
+    // Results read from the cache carry the age of the rates, offline or while fresh.
+    let mut cache = RunCache::load(no_cache, refresh);
+    let results: Vec<Result<ListedRates, CurrencyError>> = if offline {
+        base_currencies
+            .iter()
+            .map(|base| {
+                offline_rates(base, &cache, date).map(|item| (item.rates.clone(), Some(item.age())))
+            })
+            .collect()
+    } else {
+        // Rates on a past day never expire, so their age says nothing.
+        let cached_ages: Vec<Option<Duration>> = base_currencies
+            .iter()
+            .map(|base| {
+                cached_entry(&cache, base, None)
+                    .map(|item| item.age())
+                    .filter(|age| date.is_none() && *age < max_age)
+            })
+            .collect();
+        runtime
+            .block_on(api.fetch_many_base_rates(&base_currencies, &mut cache, max_age, date))
+            .into_iter()
+            .zip(cached_ages)
+            .map(|(result, age)| result.map(|rates| (rates, age)))
+            .collect()
+    };
+    // A failed comparison costs only the changes, never the rates themselves. Bases
+    // that couldn't be listed aren't compared.
+    let mut previous: HashMap<String, HashMap<String, f64>> = HashMap::new();
+    if let Some(since) = since {
+        let compared: Vec<String> = base_currencies
+            .iter()
+            .zip(&results)
+            .filter(|(_, result)| result.is_ok())
+            .map(|(base, _)| base.clone())
+            .collect();
+        let fetched: Vec<Result<HashMap<String, f64>, CurrencyError>> = if offline {
+            compared
+                .iter()
+                .map(|base| offline_rates(base, &cache, Some(since)).map(|item| item.rates.clone()))
+                .collect()
+        } else {
+            runtime.block_on(api.fetch_many_base_rates(&compared, &mut cache, max_age, Some(since)))
+        };
+        for (base, result) in compared.into_iter().zip(fetched) {
+            match result {
+                Ok(rates) => {
+                    previous.insert(base, rates);
+                }
+                Err(e) => log::warn!(
+                    "cannot compare the {} rates with {}: {}",
+                    base,
+                    since,
+                    error_message(&e)
+                ),
+            }
+        }
+    }
+    cache.save();
+    let mut status = 0;
+    let mut printed_table = false;
+    if csv && csv_header {
+        let mut header = RATES_CSV_HEADER.to_vec();
+        if since.is_some() {
+            header.push("change");
+        }
+        out!("{}", csv_record(&header));
+    }
+    let unknown = RateChanges::new();
+    for (base_currency, result) in base_currencies.iter().zip(results) {
+        let previous = previous.get(base_currency);
+        match result {
+            // One header covers every base, so the rows load as a single table.
+            Ok((rates, _)) if csv => {
+                let (entries, changes, _) = rows(base_currency, rates, previous);
+                let changes = changes.as_ref().or(since.map(|_| &unknown));
+                for row in rates_csv(base_currency, &entries, changes) {
+                    out!("{}", row);
+                }
+            }
+            Ok((rates, age)) if json => {
+                let (entries, changes, hidden) = rows(base_currency, rates, previous);
+                let mut document = rates_json(base_currency, &entries);
+                if hidden > 0 && top.is_none() {
+                    document["hidden"] = hidden.into();
+                }
+                if let Some(date) = date {
+                    document["date"] = date.to_string().into();
+                }
+                // Like the CSV column, the changes are kept as nulls when the comparison failed.
+                if let Some(since) = since {
+                    document["change_since"] = since.to_string().into();
+                    document["changes"] =
+                        changes_json(&entries, changes.as_ref().unwrap_or(&unknown));
+                }
+                if let Some(age) = age {
+                    document["age_seconds"] = age.as_secs().into();
+                }
+                out!("{}", document);
+            }
+            Ok((rates, age)) => {
+                if printed_table {
+                    out!();
+                }
+                printed_table = true;
+                let mut heading = format!("Exchange rates for {}", base_currency);
+                if let Some(date) = date {
+                    heading.push_str(&format!(" on {}", date));
+                }
+                let (entries, changes, hidden) = rows(base_currency, rates, previous);
+                if let (Some(since), Some(_)) = (since, &changes) {
+                    heading.push_str(&format!(", changes since {}", since));
+                }
+                if let Some(age) = age {
+                    heading.push_str(&format!(" (cached {} ago)", describe_age(age)));
+                }
+                out!("{}:", heading);
+                let lines = if table {
+                    rate_table(
+                        &entries,
+                        &columns,
+                        changes.as_ref(),
+                        terminal_width(),
+                        color,
+                    )
+                } else {
+                    rate_lines(&entries, output_format.with_names, changes.as_ref())
+                };
+                for line in lines {
+                    out!("{}", line);
+                }
+                // On a pipe the note goes to stderr, so the `CODE: rate` lines stay clean.
+                if hidden > 0 && top.is_none() {
+                    if table {
+                        out!("{}", hidden_line(hidden));
+                    } else {
+                        eprintln!("{}", hidden_line(hidden));
                     }
                 }
-                Err(e) => eprintln!("Error fetching exchange rates: {}", e),
             }
-        });
+            Err(e) => {
+                status = e.exit_code();
+                report_error(
+                    json,
+                    status,
+                    &format!("Error fetching exchange rates for {}", base_currency),
+                    error_message(&e),
+                );
+            }
+        }
+    }
+    status
+}
+
+/// `currencies`: the codes the provider knows, or the built-in ISO 4217 list.
+fn run_currencies(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context {
+        json,
+        ref api,
+        offline,
+        no_cache,
+        refresh,
+        ref runtime,
+        ..
+    } = ctx;
+    let mut cache = RunCache::load(no_cache, refresh);
+    // Offline, a list cached however long ago beats the built-in one.
+    let list = if offline {
+        cached_code_list(cache.loaded()).cloned()
     } else {
-        let from_currency = matches
-            .get_one::<String>("FROM_CURRENCY")
-            .expect("Source currency code is required")
-            .to_uppercase();
-        let to_currency = matches
-            .get_one::<String>("TO_CURRENCY")
-            .expect("Target currency code is required")
-            .to_uppercase();
-        let amount: f64 = matches
-            .get_one::<String>("AMOUNT")
-            .expect("Amount is required")
-            .parse()
-            .expect("Please type a number.");
-
-        let mut cache = load_cache().unwrap_or_else(|_| HashMap::new());
-        let runtime = tokio::runtime::Runtime::new().unwrap();
-        runtime.block_on(async {
-            match fetch_exchange_rate(&from_currency, &to_currency, &mut cache).await {
-                Ok(rate) => {
-                    let converted_amount = amount * rate;
-                    println!(
-                        "{} {} is {:.2} {} at an exchange rate of {:.2}",
-                        amount, from_currency, converted_amount, to_currency, rate
-                    );
-                }
-                Err(e) => eprintln!("Error fetching exchange rate: {}", e),
+        match runtime.block_on(api.fetch_code_list(&mut cache)) {
+            Ok(item) => Some(item),
+            Err(e) => {
+                log::warn!("{}; listing the built-in ISO 4217 codes", error_message(&e));
+                None
             }
-        });
-        save_cache(&cache).expect("Failed to save cache");
+        }
+    };
+    cache.save();
+    let (source, names) = match list {
+        Some(item) => (item.provider, item.names),
+        None => ("ISO 4217".to_string(), currencies::iso_names()),
+    };
+    let filter = sub_matches.get_one::<String>("FILTER").map(String::as_str);
+    let entries = currencies::matching(&names, filter.unwrap_or_default());
+    if json {
+        out!("{}", currencies_json(&source, &entries));
+    } else if entries.is_empty() {
+        out!("No currency matches '{}'", filter.unwrap_or_default());
+    } else {
+        for line in currency_lines(&entries) {
+            out!("{}", line);
+        }
+    }
+    if entries.is_empty() {
+        EXIT_FAILURE
+    } else {
+        0
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
+/// `matrix`: the cross rates between every pair of the given currencies.
+fn run_matrix(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context {
+        json,
+        ref api,
+        ref output_format,
+        max_age,
+        offline,
+        no_cache,
+        refresh,
+        date,
+        ref runtime,
+        ..
+    } = ctx;
+    let mut codes: Vec<String> = Vec::new();
+    for token in sub_matches.get_many::<String>("CURRENCIES").unwrap() {
+        let code = ctx.code(token);
+        if !codes.contains(&code) {
+            codes.push(code);
+        }
+    }
+    if codes.len() > MAX_MATRIX_CURRENCIES {
+        fail(
+            json,
+            EXIT_INVALID_INPUT,
+            format!(
+                "matrix takes at most {} currencies, got {}; each one costs a request",
+                MAX_MATRIX_CURRENCIES,
+                codes.len()
+            ),
+        );
+    }
+    let mut cache = RunCache::load(no_cache, refresh);
+    let results: Vec<Result<HashMap<String, f64>, CurrencyError>> = if offline {
+        codes
+            .iter()
+            .map(|base| offline_rates(base, &cache, date).map(|item| item.rates.clone()))
+            .collect()
+    } else {
+        runtime.block_on(api.fetch_many_base_rates(&codes, &mut cache, max_age, date))
+    };
+    cache.save();
+    if let (true, Some(Err(e))) = (results.iter().all(Result::is_err), results.first()) {
+        fail(json, e.exit_code(), error_message(e));
+    }
+    let rates: Vec<Option<&HashMap<String, f64>>> =
+        results.iter().map(|result| result.as_ref().ok()).collect();
+    let cells = rate_matrix(&codes, &rates);
+    // A failed base whose row was triangulated in full costs nothing; a gap does.
+    let mut status = 0;
+    for ((code, result), row) in codes.iter().zip(&results).zip(&cells) {
+        match result {
+            Err(e) if row.iter().any(Option::is_none) => {
+                log::warn!("cannot get {} rates: {}", code, error_message(e));
+                if status == 0 {
+                    status = e.exit_code();
+                }
+            }
+            Err(e) => log::debug!(
+                "no {} rates ({}), its row is derived from the others",
+                code,
+                error_message(e)
+            ),
+            Ok(_) => {}
+        }
+    }
+    if json {
+        let mut document = matrix_json(&codes, &cells);
+        if let Some(date) = date {
+            document = serde_json::json!({ "date": date.to_string(), "rates": document });
+        }
+        out!("{}", document);
+    } else {
+        if let Some(date) = date {
+            out!("Cross rates on {}:", date);
+        }
+        for line in matrix_table(&codes, &cells, output_format.color) {
+            out!("{}", line);
+        }
+    }
+    match status {
+        _ if cells.iter().flatten().all(Option::is_some) => 0,
+        0 => EXIT_FAILURE,
+        status => status,
+    }
+}
 
-    async fn fetch_mock_exchange_rate(
-        from: &str,
-        to: &str,
-    ) -> Result<f64, Box<dyn std::error::Error>> {
-        if from == "ERROR" || to == "ERROR" {
-            return Err("Network error or API limit reached".into());
+/// `quote`: one rate as each provider answers it.
+fn run_quote(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context {
+        json,
+        ref api,
+        ref output_format,
+        offline,
+        date,
+        ref runtime,
+        ..
+    } = ctx;
+    let [from_currency, to_currency] = ["FROM_CURRENCY", "TO_CURRENCY"]
+        .map(|id| ctx.code(sub_matches.get_one::<String>(id).unwrap()));
+    let amount = sub_matches
+        .get_one::<String>("AMOUNT")
+        .map(|amount| parse_amount(amount).unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e)));
+    if offline {
+        fail(
+            json,
+            EXIT_INVALID_INPUT,
+            "quote asks the providers themselves and cannot run --offline",
+        );
+    }
+    let timeout = match sub_matches.get_one::<Duration>("PROVIDER_TIMEOUT") {
+        Some(timeout) if timeout.is_zero() => fail(
+            json,
+            EXIT_INVALID_INPUT,
+            "--provider-timeout must be more than 0",
+        ),
+        Some(timeout) => *timeout,
+        None => DEFAULT_QUOTE_TIMEOUT,
+    };
+    let quotes = runtime.block_on(api.quote_providers(&from_currency, &to_currency, date, timeout));
+    // Failed providers only sink the comparison when none answered.
+    if let Some(e) = quotes
+        .iter()
+        .map(|quote| quote.rate.as_ref().err())
+        .collect::<Option<Vec<_>>>()
+        .and_then(|errors| errors.into_iter().next())
+    {
+        fail(
+            json,
+            e.exit_code(),
+            format!(
+                "no provider could quote {}/{}: {}",
+                from_currency,
+                to_currency,
+                error_message(e)
+            ),
+        );
+    }
+    if json {
+        let mut document = quote_json(&from_currency, &to_currency, amount, &quotes, output_format);
+        if let Some(date) = date {
+            document["date"] = date.to_string().into();
+        }
+        out!("{}", document);
+    } else {
+        let mut heading = format!("{}/{} across providers", from_currency, to_currency);
+        if let Some(date) = date {
+            heading.push_str(&format!(" on {}", date));
         }
-        if from == "INVALID" || to == "INVALID" {
-            return Err("Invalid currency code".into());
+        out!("{}:", heading);
+        for line in quote_table(&from_currency, &to_currency, amount, &quotes, output_format) {
+            out!("{}", line);
         }
+    }
+    0
+}
+
+/// `timeseries`: a rate over a range of days.
+fn run_timeseries(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context {
+        json,
+        csv,
+        csv_header,
+        ref api,
+        ref output_format,
+        offline,
+        no_cache,
+        refresh,
+        ref runtime,
+        ..
+    } = ctx;
+    let from_currency = ctx.code(sub_matches.get_one::<String>("FROM_CURRENCY").unwrap());
+    let to_currency = ctx.code(sub_matches.get_one::<String>("TO_CURRENCY").unwrap());
+    let today = chrono::Utc::now().date_naive();
+    let parse = |id: &str| {
+        sub_matches.get_one::<String>(id).map(|date| {
+            parse_date(date, today).unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e))
+        })
+    };
+    let start = parse("START_DATE").unwrap();
+    let end = parse("END_DATE").unwrap_or(today);
+    if start > end {
+        fail(
+            json,
+            EXIT_INVALID_INPUT,
+            format!("--from {} is after --to {}", start, end),
+        );
+    }
+    let interval: Interval = sub_matches
+        .get_one::<String>("INTERVAL")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let dates = series_dates(start, end, interval);
 
-        let rates = HashMap::from([
-            ("USD".to_string(), 1.0),
-            ("EUR".to_string(), 0.9),
-            ("PLN".to_string(), 4.0),
-        ]);
+    let mut cache = RunCache::load(no_cache, refresh);
+    let series = runtime.block_on(api.fetch_timeseries(
+        &from_currency,
+        &to_currency,
+        &dates,
+        &mut cache,
+        offline,
+    ));
+    cache.save();
 
-        let from_rate = rates
-            .get(from)
-            .ok_or("Rate not found for source currency")?;
-        let to_rate = rates.get(to).ok_or("Rate not found for target currency")?;
+    let mut status = 0;
+    let mut points = Vec::new();
+    for (date, result) in series {
+        match result {
+            Ok(rate) => points.push((date, rate)),
+            Err(e) => {
+                status = e.exit_code();
+                report_error(
+                    json,
+                    status,
+                    &format!("Error fetching rate for {}", date),
+                    error_message(&e),
+                );
+            }
+        }
+    }
+    // Charts are for reading at a terminal; pipes and narrow windows get the table.
+    let chart = sub_matches
+        .get_one::<String>("CHART")
+        .filter(|_| !json && !csv && std::io::stdout().is_terminal())
+        .and_then(|style| render_chart(&points, style.parse().unwrap(), terminal_width()));
+    if json {
+        let rates: serde_json::Map<String, serde_json::Value> = points
+            .iter()
+            .map(|(date, rate)| (date.to_string(), (*rate).into()))
+            .collect();
+        out!(
+            "{}",
+            serde_json::json!({ "from": from_currency, "to": to_currency, "rates": rates })
+        );
+    } else if csv {
+        if csv_header {
+            out!("{}", csv_record(&TIMESERIES_CSV_HEADER));
+        }
+        for row in timeseries_csv(&points) {
+            out!("{}", row);
+        }
+    } else if let Some(chart) = chart {
+        out!("{}", chart);
+    } else {
+        for (date, rate) in &points {
+            out!("{}  {}", date, format_rate(*rate, output_format.precision));
+        }
+    }
+    status
+}
 
-        Ok(to_rate / from_rate)
+/// `change`: how a rate moved between two days.
+fn run_change(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context {
+        json,
+        ref api,
+        ref output_format,
+        max_age,
+        offline,
+        no_cache,
+        refresh,
+        ref runtime,
+        ..
+    } = ctx;
+    let from_currency = ctx.code(sub_matches.get_one::<String>("FROM_CURRENCY").unwrap());
+    let to_currency = ctx.code(sub_matches.get_one::<String>("TO_CURRENCY").unwrap());
+    let today = chrono::Utc::now().date_naive();
+    let parse = |id: &str| {
+        sub_matches.get_one::<String>(id).map(|date| {
+            parse_date(date, today).unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e))
+        })
+    };
+    let start = parse("START_DATE").unwrap();
+    let end = parse("END_DATE").unwrap_or(today);
+    if start > end {
+        fail(
+            json,
+            EXIT_INVALID_INPUT,
+            format!("--from {} is after --to {}", start, end),
+        );
     }
 
-    async fn fetch_mock_all_exchange_rates(
-        base: &str,
-    ) -> Result<Rates, Box<dyn std::error::Error>> {
-        let mut rates = HashMap::new();
-        rates.insert("USD".to_string(), 1.0);
-        rates.insert("EUR".to_string(), 0.9);
-        rates.insert("PLN".to_string(), 4.0);
+    let mut cache = RunCache::load(no_cache, refresh);
+    let mut quote_on = |date: chrono::NaiveDate| {
+        let result = if offline {
+            offline_rate(&from_currency, &to_currency, &cache, Some(date))
+        } else {
+            runtime.block_on(api.fetch_exchange_rate(
+                &from_currency,
+                &to_currency,
+                &mut cache,
+                max_age,
+                Some(date),
+            ))
+        };
+        result.unwrap_or_else(|e| {
+            let code = e.exit_code();
+            report_error(
+                json,
+                code,
+                &format!("Error fetching rate for {}", date),
+                error_message(&e),
+            );
+            std::process::exit(code)
+        })
+    };
+    let start_quote = quote_on(start);
+    let end_quote = if end == start {
+        start_quote.clone()
+    } else {
+        quote_on(end)
+    };
+    cache.save();
 
-        if !rates.contains_key(base) {
-            return Err("Base currency not found".into());
+    let (from, to) = ((start, &start_quote), (end, &end_quote));
+    if json {
+        out!("{}", change_json(&from_currency, &to_currency, from, to));
+    } else {
+        for line in change_lines(&from_currency, &to_currency, from, to, output_format.color) {
+            out!("{}", line);
         }
+    }
+    0
+}
 
-        Ok(Rates { rates })
+/// `alert`: polls a rate until it crosses a threshold.
+fn run_alert(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context {
+        json,
+        ref api,
+        ref output_format,
+        offline,
+        no_cache,
+        refresh,
+        ref runtime,
+        ..
+    } = ctx;
+    let from_currency = ctx.code(sub_matches.get_one::<String>("FROM_CURRENCY").unwrap());
+    let to_currency = ctx.code(sub_matches.get_one::<String>("TO_CURRENCY").unwrap());
+    if offline {
+        fail(
+            json,
+            EXIT_INVALID_INPUT,
+            "alert needs live rates; drop --offline",
+        );
     }
+    let threshold = Threshold::new(
+        sub_matches.get_one::<f64>("BELOW").copied(),
+        sub_matches.get_one::<f64>("ABOVE").copied(),
+    )
+    .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e));
+    let interval = sub_matches.get_one::<u64>("INTERVAL").unwrap();
 
-    #[tokio::test]
-    async fn test_exchange_rate_conversion() {
-        let from_currency = "USD";
-        let to_currency = "EUR";
-        let amount = 1.0;
+    let mut cache = RunCache::load(no_cache, refresh);
+    let fired = runtime.block_on(alert_rate(
+        api,
+        &from_currency,
+        &to_currency,
+        &threshold,
+        Duration::from_secs(*interval),
+        output_format.color,
+        &mut cache,
+    ));
+    cache.save();
+    match (fired, sub_matches.get_one::<String>("EXEC")) {
+        (Ok(rate), Some(command)) => run_alert_command(command, rate),
+        (Ok(_), None) => 0,
+        (Err(status), _) => status,
+    }
+}
 
-        let rate = fetch_mock_exchange_rate(from_currency, to_currency)
-            .await
-            .unwrap();
-        let converted_amount = amount * rate;
+/// `batch`: converts the lines of a file or stdin.
+fn run_batch(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context {
+        json,
+        csv,
+        csv_header,
+        ref settings,
+        ref api,
+        validate,
+        allow_zero,
+        ref output_format,
+        max_age,
+        offline,
+        no_cache,
+        refresh,
+        ref runtime,
+        ..
+    } = ctx;
+    let path = sub_matches
+        .get_one::<String>("FILE")
+        .or(sub_matches.get_one::<String>("FILE_PATH"));
+    let contents = match path {
+        Some(path) => fs::read_to_string(path).map_err(|e| (path.as_str(), e)),
+        None => std::io::read_to_string(std::io::stdin()).map_err(|e| ("stdin", e)),
+    };
+    let contents = match contents {
+        Ok(contents) => contents,
+        Err((path, e)) => {
+            let context = format!("Error reading {}", path);
+            eprintln!("{}", error_line(&context, e, stderr_color()));
+            return EXIT_INVALID_INPUT;
+        }
+    };
+    let columns = parse_columns(sub_matches.get_one::<String>("COLUMNS").unwrap())
+        .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e));
+    let skip = usize::from(sub_matches.get_flag("HEADER"));
+    let strict = sub_matches.get_flag("STRICT");
 
-        assert_eq!(converted_amount, 0.9);
+    let mut cache = RunCache::load(no_cache, refresh);
+    let (mut converted, mut skipped) = (0, 0);
+    if csv && csv_header {
+        out!("{}", csv_record(&conversion_csv_header(output_format)));
     }
+    runtime.block_on(async {
+        // Each base is resolved once per batch, so rows sharing a source currency reuse one rate map.
+        let mut base_rates: HashMap<String, Result<HashMap<String, f64>, String>> = HashMap::new();
+        for (index, line) in contents.lines().enumerate().skip(skip) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            // The flag says whether the rate was pinned.
+            let result: Result<(BatchRow, f64, bool), String> = async {
+                let row = parse_batch_line_with(line, &columns)?.resolve(&settings.aliases)?;
+                check_nonzero(row.amount, allow_zero)?;
+                if validate {
+                    if let Some(e) = [&row.from, &row.to]
+                        .into_iter()
+                        .find_map(|code| validate_currency_code(code).err())
+                    {
+                        return Err(error_message(&e));
+                    }
+                }
+                if offline {
+                    let quote = api
+                        .offline_rate(&row.from, &row.to, &cache, None)
+                        .map_err(|e| error_message(&e))?;
+                    return Ok((row, quote.rate, quote.pinned));
+                }
+                if let Some(quote) = api.cached_rate(&row.from, &row.to, &cache, max_age, None) {
+                    return Ok((row, quote.rate, quote.pinned));
+                }
+                let base = provider_base().unwrap_or_else(|| row.from.clone());
+                if !base_rates.contains_key(&base) {
+                    let rates = api
+                        .fetch_base_rates(&base, &mut cache, max_age, None)
+                        .await
+                        .map_err(|e| error_message(&e));
+                    base_rates.insert(base.clone(), rates);
+                }
+                let rate = match &base_rates[&base] {
+                    Ok(rates) => {
+                        cross_rate(&base, rates, &row.from, &row.to).map_err(|e| e.to_string())?
+                    }
+                    Err(e) => return Err(e.clone()),
+                };
+                Ok((row, rate, false))
+            }
+            .await;
+            match result {
+                Ok((row, rate, _)) if csv => {
+                    out!(
+                        "{}",
+                        conversion_csv(row.amount, &row.from, &row.to, rate, output_format)
+                    );
+                    converted += 1;
+                }
+                Ok((row, rate, pinned)) => {
+                    let line = conversion_line(row.amount, &row.from, &row.to, rate, output_format);
+                    if pinned {
+                        out!("{} (pinned rate)", line);
+                    } else {
+                        out!("{}", line);
+                    }
+                    converted += 1;
+                }
+                // Problems go to stderr so the converted lines can be piped onward.
+                Err(e) => {
+                    let context = format!("line {}", index + 1);
+                    eprintln!("{}", error_line(&context, e, stderr_color()));
+                    skipped += 1;
+                    if strict {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    cache.save();
+    eprintln!("{} converted, {} skipped", converted, skipped);
+    if skipped > 0 {
+        EXIT_FAILURE
+    } else {
+        0
+    }
+}
 
-    #[tokio::test]
-    async fn test_cache_logic() {
-        let mut cache: HashMap<String, CacheItem> = HashMap::new();
+/// `serve`: answers conversions over HTTP until Ctrl-C.
+fn run_serve(ctx: Context, sub_matches: &ArgMatches) -> i32 {
+    let Context {
+        json,
+        settings,
+        api,
+        validate,
+        output_format,
+        max_age,
+        offline,
+        no_cache,
+        refresh,
+        runtime,
+        ..
+    } = ctx;
+    let port = sub_matches
+        .get_one::<u16>("PORT")
+        .copied()
+        .unwrap_or(DEFAULT_SERVE_PORT);
+    let listener = runtime
+        .block_on(tokio::net::TcpListener::bind(("127.0.0.1", port)))
+        .unwrap_or_else(|e| {
+            fail(
+                json,
+                EXIT_FAILURE,
+                format!("cannot listen on port {}: {}", port, e),
+            )
+        });
+    let cache = RunCache::load(no_cache, refresh);
+    let server = Server::new(api, cache.entries, max_age, output_format)
+        .cache_file((!no_cache).then(|| cache_file().to_path_buf()))
+        .offline(offline)
+        .validate(validate)
+        .prune(settings.prune.clone());
+    log::info!("listening on http://127.0.0.1:{} ({})", port, ENDPOINTS);
+    runtime.block_on(Arc::new(server).run(listener, async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::warn!("cannot wait for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    }));
+    0
+}
 
-        let from_currency = "USD";
-        let to_currency = "EUR";
-        let amount = 1.0;
+/// `interactive`: converts the lines typed at a prompt.
+fn run_interactive(ctx: &Context) -> i32 {
+    let &Context {
+        ref settings,
+        ref api,
+        validate,
+        allow_zero,
+        ref default_base,
+        ref output_format,
+        max_age,
+        offline,
+        no_cache,
+        refresh,
+        ref runtime,
+        ..
+    } = ctx;
+    let cache = RunCache::load(no_cache, refresh);
+    let session = Session {
+        api,
+        max_age,
+        validate,
+        offline,
+        output_format,
+        runtime,
+        history: settings.history,
+        allow_zero,
+        aliases: &settings.aliases,
+    };
+    session.run(cache, default_base.clone());
+    0
+}
 
-        let rate = fetch_mock_exchange_rate(from_currency, to_currency)
-            .await
-            .unwrap();
-        cache.insert(
-            from_currency.to_string(),
-            CacheItem {
-                rates: HashMap::from([(to_currency.to_string(), rate)]),
-                timestamp: SystemTime::now(),
+/// `rate`: pins, lists and unpins rates.
+fn run_rate(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context { json, .. } = ctx;
+    let path = overrides_path(cache_file());
+    let mut overrides = load_overrides(&path).unwrap_or_else(|e| fail(json, EXIT_FAILURE, e));
+    let today = chrono::Local::now().date_naive();
+    let pair = |pair_matches: &clap::ArgMatches| {
+        let [from, to] = ["FROM_CURRENCY", "TO_CURRENCY"]
+            .map(|id| ctx.code(pair_matches.get_one::<String>(id).unwrap()));
+        if from == to {
+            fail(
+                json,
+                EXIT_INVALID_INPUT,
+                format!("cannot pin {} against itself", from),
+            );
+        }
+        (from, to)
+    };
+    let save = |overrides: &RateOverrides| {
+        save_overrides(&path, overrides).unwrap_or_else(|e| fail(json, EXIT_FAILURE, e))
+    };
+    match sub_matches.subcommand() {
+        Some(("set", set_matches)) => {
+            let (from, to) = pair(set_matches);
+            let rate = RateOverride::parse(
+                set_matches.get_one::<String>("RATE").unwrap(),
+                set_matches.get_one::<String>("EXPIRES").map(String::as_str),
+                today,
+            )
+            .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e));
+            let until = match &rate.expires {
+                Some(expires) => format!(" until {}", expires),
+                None => String::new(),
+            };
+            out!(
+                "Pinned {}/{} at {}{}",
+                from,
+                to,
+                format_rate(rate.rate, 0),
+                until
+            );
+            overrides.set(&from, &to, rate);
+            save(&overrides);
+        }
+        Some(("list", _)) if json => out!("{}", overrides_json(&overrides, today)),
+        Some(("list", _)) if overrides.is_empty() => out!("No pinned rates"),
+        Some(("list", _)) => {
+            for line in override_lines(&overrides, today) {
+                out!("{}", line);
+            }
+        }
+        Some(("unset", unset_matches)) => {
+            let (from, to) = pair(unset_matches);
+            if overrides.unset(&from, &to) {
+                save(&overrides);
+                out!("Unpinned {}/{}", from, to);
+            } else {
+                out!("{}/{} is not pinned", from, to);
+            }
+        }
+        _ => unreachable!("clap requires a rate subcommand"),
+    }
+    0
+}
+
+/// `history`: lists or clears the recorded conversions.
+fn run_history(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context {
+        json, ref settings, ..
+    } = ctx;
+    let path = history_path();
+    if let Some(("clear", _)) = sub_matches.subcommand() {
+        match clear_history(&path) {
+            Ok(_) => out!("History cleared"),
+            Err(e) => fail(
+                json,
+                EXIT_FAILURE,
+                format!("cannot delete history file {}: {}", path.display(), e),
+            ),
+        }
+        return 0;
+    }
+    let filter = HistoryFilter {
+        last: sub_matches.get_one::<usize>("LAST").copied(),
+        pair: sub_matches.get_one::<String>("PAIR").map(|pair| {
+            parse_pair(pair, &settings.aliases)
+                .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e))
+        }),
+        since: sub_matches.get_one::<String>("SINCE").map(|date| {
+            parse_date(date, chrono::Utc::now().date_naive())
+                .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e))
+        }),
+    };
+    let records = read_history(&path).unwrap_or_else(|e| {
+        fail(
+            json,
+            EXIT_FAILURE,
+            format!("cannot read history file {}: {}", path.display(), e),
+        )
+    });
+    let records = filter.apply(records);
+    if json {
+        out!("{}", serde_json::json!(records));
+    } else if records.is_empty() {
+        out!("No conversions recorded");
+    } else {
+        for record in &records {
+            out!("{}", history_line(record));
+        }
+    }
+    0
+}
+
+/// `cache`: inspects and edits the cache file.
+fn run_cache(ctx: &Context, sub_matches: &ArgMatches) -> i32 {
+    let &Context {
+        json,
+        ref settings,
+        max_age,
+        ..
+    } = ctx;
+    let path = cache_file();
+    match sub_matches.subcommand() {
+        Some(("info", _)) => {
+            let stats = match cache_stats(path) {
+                Ok(stats) => stats,
+                Err(e) => fail(json, e.exit_code(), error_message(&e)),
+            };
+            let timestamp = |time: Option<SystemTime>| match time {
+                Some(time) => format!(
+                    "{} ({} ago)",
+                    chrono::DateTime::<chrono::Local>::from(time).format("%Y-%m-%d %H:%M:%S"),
+                    describe_age(time.elapsed().unwrap_or_default())
+                ),
+                None => "-".to_string(),
+            };
+            out!("Path: {}", stats.path.display());
+            out!("Size: {} bytes", stats.size);
+            out!(
+                "Base currencies: {} ({} entries)",
+                stats.bases,
+                stats.entries
+            );
+            out!("Oldest: {}", timestamp(stats.oldest));
+            out!("Newest: {}", timestamp(stats.newest));
+        }
+        Some(("show", show_matches)) => {
+            let cache = load_cache_at(path)
+                .unwrap_or_else(|e| fail(json, e.exit_code(), error_message(&e)));
+            let base = show_matches
+                .get_one::<String>("BASE_CURRENCY")
+                .map(|base| ctx.resolve(base));
+            let mut keys: Vec<&String> = cache
+                .iter()
+                .filter(|(_, item)| base.as_ref().is_none_or(|base| item.base == *base))
+                .map(|(key, _)| key)
+                .collect();
+            keys.sort();
+            if keys.is_empty() {
+                match &base {
+                    Some(base) => out!("{} is not cached", base),
+                    None => out!("Cache is empty"),
+                }
+            }
+            for key in keys {
+                let item = &cache[key];
+                let age = item.age().as_secs();
+                if item.is_code_list() {
+                    let fresh = age < CODE_LIST_TTL.as_secs();
+                    out!(
+                        "currency list ({}): {} codes, age {}s of {}s ({})",
+                        item.provider,
+                        item.names.len(),
+                        age,
+                        CODE_LIST_TTL.as_secs(),
+                        if fresh { "fresh" } else { "expired" }
+                    );
+                    continue;
+                }
+                let status = if item.date.is_some() || age < max_age.as_secs() {
+                    "fresh"
+                } else {
+                    "expired"
+                };
+                let mut day = item.date.clone().unwrap_or_else(|| "latest".to_string());
+                match &item.effective_date {
+                    Some(effective) if item.date.as_ref() != Some(effective) => {
+                        day.push_str(&format!(" as of {}", effective))
+                    }
+                    _ => {}
+                }
+                out!(
+                    "{} ({}, {}): {} rates, age {}s of {}s ({})",
+                    item.base,
+                    item.provider,
+                    day,
+                    item.rates.len(),
+                    age,
+                    max_age.as_secs(),
+                    status
+                );
+                let rates = Rates {
+                    provider: item.provider.clone(),
+                    effective_date: item.effective_date.clone(),
+                    ..Rates::from_map(item.rates.clone())
+                };
+                for (code, rate) in rates.sorted_entries(SortKey::Code, SortOrder::Asc) {
+                    out!("  {}: {}", code, rate);
+                }
+            }
+        }
+        Some(("clear", clear_matches)) => match clear_matches.get_one::<String>("BASE_CURRENCY") {
+            Some(base) => {
+                let base = ctx.resolve(base);
+                match remove_cached_base(path, &base) {
+                    Ok(0) => out!("{} is not cached", base),
+                    Ok(_) => out!("Removed {} from cache", base),
+                    Err(e) => fail(json, e.exit_code(), error_message(&e)),
+                }
+            }
+            None => match remove_cache_file(path) {
+                Ok(_) => out!("Cache cleared"),
+                Err(e) => fail(json, e.exit_code(), error_message(&e)),
             },
+        },
+        Some(("prune", _)) => {
+            let report = if path.exists() {
+                save_pruned_cache_to(path, &HashMap::new(), &settings.prune)
+                    .unwrap_or_else(|e| fail(json, e.exit_code(), error_message(&e)))
+            } else {
+                PruneReport::default()
+            };
+            if json {
+                out!(
+                    "{}",
+                    serde_json::json!({ "expired": report.expired, "evicted": report.evicted })
+                );
+            } else if report.is_empty() {
+                out!("Nothing to prune");
+            } else {
+                for (keys, reason) in [
+                    (&report.expired, "expired"),
+                    (&report.evicted, "over the size cap"),
+                ] {
+                    if !keys.is_empty() {
+                        out!("Removed {} {}: {}", keys.len(), reason, keys.join(", "));
+                    }
+                }
+            }
+        }
+        Some(("export", export_matches)) => {
+            let cache = load_cache_at(path)
+                .unwrap_or_else(|e| fail(json, e.exit_code(), error_message(&e)));
+            let only: Vec<String> = export_matches
+                .get_many::<String>("ONLY")
+                .unwrap_or_default()
+                .map(|code| ctx.resolve(code))
+                .collect();
+            let exported = export_cache(&cache, &only);
+            match export_matches.get_one::<String>("PATH") {
+                Some(target) => {
+                    if let Err(e) = fs::write(target, format!("{}\n", exported)) {
+                        fail(
+                            json,
+                            EXIT_FAILURE,
+                            format!("cannot write {}: {}", target, e),
+                        );
+                    }
+                    eprintln!("Exported the cache to {}", target);
+                }
+                None => out!("{}", exported),
+            }
+        }
+        Some(("import", import_matches)) => {
+            let source = import_matches.get_one::<String>("PATH").unwrap();
+            let (contents, source) = if source == "-" {
+                (std::io::read_to_string(std::io::stdin()), "stdin")
+            } else {
+                (fs::read_to_string(source), source.as_str())
+            };
+            let contents = contents.unwrap_or_else(|e| {
+                fail(
+                    json,
+                    EXIT_INVALID_INPUT,
+                    format!("cannot read {}: {}", source, e),
+                )
+            });
+            let imported = parse_cache_export(&contents, SystemTime::now())
+                .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, format!("{}: {}", source, e)));
+            let mode = if import_matches.get_flag("REPLACE") {
+                ImportMode::Replace
+            } else {
+                ImportMode::Merge
+            };
+            let summary = import_cache(path, imported, mode)
+                .unwrap_or_else(|e| fail(json, e.exit_code(), error_message(&e)));
+            if json {
+                out!(
+                    "{}",
+                    serde_json::json!({ "imported": summary.imported, "skipped": summary.skipped })
+                );
+            } else if summary.skipped > 0 {
+                out!(
+                    "Imported {} cache entries, skipped {} older than the cached ones",
+                    summary.imported,
+                    summary.skipped
+                );
+            } else {
+                out!("Imported {} cache entries", summary.imported);
+            }
+        }
+        _ => unreachable!("clap requires a cache subcommand"),
+    }
+    0
+}
+
+/// A conversion, with no subcommand or as `fav`.
+fn run_convert(ctx: &Context, matches: &ArgMatches) -> i32 {
+    let &Context {
+        json,
+        csv,
+        csv_header,
+        ref settings,
+        ref api,
+        validate,
+        allow_zero,
+        ref default_base,
+        ref output_format,
+        max_age,
+        offline,
+        no_cache,
+        refresh,
+        date,
+        ref runtime,
+        ..
+    } = ctx;
+    // `fav NAME AMOUNT` is the conversion of a pair saved in the config file.
+    let args: Vec<&str> = match matches.subcommand() {
+        Some(("fav", fav_matches)) => {
+            let name = fav_matches.get_one::<String>("NAME").unwrap();
+            let Some((from, to)) = settings.pairs.get(&name.trim().to_lowercase()) else {
+                let saved: Vec<&str> = settings.pairs.keys().map(String::as_str).collect();
+                fail(
+                    json,
+                    EXIT_INVALID_INPUT,
+                    if saved.is_empty() {
+                        format!(
+                            "no pair named '{}'; save one under [pairs] in the config file, e.g. trip = \"EUR/PLN\"",
+                            name
+                        )
+                    } else {
+                        format!("no pair named '{}' (saved: {})", name, saved.join(", "))
+                    },
+                );
+            };
+            vec![from, to, fav_matches.get_one::<String>("AMOUNT").unwrap()]
+        }
+        _ => matches
+            .get_many::<String>("CONVERSION")
+            .unwrap_or_default()
+            .map(String::as_str)
+            .collect(),
+    };
+    let given: Vec<Decimal> = matches
+        .get_many::<String>("AMOUNT")
+        .unwrap_or_default()
+        .map(|amount| parse_amount(amount).unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e)))
+        .collect();
+    let ConversionArgs {
+        from: from_currency,
+        to: to_currency,
+        amounts,
+        from_default,
+    } = parse_conversion_args_with(&args, Some(default_base), &given)
+        .unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e));
+    let mut from_currency = ctx.resolve(&from_currency);
+    let mut to_currency = to_currency
+        .split(',')
+        .map(|code| ctx.resolve(code))
+        .collect::<Vec<_>>()
+        .join(",");
+    let amounts: Vec<Decimal> = amounts
+        .into_iter()
+        .map(|amount| {
+            check_nonzero(amount, allow_zero).unwrap_or_else(|e| fail(json, EXIT_INVALID_INPUT, e))
+        })
+        .collect();
+    if amounts.len() > 1 && matches.contains_id("WATCH") {
+        fail(json, EXIT_INVALID_INPUT, "--watch takes a single amount");
+    }
+    let invert = matches.get_flag("INVERT");
+    if amounts.len() > 1 && invert {
+        fail(json, EXIT_INVALID_INPUT, "--invert takes a single amount");
+    }
+    if matches.get_flag("TO_BASE") {
+        if !from_default || to_currency.contains(',') {
+            fail(
+                json,
+                EXIT_INVALID_INPUT,
+                "--to-base needs a single currency and an amount, e.g. EUR 100 --to-base",
+            );
+        }
+        std::mem::swap(&mut from_currency, &mut to_currency);
+    }
+    let mut to_currencies: Vec<String> = to_currency.split(',').map(str::to_string).collect();
+    if to_currencies.iter().any(String::is_empty) {
+        fail(
+            json,
+            EXIT_INVALID_INPUT,
+            format!(
+                "invalid target currencies '{}', expected codes separated by commas",
+                to_currency
+            ),
         );
+    }
+    if to_currencies.len() > 1 && matches.contains_id("WATCH") {
+        fail(
+            json,
+            EXIT_INVALID_INPUT,
+            "--watch takes a single target currency",
+        );
+    }
+    // Offline answers may be arbitrarily old, so their age is always shown.
+    let show_age = matches.get_flag("SHOW_AGE") || offline;
+    // An unknown target only fails its own line; the others are still converted.
+    let mut status = 0;
+    let from_currency = ctx.code(&from_currency);
+    if validate {
+        to_currencies.retain(|code| match validate_currency_code(code) {
+            Ok(()) => true,
+            Err(e) => {
+                status = e.exit_code();
+                report_error(json, status, "Error", error_message(&e));
+                false
+            }
+        });
+        if to_currencies.is_empty() {
+            return status;
+        }
+    }
 
-        let cached_rate = fetch_exchange_rate(from_currency, to_currency, &mut cache)
-            .await
-            .unwrap();
-        let converted_amount = amount * cached_rate;
+    let mut cache = RunCache::load(no_cache, refresh);
+    if let Some(interval) = matches.get_one::<u64>("WATCH") {
+        runtime.block_on(watch_rate(
+            api,
+            &from_currency,
+            &to_currencies[0],
+            amounts[0],
+            output_format,
+            Duration::from_secs(*interval),
+            &mut cache,
+        ));
+        cache.save();
+        return 0;
+    }
+    let compare_cached = matches.get_flag("COMPARE_CACHED");
+    // The previous observations are read before the fresh fetch overwrites them.
+    let previous: Vec<Option<RateQuote>> = to_currencies
+        .iter()
+        .map(|to_currency| {
+            compare_cached
+                .then(|| {
+                    cached_rate(
+                        &from_currency,
+                        to_currency,
+                        cache.loaded(),
+                        Duration::MAX,
+                        None,
+                    )
+                })
+                .flatten()
+        })
+        .collect();
+    let max_age = if compare_cached {
+        Duration::ZERO
+    } else {
+        max_age
+    };
+    let targets: Vec<&str> = to_currencies.iter().map(String::as_str).collect();
+    let results: Vec<Result<RateQuote, CurrencyError>> = if offline {
+        targets
+            .iter()
+            .map(|to_currency| api.offline_rate(&from_currency, to_currency, &cache, date))
+            .collect()
+    } else {
+        // All targets share one fetch, so when it fails there is a single error to report.
+        match runtime.block_on(api.fetch_exchange_rates(
+            &from_currency,
+            &targets,
+            &mut cache,
+            max_age,
+            date,
+        )) {
+            Ok(quotes) => quotes
+                .into_iter()
+                .map(|quote| quote.map_err(CurrencyError::from))
+                .collect(),
+            Err(e) => {
+                status = e.exit_code();
+                report_error(
+                    json,
+                    status,
+                    "Error fetching exchange rate",
+                    error_message(&e),
+                );
+                Vec::new()
+            }
+        }
+    };
+    let show_inverse = !matches.get_flag("NO_INVERSE");
+    let raw: Option<RawValue> = matches
+        .get_one::<String>("RAW")
+        .map(|value| value.parse().unwrap());
+    let mut history = Vec::new();
+    if csv && csv_header {
+        out!("{}", csv_record(&conversion_csv_header(output_format)));
+    }
+    for ((to_currency, result), previous) in to_currencies.iter().zip(results).zip(&previous) {
+        // Under --invert the single amount is the target, and the source amount is solved for.
+        let required = match &result {
+            Ok(quote) if invert => match required_amount(
+                amounts[0],
+                quote.rate,
+                output_format.fee,
+                amount_precision(&from_currency, output_format.precision),
+                output_format.round_mode,
+            ) {
+                Ok(required) => Some(required),
+                Err(e) => {
+                    status = EXIT_FAILURE;
+                    report_error(json, status, "Error", e);
+                    continue;
+                }
+            },
+            _ => None,
+        };
+        if let Ok(quote) = &result {
+            history.extend(amounts.iter().map(|amount| {
+                // What converting the amount needed, fee included, would have recorded.
+                let amount = required
+                    .as_ref()
+                    .map_or(*amount, |required| required.with_fee);
+                HistoryRecord::new(amount, &from_currency, to_currency, quote, output_format)
+            }));
+        }
+        match (result, raw) {
+            (Ok(quote), Some(raw)) if invert => {
+                match raw_required_line(amounts[0], quote.rate, output_format.fee, raw) {
+                    Ok(line) => out!("{}", line),
+                    Err(e) => {
+                        status = EXIT_FAILURE;
+                        report_error(json, status, "Error", e);
+                    }
+                }
+            }
+            (Ok(quote), Some(raw)) => {
+                for amount in &amounts {
+                    out!("{}", raw_line(*amount, quote.rate, output_format.fee, raw));
+                }
+            }
+            (Ok(quote), None) if csv => {
+                for amount in &amounts {
+                    out!(
+                        "{}",
+                        conversion_csv(
+                            *amount,
+                            &from_currency,
+                            to_currency,
+                            quote.rate,
+                            output_format
+                        )
+                    );
+                }
+            }
+            (Ok(quote), None) if json => {
+                let mut documents: Vec<serde_json::Value> = amounts
+                    .iter()
+                    .map(|amount| match &required {
+                        Some(required) => required_json(
+                            *amount,
+                            required,
+                            &from_currency,
+                            to_currency,
+                            &quote,
+                            output_format,
+                        ),
+                        None => conversion_json(
+                            *amount,
+                            &from_currency,
+                            to_currency,
+                            &quote,
+                            output_format,
+                        ),
+                    })
+                    .collect();
+                let fallback = !quote.pinned && quote.provider != api.primary_provider();
+                for document in &mut documents {
+                    if let Some(date) = date {
+                        document["date"] = date.to_string().into();
+                    }
+                    if show_age || quote.stale || fallback {
+                        document["age_seconds"] = quote.age.as_secs().into();
+                    }
+                    if compare_cached {
+                        document["change_percent"] = rate_change(&quote, previous.as_ref()).into();
+                    }
+                }
+                // Several amounts make one array, so a script reads a single document.
+                if documents.len() == 1 {
+                    out!("{}", documents[0]);
+                } else {
+                    out!("{}", serde_json::Value::from(documents));
+                }
+            }
+            (Ok(quote), None) => {
+                let line = match (amounts.as_slice(), &required) {
+                    ([amount], Some(required)) => required_line(
+                        *amount,
+                        required,
+                        &from_currency,
+                        to_currency,
+                        quote.rate,
+                        output_format,
+                    ),
+                    ([amount], None) => conversion_line(
+                        *amount,
+                        &from_currency,
+                        to_currency,
+                        quote.rate,
+                        output_format,
+                    ),
+                    _ => {
+                        let mut lines = amount_table(
+                            &amounts,
+                            &from_currency,
+                            to_currency,
+                            quote.rate,
+                            output_format,
+                        );
+                        // The rate line beneath the table takes the date and source.
+                        let footer = lines.pop().unwrap_or_default();
+                        for line in lines {
+                            out!("{}", line);
+                        }
+                        footer
+                    }
+                };
+                let line = match date {
+                    Some(date) => format!("{} on {}", line, date),
+                    None => line,
+                };
+                // A pinned rate, one served by a fallback provider, one derived through
+                // another base or one from an earlier day, as on weekends, is always
+                // attributed.
+                if show_age
+                    || quote.predates(date.unwrap_or_else(|| chrono::Utc::now().date_naive()))
+                    || quote.pinned
+                    || quote.stale
+                    || quote.derived_via.is_some()
+                    || quote.provider != api.primary_provider()
+                {
+                    out!("{} ({})", line, describe_source(&quote));
+                } else {
+                    out!("{}", line);
+                }
+                if show_inverse {
+                    match inverse_line(&from_currency, to_currency, quote.rate, output_format) {
+                        Some(line) => out!("{}", line),
+                        None => log::warn!(
+                            "not showing the inverse of the {}->{} rate {}",
+                            from_currency,
+                            to_currency,
+                            quote.rate
+                        ),
+                    }
+                }
+                if compare_cached {
+                    out!(
+                        "{}",
+                        comparison_line(
+                            &from_currency,
+                            to_currency,
+                            &quote,
+                            previous.as_ref(),
+                            output_format.color
+                        )
+                    );
+                }
+            }
+            (Err(e), _) => {
+                status = e.exit_code();
+                report_error(
+                    json,
+                    status,
+                    "Error fetching exchange rate",
+                    error_message(&e),
+                );
+            }
+        }
+    }
+    cache.save();
+    if settings.history {
+        record_history(&history);
+    }
+    status
+}
+
+/// Prints `error` to stderr after `context`, or under `--json` as an object whose `kind`
+/// tells scripts what failed.
+fn report_error(json: bool, code: i32, context: &str, error: impl Display) {
+    if json {
+        eprintln!("{}", error_json(code, &error.to_string()));
+    } else {
+        eprintln!("{}", error_line(context, error, stderr_color()));
+    }
+}
+
+/// Prints `error` to stderr as a plain, non-`--json` error.
+fn print_error(error: impl Display) {
+    eprintln!("{}", error_line("Error", error, stderr_color()));
+}
+
+/// Appends `records` to the history file, warning rather than failing when it can't be
+/// written.
+fn record_history(records: &[HistoryRecord]) {
+    if records.is_empty() {
+        return;
+    }
+    let path = history_path();
+    if let Err(e) = append_history(&path, records) {
+        log::warn!("cannot write history file {}: {}", path.display(), e);
+    }
+}
+
+fn stderr_color() -> bool {
+    STDERR_COLOR.get().copied().unwrap_or(false)
+}
+
+fn cache_file() -> &'static Path {
+    CACHE_FILE.get_or_init(cache_path)
+}
+
+/// Columns of the terminal as the shell exports them in `COLUMNS`, or 80.
+fn terminal_width() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(80)
+}
 
-        assert_eq!(converted_amount, 0.9);
+/// The user-facing message for `error`, with a hint on what to do about the common ones.
+fn error_message(error: &CurrencyError) -> String {
+    match error {
+        CurrencyError::MissingApiKey => "no API key for exchangerate-api; pass --api-key KEY, \
+            set API_KEY in the environment or .env, or add api_key to the config file \
+            (open-er-api and frankfurter need none, see --provider)"
+            .to_string(),
+        CurrencyError::InvalidApiKey => "the API key was rejected by exchangerate-api; check \
+            --api-key, API_KEY or api_key in the config file"
+            .to_string(),
+        CurrencyError::RateLimited => {
+            "API request limit exceeded; try again later or use --offline for cached rates"
+                .to_string()
+        }
+        CurrencyError::TooManyRequests(Some(wait)) => {
+            let at = chrono::Local::now() + *wait;
+            format!(
+                "rate limited by the provider; try again in {} (at {})",
+                describe_age(*wait),
+                at.format("%H:%M:%S")
+            )
+        }
+        CurrencyError::Timeout(_) => format!("{}; use --timeout to wait longer", error),
+        CurrencyError::StaleRate { error, age } => format!(
+            "{}; --strict refuses the cached rate from {} ago",
+            error_message(error),
+            describe_age(*age)
+        ),
+        CurrencyError::UnknownCurrency(_) => {
+            format!("{} (--allow-unknown accepts codes outside ISO 4217)", error)
+        }
+        CurrencyError::Http(e) if e.is_connect() || e.is_timeout() => {
+            format!("could not reach the exchange-rate API: {}", e)
+        }
+        CurrencyError::Cache(e) => {
+            format!(
+                "cannot use the cache file {}: {}",
+                cache_file().display(),
+                e
+            )
+        }
+        error => error.to_string(),
+    }
+}
+
+fn fail(json: bool, code: i32, error: impl Display) -> ! {
+    report_error(json, code, "Error", error);
+    std::process::exit(code);
+}
+
+/// Settings shared by every line of an `interactive` session.
+struct Session<'a> {
+    api: &'a ApiClient,
+    max_age: Duration,
+    validate: bool,
+    offline: bool,
+    output_format: &'a OutputFormat,
+    runtime: &'a Runtime,
+    /// Conversions are recorded for `history`.
+    history: bool,
+    /// `--allow-zero`.
+    allow_zero: bool,
+    /// The `[aliases]` of the config file, read like codes.
+    aliases: &'a Aliases,
+}
+
+impl Session<'_> {
+    /// Reads and answers lines until `quit` or end of input. A failing line is reported and
+    /// the session carries on; the cache is only written back when it ends.
+    fn run(&self, mut cache: RunCache, default_base: String) {
+        let mut editor = match rustyline::DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(e) => fail(false, EXIT_FAILURE, e),
+        };
+        out!("Type a conversion such as `100 USD EUR`, or help.");
+        // The previous conversion, repeated by a bare amount and reversed by `swap`.
+        let mut last: Option<(Decimal, String, String)> = None;
+        loop {
+            let line = match editor.readline("> ") {
+                Ok(line) => line,
+                // Ctrl-C abandons the line being typed, as in a shell.
+                Err(rustyline::error::ReadlineError::Interrupted) => continue,
+                Err(rustyline::error::ReadlineError::Eof) => break,
+                Err(e) => {
+                    print_error(e);
+                    break;
+                }
+            };
+            let _ = editor.add_history_entry(line.as_str());
+            let command =
+                match parse_repl_line(&line).and_then(|command| command.resolve(self.aliases)) {
+                    Ok(command) => command,
+                    Err(e) => {
+                        print_error(e);
+                        continue;
+                    }
+                };
+            let conversion = match command {
+                ReplCommand::Empty => continue,
+                ReplCommand::Quit => break,
+                ReplCommand::Help => {
+                    out!("{}", REPL_HELP);
+                    continue;
+                }
+                ReplCommand::List(base) => {
+                    let base = base
+                        .or_else(|| last.as_ref().map(|(_, from, _)| from.clone()))
+                        .unwrap_or_else(|| default_base.clone());
+                    self.list(&base, &mut cache);
+                    continue;
+                }
+                ReplCommand::Convert { amount, from, to } => (amount, from, to),
+                ReplCommand::Amount(amount) => match &last {
+                    Some((_, from, to)) => (amount, from.clone(), to.clone()),
+                    None => {
+                        print_error("no previous conversion; type e.g. `100 USD EUR` first");
+                        continue;
+                    }
+                },
+                ReplCommand::Swap => match &last {
+                    Some((amount, from, to)) => (*amount, to.clone(), from.clone()),
+                    None => {
+                        print_error("no previous conversion to swap");
+                        continue;
+                    }
+                },
+            };
+            if self.convert(&conversion, &mut cache) {
+                last = Some(conversion);
+            }
+        }
+        cache.save();
     }
 
-    #[tokio::test]
-    async fn test_listing_exchange_rates() {
-        let base_currency = "USD";
-        let response = fetch_mock_all_exchange_rates(base_currency).await.unwrap();
+    /// Prints one conversion, returning whether it succeeded.
+    fn convert(
+        &self,
+        (amount, from, to): &(Decimal, String, String),
+        cache: &mut RunCache,
+    ) -> bool {
+        if let Err(e) = check_nonzero(*amount, self.allow_zero) {
+            print_error(e);
+            return false;
+        }
+        if self.validate {
+            if let Some(e) = [from, to]
+                .into_iter()
+                .find_map(|code| validate_currency_code(code).err())
+            {
+                print_error(error_message(&e));
+                return false;
+            }
+        }
+        let result = if self.offline {
+            self.api.offline_rate(from, to, cache, None)
+        } else {
+            self.runtime.block_on(
+                self.api
+                    .fetch_exchange_rate(from, to, cache, self.max_age, None),
+            )
+        };
+        match result {
+            Ok(quote) => {
+                let line = conversion_line(*amount, from, to, quote.rate, self.output_format);
+                if self.offline
+                    || quote.predates(chrono::Utc::now().date_naive())
+                    || quote.pinned
+                    || quote.stale
+                    || quote.derived_via.is_some()
+                    || quote.provider != self.api.primary_provider()
+                {
+                    out!("{} ({})", line, describe_source(&quote));
+                } else {
+                    out!("{}", line);
+                }
+                if self.history {
+                    record_history(&[HistoryRecord::new(
+                        *amount,
+                        from,
+                        to,
+                        &quote,
+                        self.output_format,
+                    )]);
+                }
+                true
+            }
+            Err(e) => {
+                print_error(error_message(&e));
+                false
+            }
+        }
+    }
 
-        assert_eq!(response.rates.len(), 3);
-        assert_eq!(response.rates.get("EUR"), Some(&0.9));
-        assert_eq!(response.rates.get("PLN"), Some(&4.0));
-        assert_eq!(response.rates.get("USD"), Some(&1.0));
+    fn list(&self, base: &str, cache: &mut RunCache) {
+        if self.validate {
+            if let Err(e) = validate_currency_code(base) {
+                print_error(error_message(&e));
+                return;
+            }
+        }
+        let rates = if self.offline {
+            offline_rates(base, cache, None).map(|item| item.rates.clone())
+        } else {
+            self.runtime
+                .block_on(self.api.fetch_base_rates(base, cache, self.max_age, None))
+        };
+        match rates {
+            Ok(rates) => {
+                out!("Exchange rates for {}:", base);
+                let rates = Rates::from_map(rates);
+                let entries = rates.sorted_entries(SortKey::Code, SortOrder::Asc);
+                for line in rate_lines(&entries, self.output_format.with_names, None) {
+                    out!("{}", line);
+                }
+            }
+            Err(e) => print_error(error_message(&e)),
+        }
     }
+}
+
+/// Polls the live `from`->`to` rate every `interval` until it crosses `threshold`, printing
+/// each check, then prints the alert and returns the rate. Failures that may pass, such as
+/// a timeout or a stale answer, are retried on the next tick. Otherwise the exit status is
+/// returned: the error's own for a failure that retrying won't fix, [`EXIT_FAILURE`] when
+/// stopped with Ctrl-C.
+async fn alert_rate(
+    api: &ApiClient,
+    from: &str,
+    to: &str,
+    threshold: &Threshold,
+    interval: Duration,
+    color: bool,
+    cache: &mut HashMap<String, CacheItem>,
+) -> Result<f64, i32> {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = tokio::signal::ctrl_c() => {
+                out!("Stopped waiting for {}/{} {}", from, to, threshold);
+                return Err(EXIT_FAILURE);
+            }
+        }
+
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let quote = match api
+            .fetch_exchange_rate(from, to, cache, Duration::ZERO, None)
+            .await
+        {
+            Ok(quote) if !quote.stale => quote,
+            // A stale quote means the fetch failed and the cache answered; it can't fire the alert.
+            Ok(_) => {
+                eprintln!(
+                    "[{}] no fresh rate, retrying in {}s",
+                    now,
+                    interval.as_secs()
+                );
+                continue;
+            }
+            Err(e)
+                if is_transient(&e)
+                    || matches!(
+                        e,
+                        CurrencyError::RateLimited | CurrencyError::TooManyRequests(_)
+                    ) =>
+            {
+                let context = format!("[{}] Error fetching exchange rate", now);
+                let retry = format!("{}; retrying in {}s", error_message(&e), interval.as_secs());
+                eprintln!("{}", error_line(&context, retry, stderr_color()));
+                continue;
+            }
+            Err(e) => {
+                report_error(
+                    false,
+                    e.exit_code(),
+                    "Error fetching exchange rate",
+                    error_message(&e),
+                );
+                return Err(e.exit_code());
+            }
+        };
 
-    #[tokio::test]
-    async fn test_network_error_handling() {
-        let result = fetch_mock_exchange_rate("ERROR", "EUR").await;
-        assert!(result.is_err());
+        out!(
+            "{}",
+            alert_status_line(&now, from, to, quote.rate, threshold)
+        );
+        let Some(crossed) = threshold.crossed(quote.rate) else {
+            continue;
+        };
+        out!("{}", alert_line(from, to, quote.rate, &crossed, color));
+        return Ok(quote.rate);
     }
+}
 
-    #[tokio::test]
-    async fn test_invalid_currency_code_handling() {
-        let result = fetch_mock_exchange_rate("INVALID", "EUR").await;
-        assert!(result.is_err());
+/// Runs the `--exec` command of a fired alert through the shell, with `{rate}` replaced by
+/// `rate`, returning the exit status for the run: 0, or [`EXIT_FAILURE`] if it failed.
+fn run_alert_command(command: &str, rate: f64) -> i32 {
+    let command = command.replace("{rate}", &rate.to_string());
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    match std::process::Command::new(shell)
+        .args([flag, &command])
+        .status()
+    {
+        Ok(status) if status.success() => 0,
+        Ok(status) => {
+            print_error(format!("--exec command failed ({})", status));
+            EXIT_FAILURE
+        }
+        Err(e) => {
+            print_error(format!("could not run the --exec command: {}", e));
+            EXIT_FAILURE
+        }
     }
+}
 
-    #[tokio::test]
-    async fn test_rate_not_found_handling() {
-        let result = fetch_mock_exchange_rate("USD", "INVALID").await;
-        assert!(result.is_err());
+/// Polls the live rate every `interval` until Ctrl-C, showing the converted amount, its
+/// change from the previous fetch and when it was fetched. On a terminal the line is
+/// rewritten in place; otherwise each observation gets its own line. A failed fetch keeps
+/// the last good value on screen, marked as stale.
+async fn watch_rate(
+    api: &ApiClient,
+    from: &str,
+    to: &str,
+    amount: Decimal,
+    output_format: &OutputFormat,
+    interval: Duration,
+    cache: &mut HashMap<String, CacheItem>,
+) {
+    let in_place = std::io::stdout().is_terminal();
+    let decimals = output_format.precision.max(4);
+    let mut ticker = tokio::time::interval(interval);
+    // The last good observation: its line, rate, fetch time and change from the one before.
+    let mut last: Option<(String, f64, String, Option<f64>)> = None;
+    let mut stale_since: Option<String> = None;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = tokio::signal::ctrl_c() => {
+                if in_place && last.is_some() {
+                    out!();
+                }
+                out!("Stopped watching {}->{}", from, to);
+                return;
+            }
+        }
+
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        match api
+            .fetch_exchange_rate(from, to, cache, Duration::ZERO, None)
+            .await
+        {
+            // A stale quote is the cache answering for a failed fetch: news only on the first tick.
+            Ok(quote) if !quote.stale || last.is_none() => {
+                let line = conversion_line(amount, from, to, quote.rate, output_format);
+                let delta = last.as_ref().map(|(_, rate, _, _)| quote.rate - rate);
+                let fetched_at = (chrono::Local::now() - quote.age).format("%Y-%m-%d %H:%M:%S");
+                last = Some((line, quote.rate, fetched_at.to_string(), delta));
+                stale_since = quote.stale.then_some(now);
+            }
+            Ok(_) => {
+                stale_since.get_or_insert(now);
+            }
+            Err(e) if last.is_none() => {
+                eprintln!(
+                    "[{}] {}",
+                    now,
+                    error_line(
+                        "Error fetching exchange rate",
+                        error_message(&e),
+                        stderr_color()
+                    )
+                );
+                continue;
+            }
+            Err(e) => {
+                log::debug!("watch fetch failed: {}", error_message(&e));
+                stale_since.get_or_insert(now);
+            }
+        }
+
+        let Some((line, _, fetched_at, delta)) = &last else {
+            continue;
+        };
+        let line = watch_line(
+            line,
+            fetched_at,
+            *delta,
+            decimals,
+            stale_since.as_deref(),
+            output_format.color,
+        );
+        if in_place {
+            write_stdout(format_args!("\r\x1b[2K{}", line));
+        } else {
+            out!("{}", line);
+        }
     }
 }
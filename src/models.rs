@@ -1,14 +1,746 @@
+use crate::config::EARLIEST_HISTORICAL_DATE;
+use crate::currencies::CURRENCIES;
+use crate::expr;
+use crate::numbers::strip_separators;
+use chrono::{DateTime, Days, Months, NaiveDate};
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::time::SystemTime;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, SystemTime};
+use std::{error::Error, fmt, str::FromStr};
 
+/// Rates for one base currency, keyed by currency code.
 #[derive(Serialize, Deserialize)]
 pub struct Rates {
     pub rates: HashMap<String, f64>,
+    /// Name of the provider that served the rates; empty until the fetch fills it in.
+    #[serde(default)]
+    pub provider: String,
+    /// Day the provider says the rates are from, if it says. For a weekend or holiday this
+    /// can be the business day before the one asked for.
+    #[serde(default)]
+    pub effective_date: Option<String>,
+    /// Currency the provider says the rates are quoted against, if it says.
+    #[serde(default)]
+    pub base: Option<String>,
+    /// When the provider last updated the rates, in Unix seconds, if it says.
+    #[serde(default)]
+    pub time_last_updated: Option<i64>,
 }
 
+impl Rates {
+    /// Rates with nothing known about where they came from, for tables built from a cached
+    /// or fetched map.
+    pub fn from_map(rates: HashMap<String, f64>) -> Rates {
+        Rates {
+            rates,
+            provider: String::new(),
+            effective_date: None,
+            base: None,
+            time_last_updated: None,
+        }
+    }
+
+    /// Just the rates of `codes`, for `list --only`. Codes without a rate are left out; see
+    /// [`Rates::missing`].
+    pub fn filtered(&self, codes: &[&str]) -> Rates {
+        self.retained(|code| codes.contains(&code))
+    }
+
+    /// The rates of every code except `codes`, for `list --exclude`.
+    pub fn excluding(&self, codes: &[&str]) -> Rates {
+        self.retained(|code| !codes.contains(&code))
+    }
+
+    /// Which of `codes` have no rate here, in the order given.
+    pub fn missing<'a>(&self, codes: &[&'a str]) -> Vec<&'a str> {
+        codes
+            .iter()
+            .copied()
+            .filter(|code| !self.rates.contains_key(*code))
+            .collect()
+    }
+
+    /// Every rate, sorted by `sort` in `order`. Rates that tie keep code order, and a NaN
+    /// rate (which no provider should send) goes last in either order.
+    pub fn sorted_entries(&self, sort: SortKey, order: SortOrder) -> Vec<(String, f64)> {
+        let mut entries: Vec<(String, f64)> = self
+            .rates
+            .iter()
+            .map(|(code, rate)| (code.clone(), *rate))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        match (sort, order) {
+            (SortKey::Code, SortOrder::Asc) => {}
+            (SortKey::Code, SortOrder::Desc) => entries.reverse(),
+            // Rates alone don't say how they moved: see `output::sort_by_change`.
+            (SortKey::Change, _) => {}
+            (SortKey::Rate, _) => entries.sort_by(|a, b| match (a.1.is_nan(), b.1.is_nan()) {
+                (false, false) => {
+                    // partial_cmp, unlike total_cmp, keeps 0.0 and -0.0 tied.
+                    let ordering = a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal);
+                    match order {
+                        SortOrder::Asc => ordering,
+                        SortOrder::Desc => ordering.reverse(),
+                    }
+                }
+                (nan_a, nan_b) => nan_a.cmp(&nan_b),
+            }),
+        }
+        entries
+    }
+
+    fn retained(&self, keep: impl Fn(&str) -> bool) -> Rates {
+        Rates {
+            rates: self
+                .rates
+                .iter()
+                .filter(|(code, _)| keep(code))
+                .map(|(code, rate)| (code.clone(), *rate))
+                .collect(),
+            provider: self.provider.clone(),
+            effective_date: self.effective_date.clone(),
+            base: self.base.clone(),
+            time_last_updated: self.time_last_updated,
+        }
+    }
+}
+
+/// What `list` sorts its rows by.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SortKey {
+    #[default]
+    Code,
+    Rate,
+    /// The size of the `list --change` move, whichever way it went.
+    Change,
+}
+
+impl SortKey {
+    /// Codes read best A to Z, rates and changes highest first.
+    pub fn default_order(self) -> SortOrder {
+        match self {
+            SortKey::Code => SortOrder::Asc,
+            SortKey::Rate | SortKey::Change => SortOrder::Desc,
+        }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "code" => Ok(SortKey::Code),
+            // `value` is the name the option had before `rate`.
+            "rate" | "value" => Ok(SortKey::Rate),
+            "change" => Ok(SortKey::Change),
+            _ => Err(format!("unknown sort key '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            _ => Err(format!("unknown sort order '{}'", s)),
+        }
+    }
+}
+
+/// Provider response body. Besides the bare `{"rates": {...}}` shape this accepts envelopes
+/// such as open.er-api.com's, which add `result`/`error-type` and may name the map
+/// `conversion_rates`; other envelope fields are ignored. The day the rates are from comes
+/// as Frankfurter's `date` or ExchangeRate-API's `year`, `month` and `day`, and otherwise
+/// from the day of the update time. The base comes as `base` or `base_code`.
+#[derive(Deserialize)]
+pub struct RatesResponse {
+    pub result: Option<String>,
+    #[serde(rename = "error-type")]
+    pub error_type: Option<String>,
+    #[serde(alias = "conversion_rates")]
+    pub rates: Option<HashMap<String, f64>>,
+    pub date: Option<String>,
+    #[serde(alias = "base_code")]
+    pub base: Option<String>,
+    /// Unix seconds; ExchangeRate-API and open.er-api send `time_last_update_unix`.
+    #[serde(alias = "time_last_update_unix")]
+    pub time_last_updated: Option<i64>,
+    pub year: Option<i32>,
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
+/// The error a provider explains a rejected request with, read from any of the shapes in
+/// use: `{"result": "error", "error-type": "unsupported-code"}`, `{"error": "Invalid
+/// authentication credentials"}`, `{"error": {"type": ..., "message": ...}}` or
+/// `{"message": "not found"}`.
+#[derive(Debug, Default, PartialEq)]
+pub struct ApiErrorBody {
+    /// Machine-readable error code, such as `invalid-key`.
+    pub error_type: Option<String>,
+    pub message: Option<String>,
+}
+
+impl ApiErrorBody {
+    /// Reads a response body, or `None` if it is not JSON or names no error.
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        let value: Value = serde_json::from_slice(body).ok()?;
+        let text = |value: &Value| value.as_str().map(str::to_string);
+        let error = &value["error"];
+        let body = ApiErrorBody {
+            error_type: text(&value["error-type"])
+                .or_else(|| text(&value["error_type"]))
+                .or_else(|| text(&error["type"]))
+                .or_else(|| text(&error["code"])),
+            message: text(error)
+                .or_else(|| text(&error["message"]))
+                .or_else(|| text(&error["info"]))
+                .or_else(|| text(&value["message"])),
+        };
+        (body.error_type.is_some() || body.message.is_some()).then_some(body)
+    }
+}
+
+impl TryFrom<RatesResponse> for Rates {
+    type Error = String;
+
+    fn try_from(response: RatesResponse) -> Result<Self, Self::Error> {
+        if response.result.as_deref() == Some("error") {
+            return Err(format!(
+                "Provider returned an error: {}",
+                response.error_type.as_deref().unwrap_or("unknown error")
+            ));
+        }
+        let effective_date = response
+            .date
+            .or_else(|| {
+                NaiveDate::from_ymd_opt(response.year?, response.month?, response.day?)
+                    .map(|date| date.to_string())
+            })
+            .or_else(|| {
+                DateTime::from_timestamp(response.time_last_updated?, 0)
+                    .map(|time| time.date_naive().to_string())
+            });
+        response
+            .rates
+            .map(|rates| Rates {
+                rates,
+                provider: String::new(),
+                effective_date,
+                base: response.base,
+                time_last_updated: response.time_last_updated,
+            })
+            .ok_or_else(|| "Provider response contained no rates".to_string())
+    }
+}
+
+/// Rates for one base currency as stored in the cache, keyed by [`CacheItem::key`].
 #[derive(Serialize, Deserialize, Clone)]
 pub struct CacheItem {
     pub rates: HashMap<String, f64>,
     pub timestamp: SystemTime,
+    /// Currency the rates are quoted against. Empty in caches written before this field existed.
+    #[serde(default)]
+    pub base: String,
+    #[serde(default)]
+    pub provider: String,
+    /// Day the rates were asked for, or `None` for the latest rates.
+    #[serde(default)]
+    pub date: Option<String>,
+    /// Day the provider said the rates are from; see [`Rates::effective_date`].
+    #[serde(default)]
+    pub effective_date: Option<String>,
+    /// See [`Rates::time_last_updated`].
+    #[serde(default)]
+    pub time_last_updated: Option<i64>,
+    /// Name of each code the provider supports, in the entry made by
+    /// [`CacheItem::code_list`]; empty in entries of rates.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub names: BTreeMap<String, String>,
+}
+
+/// Base of the cache entries holding a provider's supported codes; lower case, so it can't
+/// clash with a real base.
+pub const CODE_LIST_BASE: &str = "codes";
+
+/// A single exchange rate and where it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateQuote {
+    pub rate: f64,
+    /// Age of the cached data the rate was read from; zero for a live fetch.
+    pub age: Duration,
+    pub cached: bool,
+    /// The cached data is past its freshness window and was served only because the
+    /// fresh fetch failed.
+    pub stale: bool,
+    /// Name of the provider the rate came from, live or before it was cached.
+    pub provider: String,
+    /// The base the rate was worked out through when it isn't quoted against the source
+    /// currency itself: the target of an inverted entry, or the pivot of a cross rate.
+    pub derived_via: Option<String>,
+    /// Day the provider said the rate is from, which for a past weekend can be earlier than
+    /// the day asked for.
+    pub effective_date: Option<String>,
+    /// The rate is an override pinned with `rate set` rather than a market rate.
+    pub pinned: bool,
+}
+
+impl RateQuote {
+    /// Whether the provider said the rate is from a day before `day`, as Friday's rates
+    /// are on a Saturday.
+    pub fn predates(&self, day: NaiveDate) -> bool {
+        self.effective_date
+            .as_deref()
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+            .is_some_and(|date| date < day)
+    }
+}
+
+/// The rate map quoted against `base` has no usable entry for `currency`.
+#[derive(Debug, PartialEq)]
+pub struct RateNotFound {
+    pub currency: String,
+    pub base: String,
+}
+
+impl fmt::Display for RateNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rate for {} not found in {} rates",
+            self.currency, self.base
+        )
+    }
+}
+
+impl Error for RateNotFound {}
+
+impl CacheItem {
+    /// Latest rates for `base` from `provider`, stamped now.
+    pub fn new(provider: &str, base: &str, rates: HashMap<String, f64>) -> Self {
+        CacheItem {
+            rates,
+            timestamp: SystemTime::now(),
+            base: base.to_string(),
+            provider: provider.to_string(),
+            date: None,
+            effective_date: None,
+            time_last_updated: None,
+            names: BTreeMap::new(),
+        }
+    }
+
+    /// The codes `provider` supports and their `names`, stamped now. It is cached beside
+    /// the rates under the pseudo-base [`CODE_LIST_BASE`], which no currency code can be.
+    pub fn code_list(provider: &str, names: BTreeMap<String, String>) -> Self {
+        CacheItem {
+            names,
+            ..CacheItem::new(provider, CODE_LIST_BASE, HashMap::new())
+        }
+    }
+
+    /// Whether this is a [`CacheItem::code_list`] entry rather than rates.
+    pub fn is_code_list(&self) -> bool {
+        self.base == CODE_LIST_BASE
+    }
+
+    /// Cache map key for the rates of `base` from `provider` on `date` (latest when `None`).
+    pub fn key(provider: &str, base: &str, date: Option<&str>) -> String {
+        format!("{}:{}:{}", provider, base, date.unwrap_or("latest"))
+    }
+
+    /// Time elapsed since the item was stored. A timestamp in the future counts as zero age.
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.timestamp)
+            .unwrap_or_default()
+    }
+}
+
+/// Parses an amount given as a plain number or an arithmetic expression such as `12.5*3+4`.
+/// A plain number is kept exactly as typed, `1234.565` included, and may be written with
+/// separators such as `1,234.56`, `1.234,56` or `1 000,50`, after a currency symbol such
+/// as `$` or `zł`, and with a `k` or `m` suffix for thousands or millions (`10k`, `1.5m`).
+///
+/// Separators that could go either way are read as [`strip_separators`] does: a lone `.`
+/// is always a decimal point, while a lone `,` followed by exactly three digits groups
+/// them, so `1,234` is a thousand (the US reading) but `1,5` and `12,50` are fractions.
+///
+/// Negative amounts, infinities and NaN are rejected. Zero is not; see [`check_nonzero`].
+pub fn parse_amount(input: &str) -> Result<Decimal, String> {
+    let trimmed = strip_symbol(input.trim()).trim();
+    let lowered = trimmed.trim_start_matches(['+', '-']).to_lowercase();
+    if matches!(lowered.as_str(), "inf" | "infinity" | "nan") {
+        return Err(format!("amount '{}' is not a finite number", input));
+    }
+    let amount = match parse_number(trimmed) {
+        Some(amount) => amount,
+        None if trimmed.contains(['+', '-', '*', '/', '(', ')']) => expr::evaluate(trimmed)
+            .map(|amount| amount.normalize())
+            .map_err(|_| format!("could not parse amount expression '{}'", input))?,
+        None if trimmed.parse::<f64>().is_ok_and(|n| n.abs() >= 1.0) => {
+            return Err(format!("amount '{}' is too large", input))
+        }
+        None => return Err(format!("amount '{}' is not a valid number", input)),
+    };
+    if amount.is_sign_negative() && !amount.is_zero() {
+        return Err(format!("amount '{}' must not be negative", input));
+    }
+    Ok(amount)
+}
+
+/// `amount`, unless it is zero and `allow_zero` (`--allow-zero`) is off.
+pub fn check_nonzero(amount: Decimal, allow_zero: bool) -> Result<Decimal, String> {
+    if amount.is_zero() && !allow_zero {
+        Err("the amount is zero; pass --allow-zero to convert it anyway".to_string())
+    } else {
+        Ok(amount)
+    }
+}
+
+/// A plain number, possibly with separators and a `k` or `m` suffix.
+fn parse_number(input: &str) -> Option<Decimal> {
+    let (digits, multiplier) = match input.strip_suffix(['k', 'K']) {
+        Some(digits) => (digits, Some(Decimal::ONE_THOUSAND)),
+        None => match input.strip_suffix(['m', 'M']) {
+            Some(digits) => (digits, Some(Decimal::from(1_000_000))),
+            None => (input, None),
+        },
+    };
+    let digits = digits.trim_end();
+    let number = Decimal::from_str_exact(digits)
+        .or_else(|_| Decimal::from_scientific(digits))
+        .ok()
+        .or_else(|| strip_separators(digits)?.parse().ok())?;
+    match multiplier {
+        Some(multiplier) => number.checked_mul(multiplier).map(|n| n.normalize()),
+        None => Some(number),
+    }
+}
+
+/// `input` without a leading currency symbol, such as the `$` of `$100`. The longest
+/// matching symbol wins, so `R$100` loses `R$` rather than `R`.
+fn strip_symbol(input: &str) -> &str {
+    CURRENCIES
+        .iter()
+        .map(|info| info.symbol)
+        .filter(|symbol| {
+            input.strip_prefix(symbol).is_some_and(|rest| {
+                rest.trim_start()
+                    .starts_with(|c: char| c.is_ascii_digit() || matches!(c, '.' | '(' | '-' | '+'))
+            })
+        })
+        .max_by_key(|symbol| symbol.len())
+        .map_or(input, |symbol| &input[symbol.len()..])
+}
+
+/// `amount` converted at `rate`, unrounded. The rate is taken at its shortest decimal form,
+/// which is how the provider wrote it, so 0.1 at a rate of 3 is exactly 0.3. Products
+/// beyond `Decimal::MAX` saturate.
+pub fn convert_amount(amount: Decimal, rate: f64) -> Decimal {
+    amount.saturating_mul(decimal_rate(rate))
+}
+
+fn decimal_rate(rate: f64) -> Decimal {
+    Decimal::from_str(&rate.to_string())
+        .or_else(|_| Decimal::try_from(rate))
+        .unwrap_or_default()
+}
+
+/// Parses a fee percentage such as `2.5` or `2.5%`: zero (no fee) up to, but not
+/// including, 100.
+pub fn parse_fee(input: &str) -> Result<Decimal, String> {
+    let trimmed = input.trim();
+    let fee = Decimal::from_str_exact(trimmed.strip_suffix('%').unwrap_or(trimmed).trim())
+        .map_err(|_| format!("fee '{}' is not a percentage", input))?;
+    if fee.is_sign_negative() && !fee.is_zero() {
+        Err(format!("fee '{}' must not be negative", input))
+    } else if fee >= Decimal::ONE_HUNDRED {
+        Err(format!("fee '{}' must be below 100%", input))
+    } else {
+        Ok(fee.normalize())
+    }
+}
+
+/// A conversion at the mid-market rate and after a fee, each rounded like the plain output.
+#[derive(Debug, PartialEq)]
+pub struct FeeBreakdown {
+    pub mid_market: Decimal,
+    pub after_fee: Decimal,
+    /// What the fee costs, in the target currency.
+    pub fee: Decimal,
+}
+
+/// Converts `amount` at `rate` and at the effective rate a bank would give: `rate` lowered
+/// by `fee_percent`. The fee comes off the rate, not the converted amount. Both results are
+/// rounded on their own and the fee is their difference, so the printed numbers add up.
+pub fn apply_fee(
+    amount: Decimal,
+    rate: f64,
+    fee_percent: Decimal,
+    precision: usize,
+    mode: RoundMode,
+) -> FeeBreakdown {
+    let mid_market = round_amount(convert_amount(amount, rate), precision, mode);
+    let after_fee = round_amount(
+        convert_after_fee(amount, rate, fee_percent),
+        precision,
+        mode,
+    );
+    FeeBreakdown {
+        mid_market,
+        after_fee,
+        fee: mid_market - after_fee,
+    }
+}
+
+/// The source amount needed to end up with a given target amount, at the mid-market rate
+/// and with a fee, each rounded like the plain output.
+#[derive(Debug, PartialEq)]
+pub struct RequiredAmount {
+    pub mid_market: Decimal,
+    pub with_fee: Decimal,
+    /// What the fee costs, in the source currency.
+    pub fee: Decimal,
+}
+
+/// Solves [`apply_fee`] the other way round, for `--invert`: how much of the source currency
+/// converts into `target` at `rate`, and how much once `fee_percent` comes off the rate,
+/// which is always more. The fee is their difference.
+pub fn required_amount(
+    target: Decimal,
+    rate: f64,
+    fee_percent: Decimal,
+    precision: usize,
+    mode: RoundMode,
+) -> Result<RequiredAmount, String> {
+    let mid_market = round_amount(
+        source_for_target(target, rate, Decimal::ZERO)?,
+        precision,
+        mode,
+    );
+    let with_fee = round_amount(
+        source_for_target(target, rate, fee_percent)?,
+        precision,
+        mode,
+    );
+    Ok(RequiredAmount {
+        mid_market,
+        with_fee,
+        fee: with_fee - mid_market,
+    })
+}
+
+/// The unrounded amount that [`convert_after_fee`] turns into `target`. A rate that isn't
+/// positive, or one so small the quotient doesn't fit, is an error rather than an infinite
+/// amount.
+pub fn source_for_target(
+    target: Decimal,
+    rate: f64,
+    fee_percent: Decimal,
+) -> Result<Decimal, String> {
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(format!(
+            "cannot work out the amount needed at a rate of {}",
+            rate
+        ));
+    }
+    let effective_rate = decimal_rate(rate) * (Decimal::ONE - fee_percent / Decimal::ONE_HUNDRED);
+    target
+        .checked_div(effective_rate)
+        .filter(|_| !effective_rate.is_zero())
+        .ok_or_else(|| {
+            format!(
+                "the rate {} is too small to work out the amount needed",
+                rate
+            )
+        })
+}
+
+/// `amount` converted at `rate` lowered by `fee_percent`, unrounded.
+pub fn convert_after_fee(amount: Decimal, rate: f64, fee_percent: Decimal) -> Decimal {
+    let effective_rate = decimal_rate(rate) * (Decimal::ONE - fee_percent / Decimal::ONE_HUNDRED);
+    amount.saturating_mul(effective_rate)
+}
+
+/// Parses a `YYYY-MM-DD` date for historical rates, rejecting days after `today` or before
+/// the provider's earliest supported date.
+pub fn parse_date(input: &str, today: NaiveDate) -> Result<NaiveDate, String> {
+    let date = NaiveDate::parse_from_str(input.trim(), "%Y-%m-%d")
+        .map_err(|_| format!("invalid date '{}', expected YYYY-MM-DD", input))?;
+    let earliest = NaiveDate::parse_from_str(EARLIEST_HISTORICAL_DATE, "%Y-%m-%d")
+        .expect("EARLIEST_HISTORICAL_DATE is a valid date");
+    if date > today {
+        Err(format!("date {} is in the future", date))
+    } else if date < earliest {
+        Err(format!(
+            "date {} is before the earliest supported date {}",
+            date, earliest
+        ))
+    } else {
+        Ok(date)
+    }
+}
+
+/// Spacing between the days of a `timeseries`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Interval {
+    #[default]
+    Daily,
+    Weekly,
+    /// Same day of each month, clamped to the month's last day.
+    Monthly,
+}
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(Interval::Daily),
+            "weekly" => Ok(Interval::Weekly),
+            "monthly" => Ok(Interval::Monthly),
+            _ => Err(format!("unknown interval '{}'", s)),
+        }
+    }
+}
+
+/// Days from `start` to `end` inclusive, `interval` apart.
+pub fn series_dates(start: NaiveDate, end: NaiveDate, interval: Interval) -> Vec<NaiveDate> {
+    // Each step is measured from `start`, so monthly dates don't drift after a short month.
+    (0..)
+        .map_while(|step: u32| {
+            let date = match interval {
+                Interval::Daily => start.checked_add_days(Days::new(step.into())),
+                Interval::Weekly => start.checked_add_days(Days::new(u64::from(step) * 7)),
+                Interval::Monthly => start.checked_add_months(Months::new(step)),
+            }?;
+            (date <= end).then_some(date)
+        })
+        .collect()
+}
+
+/// `age` in its two largest units, e.g. `3h 1m`.
+pub fn describe_age(age: Duration) -> String {
+    match age.as_secs() {
+        secs if secs < 60 => format!("{}s", secs),
+        secs if secs < 3600 => format!("{}m {}s", secs / 60, secs % 60),
+        secs if secs < 86400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        secs => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+/// When an `alert` fires: once the rate falls below `below` or rises above `above`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Threshold {
+    pub below: Option<f64>,
+    pub above: Option<f64>,
+}
+
+impl Threshold {
+    /// Checks that at least one bound is given, that both are positive rates and that
+    /// `below` is under `above`, since otherwise every rate would fire.
+    pub fn new(below: Option<f64>, above: Option<f64>) -> Result<Self, String> {
+        for (flag, bound) in [("--below", below), ("--above", above)] {
+            if let Some(bound) = bound.filter(|bound| !(bound.is_finite() && *bound > 0.0)) {
+                return Err(format!("{} {} is not a positive rate", flag, bound));
+            }
+        }
+        match (below, above) {
+            (None, None) => Err("alert needs --below or --above (or both)".to_string()),
+            (Some(below), Some(above)) if below >= above => Err(format!(
+                "--below {} must be less than --above {}, or every rate would trigger the alert",
+                below, above
+            )),
+            _ => Ok(Threshold { below, above }),
+        }
+    }
+
+    /// The bound `rate` has crossed, described as e.g. `below 4.2`.
+    pub fn crossed(&self, rate: f64) -> Option<String> {
+        match (self.below, self.above) {
+            (Some(below), _) if rate < below => Some(format!("below {}", below)),
+            (_, Some(above)) if rate > above => Some(format!("above {}", above)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.below, self.above) {
+            (Some(below), Some(above)) => write!(f, "below {} or above {}", below, above),
+            (Some(below), None) => write!(f, "below {}", below),
+            (None, Some(above)) => write!(f, "above {}", above),
+            (None, None) => write!(f, "never"),
+        }
+    }
+}
+
+/// How a converted amount is rounded to the requested number of decimals. Amounts are
+/// never negative, so rounding up and down is the same as ceiling and floor.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RoundMode {
+    /// Half away from zero (half-up), as on invoices.
+    #[default]
+    Nearest,
+    /// Down, so an amount is never overstated.
+    Floor,
+    /// Up, for estimating a budget on the safe side.
+    Ceil,
+    /// Half to even (banker's rounding).
+    HalfEven,
+}
+
+/// The names [`RoundMode`] parses from, as `--round-mode` and `round_mode` take them.
+pub const ROUND_MODES: [&str; 7] = [
+    "nearest",
+    "half-up",
+    "half-even",
+    "floor",
+    "down",
+    "ceil",
+    "up",
+];
+
+impl FromStr for RoundMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" | "half-up" => Ok(RoundMode::Nearest),
+            "floor" | "down" => Ok(RoundMode::Floor),
+            "ceil" | "up" => Ok(RoundMode::Ceil),
+            "half-even" => Ok(RoundMode::HalfEven),
+            _ => Err(format!(
+                "unknown rounding mode '{}', expected one of {}",
+                s,
+                ROUND_MODES.join(", ")
+            )),
+        }
+    }
+}
+
+pub fn round_amount(value: Decimal, precision: usize, mode: RoundMode) -> Decimal {
+    let strategy = match mode {
+        RoundMode::Nearest => RoundingStrategy::MidpointAwayFromZero,
+        RoundMode::Floor => RoundingStrategy::ToNegativeInfinity,
+        RoundMode::Ceil => RoundingStrategy::ToPositiveInfinity,
+        RoundMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+    };
+    value.round_dp_with_strategy(precision as u32, strategy)
 }
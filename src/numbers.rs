@@ -0,0 +1,158 @@
+//! Locale styles for printed amounts, and the separators to strip from typed ones. Only
+//! human-readable output is styled; `--json` always carries plain numbers.
+
+use std::env;
+
+/// How the digits of a number are grouped and its fraction separated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NumberFormat {
+    /// Between each group of three integer digits, or `None` for no grouping.
+    pub group: Option<char>,
+    pub decimal: char,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat::PLAIN
+    }
+}
+
+impl NumberFormat {
+    /// `1234567.89`, as printed before locales existed.
+    pub const PLAIN: NumberFormat = NumberFormat {
+        group: None,
+        decimal: '.',
+    };
+
+    /// The style of `locale`, given as a language tag (`pl-PL`) or a POSIX locale name
+    /// (`pl_PL.UTF-8`). `C`, `POSIX` and `plain` mean no grouping.
+    pub fn for_locale(locale: &str) -> Result<NumberFormat, String> {
+        let name = locale.split(['.', '@']).next().unwrap_or_default();
+        let mut parts = name.split(['-', '_']);
+        let language = parts.next().unwrap_or_default().to_lowercase();
+        let region = parts.next().unwrap_or_default().to_uppercase();
+        let style = |group, decimal| NumberFormat {
+            group: Some(group),
+            decimal,
+        };
+        match (language.as_str(), region.as_str()) {
+            ("c" | "posix" | "plain", _) => Ok(NumberFormat::PLAIN),
+            (_, "CH" | "LI") => Ok(style('\'', '.')),
+            ("en" | "ja" | "zh" | "ko" | "he" | "th" | "hi" | "ms", _) => Ok(style(',', '.')),
+            (
+                "de" | "nl" | "it" | "es" | "pt" | "da" | "tr" | "id" | "el" | "ro" | "hr" | "sl"
+                | "sr",
+                _,
+            ) => Ok(style('.', ',')),
+            (
+                "pl" | "fr" | "ru" | "cs" | "sk" | "sv" | "nb" | "no" | "fi" | "uk" | "hu" | "bg"
+                | "lt" | "lv" | "et",
+                _,
+            ) => Ok(style(' ', ',')),
+            _ => Err(format!(
+                "unknown locale '{}', expected e.g. en-US, de-DE or pl-PL",
+                locale
+            )),
+        }
+    }
+
+    /// The style of the numeric locale in `LC_ALL` or `LC_NUMERIC`, if one is set and known.
+    pub fn from_env() -> Option<NumberFormat> {
+        ["LC_ALL", "LC_NUMERIC"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|locale| NumberFormat::for_locale(&locale).ok())
+    }
+
+    /// Restyles `number`, already formatted like `-1234567.89`, in this style.
+    pub fn apply(&self, number: &str) -> String {
+        let (sign, digits) = match number.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", number),
+        };
+        let (integer, fraction) = match digits.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (digits, None),
+        };
+        let mut styled = sign.to_string();
+        for (i, digit) in integer.chars().enumerate() {
+            if let Some(group) = self.group {
+                if i > 0 && (integer.len() - i) % 3 == 0 {
+                    styled.push(group);
+                }
+            }
+            styled.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            styled.push(self.decimal);
+            styled.push_str(fraction);
+        }
+        styled
+    }
+}
+
+/// `number` with its grouping separators removed and a decimal comma made a point, or `None`
+/// if it is not a number written with separators. When both `,` and `.` appear the last one
+/// starts the fraction. A single `.` always does, while a single `,` does only if it is not
+/// followed by exactly three digits: `1,234` is a thousand and `12,50` twelve and a half.
+pub fn strip_separators(number: &str) -> Option<String> {
+    let (sign, digits) = match number.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", number),
+    };
+    let is_separator = |c: char| matches!(c, ',' | '.' | '_' | '\'' | ' ' | '\u{a0}' | '\u{202f}');
+    if !digits.starts_with(|c: char| c.is_ascii_digit())
+        || !digits
+            .chars()
+            .all(|c| c.is_ascii_digit() || is_separator(c))
+        || !digits.contains(is_separator)
+    {
+        return None;
+    }
+    // Underscores, apostrophes and (non-breaking) spaces only ever group digits.
+    let digits: String = digits
+        .chars()
+        .filter(|c| c.is_ascii_digit() || matches!(c, ',' | '.'))
+        .collect();
+    let (integer, fraction) = match (digits.rfind(','), digits.rfind('.')) {
+        (Some(comma), Some(point)) => {
+            let at = comma.max(point);
+            (&digits[..at], Some(&digits[at + 1..]))
+        }
+        (Some(at), None) | (None, Some(at)) => {
+            let separator = char::from(digits.as_bytes()[at]);
+            let single = digits.matches(separator).count() == 1;
+            if single && (separator == '.' || !is_grouped(&digits, separator)) {
+                (&digits[..at], Some(&digits[at + 1..]))
+            } else {
+                (digits.as_str(), None)
+            }
+        }
+        (None, None) => (digits.as_str(), None),
+    };
+    if fraction.is_some_and(|fraction| !fraction.chars().all(|c| c.is_ascii_digit())) {
+        return None;
+    }
+    if let Some(separator) = integer.chars().find(|c| !c.is_ascii_digit()) {
+        if !is_grouped(integer, separator) {
+            return None;
+        }
+    }
+    let mut plain = format!("{}{}", sign, integer.replace([',', '.'], ""));
+    if let Some(fraction) = fraction.filter(|fraction| !fraction.is_empty()) {
+        plain.push('.');
+        plain.push_str(fraction);
+    }
+    Some(plain)
+}
+
+/// Every `separator` in `number` starts a group of three digits after a leading group of
+/// one to three.
+fn is_grouped(number: &str, separator: char) -> bool {
+    let mut groups = number.split(separator);
+    let first = groups.next().unwrap_or_default();
+    (1..=3).contains(&first.len())
+        && first.chars().all(|c| c.is_ascii_digit())
+        && groups.all(|group| group.len() == 3 && group.chars().all(|c| c.is_ascii_digit()))
+}
@@ -0,0 +1,1288 @@
+use crate::aliases::Aliases;
+use crate::api::{quote_spread, ProviderQuote};
+use crate::codes::is_crypto;
+use crate::config::CRYPTO_PRECISION;
+use crate::currencies;
+use crate::error::error_kind;
+use crate::history::HistoryRecord;
+use crate::models::{
+    apply_fee, convert_after_fee, describe_age, source_for_target, RateQuote, Rates,
+    RequiredAmount, RoundMode, SortKey, SortOrder, Threshold,
+};
+use crate::numbers::NumberFormat;
+use crate::overrides::RateOverrides;
+use crate::table::{Align, Table};
+use chrono::NaiveDate;
+use owo_colors::OwoColorize;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use std::{cmp::Ordering, collections::HashMap, env, str::FromStr};
+
+/// How conversion results are rounded and printed.
+pub struct OutputFormat {
+    pub precision: usize,
+    pub round_mode: RoundMode,
+    pub with_names: bool,
+    /// Grouping and decimal separators of the plain output; `--json` ignores them.
+    pub numbers: NumberFormat,
+    /// Percentage taken off the rate, as by a bank or card; zero shows the mid-market
+    /// result alone.
+    pub fee: Decimal,
+    /// Highlights converted amounts and dims rates with terminal escape codes.
+    pub color: bool,
+}
+
+/// When to color output, as given to `--color`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice '{}'", s)),
+        }
+    }
+}
+
+/// Whether to color a stream under `choice`. `auto` colors it only when it is a terminal
+/// and `NO_COLOR` is not set to a non-empty value.
+pub fn color_enabled(choice: ColorChoice, is_terminal: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            is_terminal && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        }
+    }
+}
+
+/// `text` tinted green when `change` is up and red when it is down, if `color`.
+fn tint_change(text: String, change: f64, color: bool) -> String {
+    if !color || change == 0.0 {
+        text
+    } else if change > 0.0 {
+        text.green().to_string()
+    } else {
+        text.red().to_string()
+    }
+}
+
+/// Significant digits a rate is shown with, so sub-cent rates such as JPY to USD keep
+/// their meaningful digits.
+const RATE_SIGNIFICANT_DIGITS: i32 = 6;
+
+/// Formats an exchange rate with [`RATE_SIGNIFICANT_DIGITS`] significant digits, dropping
+/// trailing zeros but keeping at least `min_decimals` places. Integer digits are never
+/// rounded away: 15423.55 stays 15423.55 and 0.0000071 does not become 0.00.
+pub fn format_rate(rate: f64, min_decimals: usize) -> String {
+    if !rate.is_finite() || rate == 0.0 {
+        return format!("{:.*}", min_decimals, rate);
+    }
+    let magnitude = rate.abs().log10().floor() as i32;
+    let decimals = (RATE_SIGNIFICANT_DIGITS - 1 - magnitude).max(0) as usize;
+    let formatted = format!("{:.*}", decimals.max(min_decimals), rate);
+    let Some(point) = formatted.find('.') else {
+        return formatted;
+    };
+    let mut formatted = formatted.trim_end_matches('0').to_string();
+    let kept = formatted.len() - point - 1;
+    if kept < min_decimals {
+        formatted.push_str(&"0".repeat(min_decimals - kept));
+    }
+    formatted.trim_end_matches('.').to_string()
+}
+
+/// Decimal places an amount in `code` is rounded to: `precision`, but at least
+/// [`CRYPTO_PRECISION`] for crypto assets, where a hundredth of a unit is too coarse.
+pub fn amount_precision(code: &str, precision: usize) -> usize {
+    if is_crypto(code) {
+        precision.max(CRYPTO_PRECISION)
+    } else {
+        precision
+    }
+}
+
+pub fn conversion_line(
+    amount: Decimal,
+    from: &str,
+    to: &str,
+    rate: f64,
+    format: &OutputFormat,
+) -> String {
+    let precision = amount_precision(to, format.precision);
+    let label = |code: &str| currency_label(code, format);
+    let number = |value: Decimal| {
+        let number = format.numbers.apply(&format!("{:.precision$}", value));
+        if format.color {
+            number.green().bold().to_string()
+        } else {
+            number
+        }
+    };
+    let rate_text = format.numbers.apply(&format_rate(rate, precision));
+    let rate_text = if format.color {
+        rate_text.dimmed().to_string()
+    } else {
+        rate_text
+    };
+    let breakdown = apply_fee(amount, rate, format.fee, precision, format.round_mode);
+    let line = format!(
+        "{} {} is {} {} at an exchange rate of {}",
+        format.numbers.apply(&amount.to_string()),
+        label(from),
+        number(breakdown.mid_market),
+        label(to),
+        rate_text
+    );
+    if format.fee.is_zero() {
+        return line;
+    }
+    format!(
+        "{}, {} {} after a {}% fee (fee: {} {})",
+        line,
+        number(breakdown.after_fee),
+        to,
+        format.numbers.apply(&format.fee.to_string()),
+        number(breakdown.fee),
+        to
+    )
+}
+
+/// Several `amounts` converted at one `rate`: a row of `10 EUR → 43.50 PLN` for each, the
+/// columns aligned, and `at an exchange rate of 4.35` beneath. With a fee the converted
+/// column is after it.
+pub fn amount_table(
+    amounts: &[Decimal],
+    from: &str,
+    to: &str,
+    rate: f64,
+    format: &OutputFormat,
+) -> Vec<String> {
+    let precision = amount_precision(to, format.precision);
+    let rows: Vec<(String, String)> = amounts
+        .iter()
+        .map(|amount| {
+            let breakdown = apply_fee(*amount, rate, format.fee, precision, format.round_mode);
+            (
+                format.numbers.apply(&amount.to_string()),
+                format
+                    .numbers
+                    .apply(&format!("{:.precision$}", breakdown.after_fee)),
+            )
+        })
+        .collect();
+    let width = |column: fn(&(String, String)) -> &String| {
+        rows.iter()
+            .map(|row| column(row).chars().count())
+            .max()
+            .unwrap_or(0)
+    };
+    let (amount_width, converted_width) = (width(|row| &row.0), width(|row| &row.1));
+    let (from, to) = (currency_label(from, format), currency_label(to, format));
+    let mut lines: Vec<String> = rows
+        .iter()
+        .map(|(amount, converted)| {
+            // Padded before coloring, which would throw the widths off.
+            let converted = format!("{:>converted_width$}", converted);
+            let converted = if format.color {
+                converted.green().bold().to_string()
+            } else {
+                converted
+            };
+            format!("{:>amount_width$} {} → {} {}", amount, from, converted, to)
+        })
+        .collect();
+    let mut footer = format!(
+        "at an exchange rate of {}",
+        format.numbers.apply(&format_rate(rate, precision))
+    );
+    if !format.fee.is_zero() {
+        footer.push_str(&format!(
+            ", after a {}% fee",
+            format.numbers.apply(&format.fee.to_string())
+        ));
+    }
+    lines.push(footer);
+    lines
+}
+
+/// `code`, with its symbol when `--with-names` asks for it.
+fn currency_label(code: &str, format: &OutputFormat) -> String {
+    if format.with_names {
+        currencies::with_symbol(code)
+    } else {
+        code.to_string()
+    }
+}
+
+/// An `--invert` conversion: `You need 248.76 USD to get 1000.00 PLN at an exchange rate
+/// of 4.02`, followed by the amount needed with a fee when there is one.
+pub fn required_line(
+    target: Decimal,
+    required: &RequiredAmount,
+    from: &str,
+    to: &str,
+    rate: f64,
+    format: &OutputFormat,
+) -> String {
+    let source_precision = amount_precision(from, format.precision);
+    let label = |code: &str| currency_label(code, format);
+    let number = |value: Decimal, precision: usize| {
+        let number = format.numbers.apply(&format!("{:.precision$}", value));
+        if format.color {
+            number.green().bold().to_string()
+        } else {
+            number
+        }
+    };
+    let rate_text = format.numbers.apply(&format_rate(rate, source_precision));
+    let rate_text = if format.color {
+        rate_text.dimmed().to_string()
+    } else {
+        rate_text
+    };
+    let line = format!(
+        "You need {} {} to get {} {} at an exchange rate of {}",
+        number(required.mid_market, source_precision),
+        label(from),
+        format.numbers.apply(&format!(
+            "{:.*}",
+            amount_precision(to, format.precision),
+            target
+        )),
+        label(to),
+        rate_text
+    );
+    if format.fee.is_zero() {
+        return line;
+    }
+    format!(
+        "{}, {} {} after a {}% fee (fee: {} {})",
+        line,
+        number(required.with_fee, source_precision),
+        from,
+        format.numbers.apply(&format.fee.to_string()),
+        number(required.fee, source_precision),
+        from
+    )
+}
+
+/// `1 / rate`, unless `rate` is zero, negative or not finite or its inverse overflows, as
+/// can happen with a buggy provider's rates.
+pub fn inverse_rate(rate: f64) -> Option<f64> {
+    (rate.is_finite() && rate > 0.0)
+        .then(|| 1.0 / rate)
+        .filter(|inverse| inverse.is_finite())
+}
+
+/// The rate the other way round, e.g. `1 PLN = 0.251256 USD` after a USD to PLN
+/// conversion, or `None` when [`inverse_rate`] has nothing sensible.
+pub fn inverse_line(from: &str, to: &str, rate: f64, format: &OutputFormat) -> Option<String> {
+    let inverse = format.numbers.apply(&format_rate(inverse_rate(rate)?, 0));
+    let line = format!("1 {} = {} {}", to, inverse, from);
+    Some(if format.color {
+        line.dimmed().to_string()
+    } else {
+        line
+    })
+}
+
+/// What `--raw` prints of a conversion.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RawValue {
+    Amount,
+    Rate,
+}
+
+impl FromStr for RawValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "amount" => Ok(RawValue::Amount),
+            "rate" => Ok(RawValue::Rate),
+            _ => Err(format!("unknown raw value '{}'", s)),
+        }
+    }
+}
+
+/// `--raw` form of a conversion: just the converted amount after `fee` percent, or the
+/// mid-market rate. Neither is rounded or localized, so `bc` and `awk` can read it.
+pub fn raw_line(amount: Decimal, rate: f64, fee: Decimal, value: RawValue) -> String {
+    match value {
+        RawValue::Amount => convert_after_fee(amount, rate, fee).normalize().to_string(),
+        RawValue::Rate => rate.to_string(),
+    }
+}
+
+/// [`raw_line`] under `--invert`: the amount needed to get `target` after `fee` percent.
+pub fn raw_required_line(
+    target: Decimal,
+    rate: f64,
+    fee: Decimal,
+    value: RawValue,
+) -> Result<String, String> {
+    match value {
+        RawValue::Amount => Ok(source_for_target(target, rate, fee)?
+            .normalize()
+            .to_string()),
+        RawValue::Rate => Ok(rate.to_string()),
+    }
+}
+
+/// `--json` form of a conversion. `converted` is rounded like the plain output; `rate` and
+/// `inverse_rate` (null for a rate without a sensible inverse) are not. Amounts are written
+/// as JSON numbers, so a reader parsing them as doubles gets the nearest one.
+pub fn conversion_json(
+    amount: Decimal,
+    from: &str,
+    to: &str,
+    quote: &RateQuote,
+    format: &OutputFormat,
+) -> Value {
+    let breakdown = apply_fee(
+        amount,
+        quote.rate,
+        format.fee,
+        amount_precision(to, format.precision),
+        format.round_mode,
+    );
+    let mut document = json!({
+        "from": from,
+        "to": to,
+        "amount": amount.to_f64(),
+        "rate": quote.rate,
+        "inverse_rate": inverse_rate(quote.rate),
+        "converted": breakdown.mid_market.to_f64(),
+        "cached": quote.cached,
+        "stale": quote.stale,
+        "provider": quote.provider,
+    });
+    add_quote_details(&mut document, quote);
+    if !format.fee.is_zero() {
+        document["fee_percent"] = format.fee.to_f64().into();
+        document["converted_after_fee"] = breakdown.after_fee.to_f64().into();
+        document["fee"] = breakdown.fee.to_f64().into();
+    }
+    document
+}
+
+/// `--json` form of an `--invert` conversion, shaped like [`conversion_json`]: `amount` is
+/// what is needed of `from` and `converted` the `target` asked for, with `solved_for`
+/// saying which one was worked out. With a fee, `amount_with_fee` and `fee` are in `from`.
+pub fn required_json(
+    target: Decimal,
+    required: &RequiredAmount,
+    from: &str,
+    to: &str,
+    quote: &RateQuote,
+    format: &OutputFormat,
+) -> Value {
+    let mut document = json!({
+        "from": from,
+        "to": to,
+        "solved_for": "amount",
+        "amount": required.mid_market.to_f64(),
+        "rate": quote.rate,
+        "inverse_rate": inverse_rate(quote.rate),
+        "converted": target.to_f64(),
+        "cached": quote.cached,
+        "stale": quote.stale,
+        "provider": quote.provider,
+    });
+    add_quote_details(&mut document, quote);
+    if !format.fee.is_zero() {
+        document["fee_percent"] = format.fee.to_f64().into();
+        document["amount_with_fee"] = required.with_fee.to_f64().into();
+        document["fee"] = required.fee.to_f64().into();
+    }
+    document
+}
+
+/// The fields only some quotes have: the age of a stale rate, the pivot of a derived rate,
+/// the day the provider quoted and whether the rate was pinned.
+fn add_quote_details(document: &mut Value, quote: &RateQuote) {
+    if quote.stale {
+        document["age_seconds"] = quote.age.as_secs().into();
+    }
+    if let Some(via) = &quote.derived_via {
+        document["derived_via"] = via.as_str().into();
+    }
+    if let Some(date) = &quote.effective_date {
+        document["effective_date"] = date.as_str().into();
+    }
+    if quote.pinned {
+        document["pinned"] = true.into();
+    }
+}
+
+/// How each `list` rate moved since the `--change` comparison day, in percent: `None` for
+/// a currency the earlier rates didn't have. A code without an entry has no comparison.
+pub type RateChanges = HashMap<String, Option<f64>>;
+
+/// The [`RateChanges`] of `entries` against the `previous` rates of the same base.
+pub fn rate_changes(entries: &[(String, f64)], previous: &HashMap<String, f64>) -> RateChanges {
+    entries
+        .iter()
+        .map(|(code, rate)| {
+            let change = previous
+                .get(code)
+                .filter(|previous| previous.is_finite() && **previous > 0.0)
+                .map(|previous| (rate - previous) / previous * 100.0);
+            (code.clone(), change)
+        })
+        .collect()
+}
+
+/// A change as `list --change` shows it: signed to two places, or `new`.
+pub fn format_change(change: Option<f64>) -> String {
+    match change {
+        Some(change) => format!("{:+.2}%", change),
+        None => "new".to_string(),
+    }
+}
+
+/// Orders `entries` by the size of their change, biggest first for `Desc`. Currencies
+/// without a number to compare come last either way, and ties stay in code order.
+pub fn sort_by_change(entries: &mut [(String, f64)], changes: &RateChanges, order: SortOrder) {
+    let size = |code: &str| {
+        changes
+            .get(code)
+            .copied()
+            .flatten()
+            .filter(|change| !change.is_nan())
+            .map(f64::abs)
+    };
+    entries.sort_by(|a, b| match (size(&a.0), size(&b.0)) {
+        (Some(a), Some(b)) => {
+            let ordering = a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+            match order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        }
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    });
+}
+
+/// The `changes` of a `list --json` document: each code in `entries` to its percentage,
+/// `null` when it is new or missing from `changes`.
+pub fn changes_json(entries: &[(String, f64)], changes: &RateChanges) -> Value {
+    let changes: serde_json::Map<String, Value> = entries
+        .iter()
+        .map(|(code, _)| (code.clone(), json!(changes.get(code).copied().flatten())))
+        .collect();
+    Value::Object(changes)
+}
+
+/// `--json` form of one `list` table.
+pub fn rates_json(base: &str, entries: &[(String, f64)]) -> Value {
+    let rates: serde_json::Map<String, Value> = entries
+        .iter()
+        .map(|(code, rate)| (code.to_string(), json!(rate)))
+        .collect();
+    json!({ "base": base, "rates": rates })
+}
+
+/// Header of `list --output csv`.
+pub const RATES_CSV_HEADER: [&str; 3] = ["base", "currency", "rate"];
+
+/// Header of `timeseries --output csv`.
+pub const TIMESERIES_CSV_HEADER: [&str; 2] = ["date", "rate"];
+
+/// One RFC 4180 record, without the line ending. Fields holding a comma, a quote or a line
+/// break are quoted, with their quotes doubled.
+pub fn csv_record<S: AsRef<str>>(fields: &[S]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// `--output csv` rows of one `list` table. Rates are written in full like `--json`, and
+/// no number is localized. With `changes`, a `change` column follows, empty for a
+/// currency without one.
+pub fn rates_csv(
+    base: &str,
+    entries: &[(String, f64)],
+    changes: Option<&RateChanges>,
+) -> Vec<String> {
+    entries
+        .iter()
+        .map(|(code, rate)| {
+            let mut fields = vec![base.to_string(), code.clone(), rate.to_string()];
+            if let Some(changes) = changes {
+                let change = changes.get(code).copied().flatten();
+                fields.push(change.map(|change| change.to_string()).unwrap_or_default());
+            }
+            csv_record(&fields)
+        })
+        .collect()
+}
+
+/// `--output csv` rows of a `timeseries`.
+pub fn timeseries_csv(points: &[(NaiveDate, f64)]) -> Vec<String> {
+    points
+        .iter()
+        .map(|(date, rate)| csv_record(&[date.to_string(), rate.to_string()]))
+        .collect()
+}
+
+/// Header of conversions and `batch` under `--output csv`. With a fee the columns of
+/// [`conversion_json`] for it follow.
+pub fn conversion_csv_header(format: &OutputFormat) -> Vec<&'static str> {
+    let mut header = vec!["amount", "from", "to", "rate", "converted"];
+    if !format.fee.is_zero() {
+        header.extend(["fee_percent", "converted_after_fee", "fee"]);
+    }
+    header
+}
+
+/// `--output csv` row of a conversion, under [`conversion_csv_header`]. `converted` is
+/// rounded like the plain output but never localized.
+pub fn conversion_csv(
+    amount: Decimal,
+    from: &str,
+    to: &str,
+    rate: f64,
+    format: &OutputFormat,
+) -> String {
+    let precision = amount_precision(to, format.precision);
+    let breakdown = apply_fee(amount, rate, format.fee, precision, format.round_mode);
+    let number = |value: Decimal| format!("{:.precision$}", value);
+    let mut fields = vec![
+        amount.to_string(),
+        from.to_string(),
+        to.to_string(),
+        rate.to_string(),
+        number(breakdown.mid_market),
+    ];
+    if !format.fee.is_zero() {
+        fields.extend([
+            format.fee.to_string(),
+            number(breakdown.after_fee),
+            number(breakdown.fee),
+        ]);
+    }
+    csv_record(&fields)
+}
+
+/// An error as printed to stderr: `message` after `context`, which is red if `color`.
+pub fn error_line(context: &str, message: impl std::fmt::Display, color: bool) -> String {
+    if color {
+        format!("{}: {}", context.red().bold(), message)
+    } else {
+        format!("{}: {}", context, message)
+    }
+}
+
+/// `--json` form of an error, printed to stderr.
+pub fn error_json(exit_code: i32, message: &str) -> Value {
+    json!({ "error": { "kind": error_kind(exit_code), "message": message } })
+}
+
+/// Rows of a `list` table, sorted as asked. Without an explicit `sort`, codes named in
+/// `only` come first in the order given and the rest follow by code.
+pub fn list_entries(
+    rates: &Rates,
+    sort: Option<(SortKey, SortOrder)>,
+    only: &[String],
+) -> Vec<(String, f64)> {
+    match sort {
+        Some((key, order)) => rates.sorted_entries(key, order),
+        None => {
+            let mut entries = rates.sorted_entries(SortKey::Code, SortOrder::Asc);
+            entries.sort_by_key(|(code, _)| {
+                only.iter()
+                    .position(|wanted| wanted == code)
+                    .unwrap_or(only.len())
+            });
+            entries
+        }
+    }
+}
+
+/// Cuts `entries` to the first `limit`, returning how many were dropped. A `limit` of 0
+/// keeps them all.
+pub fn limit_entries(entries: &mut Vec<(String, f64)>, limit: usize) -> usize {
+    if limit == 0 || entries.len() <= limit {
+        return 0;
+    }
+    let hidden = entries.len() - limit;
+    entries.truncate(limit);
+    hidden
+}
+
+/// The note after a `list --limit` that left `hidden` rows out.
+pub fn hidden_line(hidden: usize) -> String {
+    format!("… and {} more, use --limit 0 to show all", hidden)
+}
+
+/// Percentage change from `previous` to `fresh`, if there is a usable previous rate.
+pub fn rate_change(fresh: &RateQuote, previous: Option<&RateQuote>) -> Option<f64> {
+    previous
+        .filter(|previous| previous.rate != 0.0)
+        .map(|previous| (fresh.rate - previous.rate) / previous.rate * 100.0)
+}
+
+/// How the rate moved since the `previous` cached quote, the change tinted by direction
+/// if `color`.
+pub fn comparison_line(
+    from: &str,
+    to: &str,
+    fresh: &RateQuote,
+    previous: Option<&RateQuote>,
+    color: bool,
+) -> String {
+    match (rate_change(fresh, previous), previous) {
+        (Some(change), Some(previous)) => format!(
+            "{}/{} moved {} since last cached {} ago",
+            from,
+            to,
+            tint_change(format!("{:+.2}%", change), change, color),
+            describe_age(previous.age)
+        ),
+        _ => format!("No cached {}/{} rate to compare against", from, to),
+    }
+}
+
+/// One `--watch` observation: `line` stamped with the time of the fetch it came from, the
+/// change since the previous observation to `decimals` places with an arrow (tinted by
+/// direction if `color`), and a marker once fetches have been failing since `stale_since`.
+pub fn watch_line(
+    line: &str,
+    fetched_at: &str,
+    delta: Option<f64>,
+    decimals: usize,
+    stale_since: Option<&str>,
+    color: bool,
+) -> String {
+    let mut watch_line = format!("[{}] {}", fetched_at, line);
+    if let Some(delta) = delta {
+        let arrow = if delta > 0.0 {
+            "▲"
+        } else if delta < 0.0 {
+            "▼"
+        } else {
+            "="
+        };
+        let change = format!("({:+.*} {})", decimals, delta, arrow);
+        watch_line.push_str(&format!(" {}", tint_change(change, delta, color)));
+    }
+    if let Some(since) = stale_since {
+        let marker = format!("[stale since {}]", since);
+        let marker = if color {
+            marker.yellow().to_string()
+        } else {
+            marker
+        };
+        watch_line.push_str(&format!(" {}", marker));
+    }
+    watch_line
+}
+
+/// The plain `CODE: rate` lines `list` prints when piped, with the name and symbol of
+/// each currency if `with_names` and the change after the rate if there are `changes`.
+pub fn rate_lines(
+    entries: &[(String, f64)],
+    with_names: bool,
+    changes: Option<&RateChanges>,
+) -> Vec<String> {
+    entries
+        .iter()
+        .map(|(code, rate)| {
+            let mut line = format!("{}: {}", code, rate);
+            if let Some(changes) = changes {
+                let change = changes.get(code).copied().flatten();
+                line.push_str(&format!(" {}", format_change(change)));
+            }
+            if let Some(info) = currencies::lookup(code).filter(|_| with_names) {
+                line.push_str(&format!(" ({}, {})", info.name, info.symbol));
+            }
+            line
+        })
+        .collect()
+}
+
+/// `currencies` rows: each code, padded to the longest, and its name.
+pub fn currency_lines(entries: &[(&str, &str)]) -> Vec<String> {
+    let width = entries
+        .iter()
+        .map(|(code, _)| code.chars().count())
+        .max()
+        .unwrap_or(0);
+    entries
+        .iter()
+        .map(|(code, name)| format!("{:<width$}  {}", code, name).trim_end().to_string())
+        .collect()
+}
+
+/// `--json` form of `currencies`: where the list came from and its entries.
+pub fn currencies_json(source: &str, entries: &[(&str, &str)]) -> Value {
+    let currencies: Vec<Value> = entries
+        .iter()
+        .map(|(code, name)| json!({ "code": code, "name": name }))
+        .collect();
+    json!({ "source": source, "currencies": currencies })
+}
+
+/// A column of the `list` table, picked with `list --columns`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListColumn {
+    Code,
+    Name,
+    Rate,
+    /// What one unit of the currency is worth in the base.
+    Inverse,
+    /// The move since the `--change` comparison day, always the last column.
+    Change,
+}
+
+/// The columns `list` shows on a terminal unless `--columns` says otherwise.
+pub const LIST_COLUMNS: [ListColumn; 4] = [
+    ListColumn::Code,
+    ListColumn::Name,
+    ListColumn::Rate,
+    ListColumn::Inverse,
+];
+
+impl ListColumn {
+    fn header(self) -> &'static str {
+        match self {
+            ListColumn::Code => "Code",
+            ListColumn::Name => "Name",
+            ListColumn::Rate => "Rate",
+            ListColumn::Inverse => "1/Rate",
+            ListColumn::Change => "Change",
+        }
+    }
+}
+
+impl FromStr for ListColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "code" => Ok(ListColumn::Code),
+            "name" => Ok(ListColumn::Name),
+            "rate" => Ok(ListColumn::Rate),
+            "inverse" => Ok(ListColumn::Inverse),
+            _ => Err(format!("unknown list column '{}'", s)),
+        }
+    }
+}
+
+/// `list` rows as a table of `columns` under a header, the numbers right-aligned. When the
+/// table is wider than `width` the Name column is left out. With `changes` a Change
+/// column follows. With `color`, codes are highlighted, rates above 1.0 tinted green and
+/// below yellow, changes green when up and red when down, and names dimmed.
+pub fn rate_table(
+    entries: &[(String, f64)],
+    columns: &[ListColumn],
+    changes: Option<&RateChanges>,
+    width: usize,
+    color: bool,
+) -> Vec<String> {
+    let mut columns = columns.to_vec();
+    columns.retain(|column| *column != ListColumn::Change);
+    if changes.is_some() {
+        columns.push(ListColumn::Change);
+    }
+    let change = |code: &str| changes.and_then(|changes| changes.get(code).copied().flatten());
+    let headers: Vec<(&str, Align)> = columns
+        .iter()
+        .map(|column| match column {
+            ListColumn::Code | ListColumn::Name => (column.header(), Align::Left),
+            ListColumn::Rate | ListColumn::Inverse | ListColumn::Change => {
+                (column.header(), Align::Right)
+            }
+        })
+        .collect();
+    let mut table = Table::new(&headers);
+    for (code, rate) in entries {
+        table.push(
+            columns
+                .iter()
+                .map(|column| match column {
+                    ListColumn::Code => code.clone(),
+                    ListColumn::Name => currencies::lookup(code)
+                        .map(|info| info.name.to_string())
+                        .unwrap_or_default(),
+                    ListColumn::Rate => rate.to_string(),
+                    ListColumn::Inverse => inverse_rate(*rate)
+                        .map(|inverse| format_rate(inverse, 0))
+                        .unwrap_or_else(|| "-".to_string()),
+                    ListColumn::Change => format_change(change(code)),
+                })
+                .collect(),
+        );
+    }
+    let mut shown = columns;
+    if table.width() > width {
+        table.remove_column(ListColumn::Name.header());
+        shown.retain(|column| *column != ListColumn::Name);
+    }
+    table.lines(|column, row, cell| {
+        if !color {
+            return cell;
+        }
+        let Some(row) = row else {
+            return cell.bold().to_string();
+        };
+        let (code, rate) = &entries[row];
+        let rate = *rate;
+        match shown[column] {
+            ListColumn::Change => match change(code) {
+                Some(change) => tint_change(cell, change, true),
+                None => cell.dimmed().to_string(),
+            },
+            ListColumn::Code => cell.cyan().bold().to_string(),
+            ListColumn::Name => cell.dimmed().to_string(),
+            ListColumn::Rate if rate > 1.0 => cell.green().to_string(),
+            ListColumn::Rate if rate < 1.0 => cell.yellow().to_string(),
+            ListColumn::Rate | ListColumn::Inverse => cell,
+        }
+    })
+}
+
+/// The `matrix` grid of [`rate_matrix`](crate::api::rate_matrix) cells: a header of the
+/// column codes, then one row per code, with `—` where no rate could be had. When
+/// `color`, the codes are bold and the diagonal dimmed.
+pub fn matrix_table(codes: &[String], cells: &[Vec<Option<f64>>], color: bool) -> Vec<String> {
+    let mut headers = vec![("", Align::Left)];
+    headers.extend(codes.iter().map(|code| (code.as_str(), Align::Right)));
+    let mut table = Table::new(&headers);
+    for (code, row) in codes.iter().zip(cells) {
+        let mut cells = vec![code.clone()];
+        cells.extend(row.iter().map(|cell| match cell {
+            Some(rate) => format_rate(*rate, 4),
+            None => "—".to_string(),
+        }));
+        table.push(cells);
+    }
+    table.lines(|column, row, cell| match (color, row) {
+        (false, _) => cell,
+        (true, None) => cell.bold().to_string(),
+        (true, Some(_)) if column == 0 => cell.cyan().bold().to_string(),
+        (true, Some(row)) if column == row + 1 => cell.dimmed().to_string(),
+        (true, Some(_)) => cell,
+    })
+}
+
+/// `--json` form of `matrix`: `{"EUR": {"EUR": 1.0, "USD": 1.08}, ...}`, with null where
+/// no rate could be had.
+pub fn matrix_json(codes: &[String], cells: &[Vec<Option<f64>>]) -> Value {
+    let mut document = serde_json::Map::new();
+    for (from, row) in codes.iter().zip(cells) {
+        let row: serde_json::Map<String, Value> = codes
+            .iter()
+            .zip(row)
+            .map(|(to, rate)| (to.clone(), (*rate).into()))
+            .collect();
+        document.insert(from.clone(), row.into());
+    }
+    document.into()
+}
+
+/// The `quote` table: a row per provider with its rate, `amount` converted at it when
+/// given, and which provider is best and worst, then the spread between the two. A
+/// provider that failed is shown as unavailable, with the reason. With `color`, the best
+/// row is tinted green, the worst red and unavailable ones dimmed.
+pub fn quote_table(
+    from: &str,
+    to: &str,
+    amount: Option<Decimal>,
+    quotes: &[ProviderQuote],
+    format: &OutputFormat,
+) -> Vec<String> {
+    let precision = amount_precision(to, format.precision);
+    let spread = quote_spread(quotes);
+    let converted = |rate: f64, amount: Decimal| {
+        apply_fee(amount, rate, format.fee, precision, format.round_mode).after_fee
+    };
+    let mut headers = vec![("Provider", Align::Left), ("Rate", Align::Right)];
+    if amount.is_some() {
+        headers.push((to, Align::Right));
+    }
+    headers.push(("", Align::Left));
+    let mut table = Table::new(&headers);
+    let mut marks = Vec::new();
+    for quote in quotes {
+        let mut row = vec![quote.provider.to_string()];
+        let mark = match &quote.rate {
+            Ok(rate) => {
+                row.push(format.numbers.apply(&format_rate(*rate, precision)));
+                if let Some(amount) = amount {
+                    let value = converted(*rate, amount);
+                    row.push(format.numbers.apply(&format!("{:.precision$}", value)));
+                }
+                match &spread {
+                    Some(spread) if spread.best.0 == quote.provider => "best".to_string(),
+                    Some(spread) if spread.worst.0 == quote.provider => "worst".to_string(),
+                    _ => String::new(),
+                }
+            }
+            Err(e) => {
+                row.extend(std::iter::repeat_n("-".to_string(), headers.len() - 2));
+                format!("unavailable ({})", e)
+            }
+        };
+        row.push(mark.clone());
+        marks.push(mark);
+        table.push(row);
+    }
+    let lines = table.lines(|_, row, cell| {
+        if !format.color {
+            return cell;
+        }
+        match row.map(|row| marks[row].as_str()) {
+            None => cell.bold().to_string(),
+            Some("best") => cell.green().to_string(),
+            Some("worst") => cell.red().to_string(),
+            Some(mark) if mark.starts_with("unavailable") => cell.dimmed().to_string(),
+            Some(_) => cell,
+        }
+    });
+    // Rows with nothing in the last column would otherwise end in the gap before it.
+    let mut lines: Vec<String> = lines
+        .into_iter()
+        .map(|line| line.trim_end().to_string())
+        .collect();
+    let Some(spread) = spread else {
+        return lines;
+    };
+    let mut footer = format!(
+        "Spread: {} {} ({:.2}%) between {} and {}",
+        format
+            .numbers
+            .apply(&format_rate(spread.absolute, precision)),
+        to,
+        spread.percent,
+        spread.best.0,
+        spread.worst.0
+    );
+    if let Some(amount) = amount {
+        let difference = converted(spread.best.1, amount) - converted(spread.worst.1, amount);
+        footer.push_str(&format!(
+            ", {} {} on {} {}",
+            format.numbers.apply(&format!("{:.precision$}", difference)),
+            to,
+            format.numbers.apply(&amount.to_string()),
+            from
+        ));
+    }
+    lines.push(footer);
+    lines
+}
+
+/// `--json` form of `quote`: a `providers` array with each provider's rate and `converted`
+/// amount, or its `error`, and a `summary` of the best, the worst and the spread, which is
+/// null when fewer than two providers answered.
+pub fn quote_json(
+    from: &str,
+    to: &str,
+    amount: Option<Decimal>,
+    quotes: &[ProviderQuote],
+    format: &OutputFormat,
+) -> Value {
+    let precision = amount_precision(to, format.precision);
+    let converted = |rate: f64| {
+        amount.map(|amount| {
+            apply_fee(amount, rate, format.fee, precision, format.round_mode).after_fee
+        })
+    };
+    let providers: Vec<Value> = quotes
+        .iter()
+        .map(|quote| match &quote.rate {
+            Ok(rate) => {
+                let mut result = json!({ "provider": quote.provider, "rate": rate });
+                if let Some(converted) = converted(*rate) {
+                    result["converted"] = converted.to_f64().into();
+                }
+                result
+            }
+            Err(e) => json!({
+                "provider": quote.provider,
+                "error": error_json(e.exit_code(), &e.to_string())["error"],
+            }),
+        })
+        .collect();
+    let summary = quote_spread(quotes).map(|spread| {
+        let mut summary = json!({
+            "best": spread.best.0,
+            "worst": spread.worst.0,
+            "spread": spread.absolute,
+            "spread_percent": spread.percent,
+        });
+        if let (Some(best), Some(worst)) = (converted(spread.best.1), converted(spread.worst.1)) {
+            summary["converted_spread"] = (best - worst).to_f64().into();
+        }
+        summary
+    });
+    let mut document = json!({
+        "from": from,
+        "to": to,
+        "providers": providers,
+        "summary": summary,
+    });
+    if let Some(amount) = amount {
+        document["amount"] = amount.to_f64().into();
+    }
+    document
+}
+
+/// One `alert` check: the rate fetched at `checked_at` and what the alert is waiting for.
+pub fn alert_status_line(
+    checked_at: &str,
+    from: &str,
+    to: &str,
+    rate: f64,
+    threshold: &Threshold,
+) -> String {
+    format!(
+        "[{}] {}/{} is {}, alerting {}",
+        checked_at,
+        from,
+        to,
+        format_rate(rate, 0),
+        threshold
+    )
+}
+
+/// The message of a fired `alert`, bold when `color`.
+pub fn alert_line(from: &str, to: &str, rate: f64, crossed: &str, color: bool) -> String {
+    let line = format!(
+        "ALERT: {}/{} is {}, {}",
+        from,
+        to,
+        format_rate(rate, 0),
+        crossed
+    );
+    if color {
+        line.bold().yellow().to_string()
+    } else {
+        line
+    }
+}
+
+/// A rate asked for on `date`, with the day the provider actually quoted when that is
+/// an earlier one, as on weekends: `2024-03-02  4.3123 (rate of 2024-03-01)`.
+fn dated_rate(date: NaiveDate, quote: &RateQuote) -> String {
+    let rate = format_rate(quote.rate, 0);
+    match &quote.effective_date {
+        Some(effective) if *effective != date.to_string() => {
+            format!("{}  {} (rate of {})", date, rate, effective)
+        }
+        _ => format!("{}  {}", date, rate),
+    }
+}
+
+/// How `from`/`to` moved between the `start` and `end` quotes of `change`: both rates,
+/// then the absolute and percentage change with an arrow, tinted by direction if `color`.
+pub fn change_lines(
+    from: &str,
+    to: &str,
+    start: (NaiveDate, &RateQuote),
+    end: (NaiveDate, &RateQuote),
+    color: bool,
+) -> Vec<String> {
+    let delta = end.1.rate - start.1.rate;
+    let arrow = if delta > 0.0 {
+        "▲"
+    } else if delta < 0.0 {
+        "▼"
+    } else {
+        "="
+    };
+    let sign = if delta > 0.0 { "+" } else { "" };
+    let mut change = format!("{} {}{}", arrow, sign, format_rate(delta, 0));
+    match rate_change(end.1, Some(start.1)) {
+        Some(_) if delta == 0.0 => change.push_str(" (0.00%)"),
+        Some(percent) => change.push_str(&format!(" ({:+.2}%)", percent)),
+        None => {}
+    }
+    vec![
+        format!("{}/{}", from, to),
+        format!("  {}", dated_rate(start.0, start.1)),
+        format!("  {}", dated_rate(end.0, end.1)),
+        format!("  {}", tint_change(change, delta, color)),
+    ]
+}
+
+/// `change_lines` as a JSON object. `change_percent` is null when the start rate is zero.
+pub fn change_json(
+    from: &str,
+    to: &str,
+    start: (NaiveDate, &RateQuote),
+    end: (NaiveDate, &RateQuote),
+) -> Value {
+    let day = |(date, quote): (NaiveDate, &RateQuote)| {
+        json!({
+            "date": date.to_string(),
+            "effective_date": quote.effective_date.clone().unwrap_or_else(|| date.to_string()),
+            "rate": quote.rate,
+        })
+    };
+    json!({
+        "from": from,
+        "to": to,
+        "start": day(start),
+        "end": day(end),
+        "change": end.1.rate - start.1.rate,
+        "change_percent": rate_change(end.1, Some(start.1)),
+    })
+}
+
+/// One `history` entry: `2024-03-01 13:00:00  100 USD = 398.00 PLN at 3.98 (exchangerate-api, live)`.
+pub fn history_line(record: &HistoryRecord) -> String {
+    let when = match chrono::DateTime::parse_from_rfc3339(&record.timestamp) {
+        Ok(timestamp) => timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+        Err(_) => record.timestamp.clone(),
+    };
+    let fee = if record.fee_percent.is_zero() {
+        String::new()
+    } else {
+        format!(" after a {}% fee", record.fee_percent)
+    };
+    let kind = if record.pinned {
+        "pinned"
+    } else if record.cached {
+        "cached"
+    } else {
+        "live"
+    };
+    let source = match record.provider.as_str() {
+        "" => kind.to_string(),
+        provider => format!("{}, {}", provider, kind),
+    };
+    format!(
+        "{}  {} {} = {} {} at {}{} ({})",
+        when,
+        record.amount,
+        record.from,
+        record.converted,
+        record.to,
+        format_rate(record.rate, 0),
+        fee,
+        source
+    )
+}
+
+/// The `rate list` table: each pinned pair, its rate and when it stops applying.
+pub fn override_lines(overrides: &RateOverrides, today: NaiveDate) -> Vec<String> {
+    overrides
+        .iter()
+        .map(|(pair, rate)| {
+            let expiry = match &rate.expires {
+                _ if rate.is_expired(today) => {
+                    format!("expired {}", rate.expires.as_deref().unwrap_or_default())
+                }
+                Some(expires) => format!("until {}", expires),
+                None => "until unset".to_string(),
+            };
+            format!("{}  {}  ({})", pair, format_rate(rate.rate, 0), expiry)
+        })
+        .collect()
+}
+
+/// `--json` form of `rate list`.
+pub fn overrides_json(overrides: &RateOverrides, today: NaiveDate) -> Value {
+    overrides
+        .iter()
+        .map(|(pair, rate)| {
+            json!({
+                "pair": pair,
+                "rate": rate.rate,
+                "expires": rate.expires,
+                "expired": rate.is_expired(today),
+            })
+        })
+        .collect()
+}
+
+/// The `alias list` rows: each alias, padded to the longest, and what it stands for, with
+/// the code at the end of the chain when it names another alias.
+pub fn alias_lines(aliases: &Aliases) -> Vec<String> {
+    let width = aliases
+        .iter()
+        .map(|(name, _)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    aliases
+        .iter()
+        .map(|(name, target)| {
+            let code = aliases.resolve(name).unwrap_or_default();
+            if code == target {
+                format!("{:<width$}  {}", name, target)
+            } else {
+                format!("{:<width$}  {} ({})", name, target, code)
+            }
+        })
+        .collect()
+}
+
+/// `--json` form of `alias list`.
+pub fn aliases_json(aliases: &Aliases) -> Value {
+    aliases
+        .iter()
+        .map(|(name, target)| {
+            json!({
+                "name": name,
+                "target": target,
+                "code": aliases.resolve(name).ok(),
+            })
+        })
+        .collect()
+}
+
+pub fn describe_source(quote: &RateQuote) -> String {
+    let kind = if quote.pinned {
+        "pinned rate"
+    } else if quote.stale {
+        "stale cached rate"
+    } else if quote.cached {
+        "cached rate"
+    } else {
+        "live rate"
+    };
+    let mut provider = match quote.provider.as_str() {
+        "" => String::new(),
+        provider => format!(" from {}", provider),
+    };
+    if let Some(date) = &quote.effective_date {
+        provider.push_str(&format!(" as of {}", date));
+    }
+    let derived = match &quote.derived_via {
+        Some(via) => format!(", derived via {}", via),
+        None => String::new(),
+    };
+    // A pinned rate has no age worth telling.
+    if quote.pinned {
+        return format!("{}{}", kind, derived);
+    }
+    format!(
+        "{}{}{}, {} old",
+        kind,
+        provider,
+        derived,
+        describe_age(quote.age)
+    )
+}
@@ -0,0 +1,159 @@
+//! Rates pinned with `rate set`, such as a fixed internal rate for the quarter. An active
+//! override answers a conversion before the cache or the network are asked.
+
+use crate::config::OVERRIDES_FILE;
+use crate::models::RateQuote;
+use chrono::NaiveDate;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// One pinned rate.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RateOverride {
+    pub rate: f64,
+    /// Last day the override applies, as `YYYY-MM-DD`; `None` keeps it until unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+}
+
+impl RateOverride {
+    /// Parses the rate and `--expires` day given to `rate set` on `today`. The rate must be
+    /// positive, and the day `YYYY-MM-DD` and not already past.
+    pub fn parse(rate: &str, expires: Option<&str>, today: NaiveDate) -> Result<Self, String> {
+        let rate = rate
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|rate| rate.is_finite() && *rate > 0.0)
+            .ok_or_else(|| format!("invalid rate '{}', expected a positive number", rate))?;
+        let expires = match expires {
+            Some(expires) => {
+                let date = NaiveDate::parse_from_str(expires.trim(), "%Y-%m-%d")
+                    .map_err(|_| format!("invalid date '{}', expected YYYY-MM-DD", expires))?;
+                if date < today {
+                    return Err(format!("--expires {} is already past", date));
+                }
+                Some(date.to_string())
+            }
+            None => None,
+        };
+        Ok(RateOverride { rate, expires })
+    }
+
+    /// Whether the override no longer applies on `today`. An expiry date that doesn't
+    /// parse counts as expired, so a hand-edited typo can't pin a rate forever.
+    pub fn is_expired(&self, today: NaiveDate) -> bool {
+        self.expires.as_deref().is_some_and(|expires| {
+            NaiveDate::parse_from_str(expires, "%Y-%m-%d").map_or(true, |expires| expires < today)
+        })
+    }
+}
+
+/// Every pinned rate, keyed by pair as `EUR/PLN`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct RateOverrides(BTreeMap<String, RateOverride>);
+
+impl RateOverrides {
+    /// Pins `from`->`to` at `rate`, replacing any earlier override of the pair.
+    pub fn set(&mut self, from: &str, to: &str, rate: RateOverride) {
+        self.0.insert(pair_key(from, to), rate);
+    }
+
+    /// Drops the override of `from`->`to`, returning whether there was one. The inverse
+    /// pair keeps its own.
+    pub fn unset(&mut self, from: &str, to: &str) -> bool {
+        self.0.remove(&pair_key(from, to)).is_some()
+    }
+
+    pub fn get(&self, from: &str, to: &str) -> Option<&RateOverride> {
+        self.0.get(&pair_key(from, to))
+    }
+
+    /// The overrides in pair order, as `(EUR/PLN, override)`.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &RateOverride)> {
+        self.0.iter().map(|(pair, rate)| (pair.as_str(), rate))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The pinned `from`->`to` rate on `today`: the pair's own override, or else the
+    /// inverse of the `to`->`from` one, marked as derived via `to`. Expired overrides are
+    /// skipped with a warning.
+    pub fn quote(&self, from: &str, to: &str, today: NaiveDate) -> Option<RateQuote> {
+        let active = |from: &str, to: &str| {
+            let rate = self.get(from, to)?;
+            if rate.is_expired(today) {
+                warn!(
+                    "ignoring the {}/{} override of {}, which expired on {}",
+                    from,
+                    to,
+                    rate.rate,
+                    rate.expires.as_deref().unwrap_or_default()
+                );
+                return None;
+            }
+            Some(rate.rate)
+        };
+        let (rate, derived_via) = match active(from, to) {
+            Some(rate) => (rate, None),
+            None => (1.0 / active(to, from)?, Some(to.to_string())),
+        };
+        Some(RateQuote {
+            rate,
+            age: Duration::ZERO,
+            cached: false,
+            stale: false,
+            provider: String::new(),
+            derived_via,
+            effective_date: None,
+            pinned: true,
+        })
+    }
+}
+
+fn pair_key(from: &str, to: &str) -> String {
+    format!("{}/{}", from, to)
+}
+
+/// The override file, `rate_overrides.json` beside the cache file at `cache_file`.
+pub fn overrides_path(cache_file: &Path) -> PathBuf {
+    cache_file.with_file_name(OVERRIDES_FILE)
+}
+
+/// Reads the overrides at `path`; a missing file has none. Unlike the cache, a corrupt
+/// file is an error: dropping its pins would silently convert at market rates.
+pub fn load_overrides(path: &Path) -> Result<RateOverrides, String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(RateOverrides::default()),
+        Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+    };
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("invalid rate overrides {}: {}", path.display(), e))
+}
+
+/// Writes `overrides` to `path`, creating its directory. With no overrides left the file
+/// is removed instead.
+pub fn save_overrides(path: &Path, overrides: &RateOverrides) -> Result<(), String> {
+    let result = if overrides.is_empty() {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    } else {
+        let contents = serde_json::to_string_pretty(overrides).expect("rate overrides serialize");
+        path.parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(path, contents))
+    };
+    result.map_err(|e| format!("cannot write {}: {}", path.display(), e))
+}
@@ -0,0 +1,353 @@
+use crate::api::ApiClient;
+use crate::codes::crypto_id;
+use crate::config::{
+    COINGECKO_URL, EXCHANGERATE_API_URL, EXCHANGERATE_API_V6_URL, FRANKFURTER_URL, OPEN_ER_API_URL,
+};
+use crate::error::CurrencyError;
+use crate::models::Rates;
+use async_trait::async_trait;
+use chrono::{Datelike, NaiveDate};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fmt::Debug,
+    sync::Arc,
+};
+
+/// A source of exchange rates. [`ApiClient`] asks its providers in order, moving on to the
+/// next one when a provider is over its quota or failing.
+#[async_trait]
+pub trait RateProvider: Debug + Send + Sync {
+    /// Name used by `--provider`, the config file, cache entries and output.
+    fn name(&self) -> &'static str;
+
+    /// Latest rates quoted against `base`, requested through `client`.
+    async fn latest(&self, client: &ApiClient, base: &str) -> Result<Rates, CurrencyError>;
+
+    /// Whether requests fail without an API key given to the [`ApiClient`].
+    fn needs_api_key(&self) -> bool {
+        false
+    }
+
+    /// Whether [`RateProvider::historical`] is supported.
+    fn has_history(&self) -> bool {
+        false
+    }
+
+    /// Rates quoted against `base` on `date`.
+    async fn historical(
+        &self,
+        _client: &ApiClient,
+        _base: &str,
+        _date: NaiveDate,
+    ) -> Result<Rates, CurrencyError> {
+        Err(CurrencyError::Provider(format!(
+            "{} has no historical rates",
+            self.name()
+        )))
+    }
+
+    /// Whether [`RateProvider::currencies`] is supported.
+    fn has_code_list(&self) -> bool {
+        false
+    }
+
+    /// The codes the provider quotes, with their names.
+    async fn currencies(
+        &self,
+        _client: &ApiClient,
+    ) -> Result<BTreeMap<String, String>, CurrencyError> {
+        Err(CurrencyError::Provider(format!(
+            "{} publishes no list of currencies",
+            self.name()
+        )))
+    }
+}
+
+/// What `/codes` answers: `[["AED", "UAE Dirham"], ...]`.
+#[derive(Deserialize)]
+struct SupportedCodes {
+    supported_codes: Vec<(String, String)>,
+}
+
+/// ExchangeRate-API, the default. Needs an API key. Latest rates come from its v4 API with
+/// the key as a parameter, history and the code list from v6 with the key in the path.
+#[derive(Debug)]
+pub struct ExchangeRateApi {
+    base_url: String,
+    v6_url: String,
+}
+
+impl ExchangeRateApi {
+    /// Requests go to `base_url` (e.g. a local test server) instead of the public API,
+    /// v6 requests included.
+    pub fn new(base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/').to_string();
+        ExchangeRateApi {
+            v6_url: base_url.clone(),
+            base_url,
+        }
+    }
+
+    /// `{v6}/{key}/{path}`.
+    fn v6(&self, client: &ApiClient, path: &str) -> Result<String, CurrencyError> {
+        let api_key = client.key().ok_or(CurrencyError::MissingApiKey)?;
+        Ok(format!("{}/{}/{}", self.v6_url, api_key, path))
+    }
+
+    async fn get(
+        &self,
+        client: &ApiClient,
+        base: &str,
+        path: &str,
+    ) -> Result<Rates, CurrencyError> {
+        let api_key = client.key().ok_or(CurrencyError::MissingApiKey)?;
+        let url = format!("{}/{}?access_key={}", self.base_url, path, api_key);
+        client.get_rates(&url, base).await
+    }
+}
+
+impl Default for ExchangeRateApi {
+    /// The public API, or `API_BASE_URL` when that is set.
+    fn default() -> Self {
+        match env::var("API_BASE_URL") {
+            Ok(url) if !url.is_empty() => ExchangeRateApi::new(&url),
+            _ => ExchangeRateApi {
+                base_url: EXCHANGERATE_API_URL.to_string(),
+                v6_url: EXCHANGERATE_API_V6_URL.to_string(),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl RateProvider for ExchangeRateApi {
+    fn name(&self) -> &'static str {
+        "exchangerate-api"
+    }
+
+    fn needs_api_key(&self) -> bool {
+        true
+    }
+
+    async fn latest(&self, client: &ApiClient, base: &str) -> Result<Rates, CurrencyError> {
+        self.get(client, base, &format!("latest/{}", base)).await
+    }
+
+    fn has_history(&self) -> bool {
+        true
+    }
+
+    async fn historical(
+        &self,
+        client: &ApiClient,
+        base: &str,
+        date: NaiveDate,
+    ) -> Result<Rates, CurrencyError> {
+        let path = format!(
+            "history/{}/{}/{}/{}",
+            base,
+            date.year(),
+            date.month(),
+            date.day()
+        );
+        client.get_rates(&self.v6(client, &path)?, base).await
+    }
+
+    fn has_code_list(&self) -> bool {
+        true
+    }
+
+    async fn currencies(
+        &self,
+        client: &ApiClient,
+    ) -> Result<BTreeMap<String, String>, CurrencyError> {
+        let body: SupportedCodes = client.get_json(&self.v6(client, "codes")?, "all").await?;
+        Ok(body.supported_codes.into_iter().collect())
+    }
+}
+
+/// The free open.er-api.com service. Latest rates only, no key needed.
+#[derive(Debug)]
+pub struct OpenErApi {
+    base_url: String,
+}
+
+impl OpenErApi {
+    pub fn new(base_url: &str) -> Self {
+        OpenErApi {
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl Default for OpenErApi {
+    fn default() -> Self {
+        OpenErApi::new(OPEN_ER_API_URL)
+    }
+}
+
+#[async_trait]
+impl RateProvider for OpenErApi {
+    fn name(&self) -> &'static str {
+        "open-er-api"
+    }
+
+    async fn latest(&self, client: &ApiClient, base: &str) -> Result<Rates, CurrencyError> {
+        let url = format!("{}/latest/{}", self.base_url, base);
+        client.get_rates(&url, base).await
+    }
+}
+
+/// Frankfurter, which publishes the European Central Bank's reference rates. No key needed.
+#[derive(Debug)]
+pub struct Frankfurter {
+    base_url: String,
+}
+
+impl Frankfurter {
+    pub fn new(base_url: &str) -> Self {
+        Frankfurter {
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl Default for Frankfurter {
+    fn default() -> Self {
+        Frankfurter::new(FRANKFURTER_URL)
+    }
+}
+
+#[async_trait]
+impl RateProvider for Frankfurter {
+    fn name(&self) -> &'static str {
+        "frankfurter"
+    }
+
+    async fn latest(&self, client: &ApiClient, base: &str) -> Result<Rates, CurrencyError> {
+        let url = format!("{}/latest?from={}", self.base_url, base);
+        client.get_rates(&url, base).await
+    }
+
+    fn has_history(&self) -> bool {
+        true
+    }
+
+    async fn historical(
+        &self,
+        client: &ApiClient,
+        base: &str,
+        date: NaiveDate,
+    ) -> Result<Rates, CurrencyError> {
+        let url = format!("{}/{}?from={}", self.base_url, date, base);
+        client.get_rates(&url, base).await
+    }
+
+    fn has_code_list(&self) -> bool {
+        true
+    }
+
+    /// `/currencies` answers with a map of code to name.
+    async fn currencies(
+        &self,
+        client: &ApiClient,
+    ) -> Result<BTreeMap<String, String>, CurrencyError> {
+        let url = format!("{}/currencies", self.base_url);
+        client.get_json(&url, "all").await
+    }
+}
+
+/// CoinGecko's simple price API, which prices crypto assets in USD. No key needed. It is not
+/// part of the fallback chain: [`ApiClient`] asks it for either side of a pair that is a
+/// crypto asset.
+#[derive(Debug)]
+pub struct CoinGecko {
+    base_url: String,
+}
+
+impl CoinGecko {
+    pub fn new(base_url: &str) -> Self {
+        CoinGecko {
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl Default for CoinGecko {
+    fn default() -> Self {
+        CoinGecko::new(COINGECKO_URL)
+    }
+}
+
+#[async_trait]
+impl RateProvider for CoinGecko {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    /// The USD price of the crypto asset `base`, as its single rate.
+    async fn latest(&self, client: &ApiClient, base: &str) -> Result<Rates, CurrencyError> {
+        let unsupported = || CurrencyError::UnsupportedCrypto(base.to_string());
+        let id = crypto_id(base).ok_or_else(unsupported)?;
+        let url = format!(
+            "{}/simple/price?ids={}&vs_currencies=usd",
+            self.base_url, id
+        );
+        // Unknown ids get an empty object, or a 404 from some mirrors.
+        let prices: HashMap<String, HashMap<String, f64>> = match client.get_json(&url, base).await
+        {
+            Err(
+                CurrencyError::Status(StatusCode::NOT_FOUND)
+                | CurrencyError::Api {
+                    status: StatusCode::NOT_FOUND,
+                    ..
+                },
+            ) => return Err(unsupported()),
+            result => result?,
+        };
+        let price = prices
+            .get(id)
+            .and_then(|price| price.get("usd"))
+            .copied()
+            .ok_or_else(unsupported)?;
+        Ok(Rates::from_map(HashMap::from([("USD".to_string(), price)])))
+    }
+}
+
+/// Every built-in provider, in the order they are tried after the selected one.
+pub fn all_providers() -> Vec<Arc<dyn RateProvider>> {
+    vec![
+        Arc::new(ExchangeRateApi::default()),
+        Arc::new(OpenErApi::default()),
+        Arc::new(Frankfurter::default()),
+    ]
+}
+
+/// The names accepted by `--provider`.
+pub fn provider_names() -> Vec<&'static str> {
+    all_providers()
+        .iter()
+        .map(|provider| provider.name())
+        .collect()
+}
+
+/// The built-in providers with `name` first, or an error naming the valid choices.
+pub fn provider_chain(name: &str) -> Result<Vec<Arc<dyn RateProvider>>, String> {
+    let mut providers = all_providers();
+    let index = providers
+        .iter()
+        .position(|provider| provider.name() == name)
+        .ok_or_else(|| {
+            format!(
+                "unknown provider '{}' (available: {})",
+                name,
+                provider_names().join(", ")
+            )
+        })?;
+    let primary = providers.remove(index);
+    providers.insert(0, primary);
+    Ok(providers)
+}
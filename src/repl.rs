@@ -0,0 +1,87 @@
+use crate::aliases::Aliases;
+use crate::models::parse_amount;
+use rust_decimal::Decimal;
+
+/// One line typed into the `interactive` session.
+#[derive(Debug, PartialEq)]
+pub enum ReplCommand {
+    /// `AMOUNT FROM TO`, e.g. `100 USD EUR`.
+    Convert {
+        amount: Decimal,
+        from: String,
+        to: String,
+    },
+    /// A bare amount, converted with the previous pair.
+    Amount(Decimal),
+    /// `list [BASE]`.
+    List(Option<String>),
+    /// Reverses the previous pair and converts its amount back.
+    Swap,
+    Help,
+    Quit,
+    /// A blank line.
+    Empty,
+}
+
+impl ReplCommand {
+    /// The command with any alias among its currencies replaced by the code it stands for.
+    pub fn resolve(self, aliases: &Aliases) -> Result<ReplCommand, String> {
+        Ok(match self {
+            ReplCommand::Convert { amount, from, to } => ReplCommand::Convert {
+                amount,
+                from: aliases.resolve(&from)?,
+                to: aliases.resolve(&to)?,
+            },
+            ReplCommand::List(Some(base)) => ReplCommand::List(Some(aliases.resolve(&base)?)),
+            command => command,
+        })
+    }
+}
+
+pub const REPL_HELP: &str = "  AMOUNT FROM TO   convert, e.g. 100 USD EUR or 12.5*3 USD EUR
+  AMOUNT           convert with the previous pair
+  swap             reverse the previous pair
+  list [BASE]      show every rate for BASE (default: the previous source currency)
+  help             show this help
+  quit             leave (also exit or Ctrl-D)";
+
+/// Parses one line of the `interactive` session. The amount may be an expression, and the
+/// currency codes are upper-cased but not validated here.
+pub fn parse_repl_line(line: &str) -> Result<ReplCommand, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [] => Ok(ReplCommand::Empty),
+        [word] if word.eq_ignore_ascii_case("help") || *word == "?" => Ok(ReplCommand::Help),
+        [word]
+            if ["quit", "exit"]
+                .iter()
+                .any(|cmd| word.eq_ignore_ascii_case(cmd)) =>
+        {
+            Ok(ReplCommand::Quit)
+        }
+        [word] if word.eq_ignore_ascii_case("swap") => Ok(ReplCommand::Swap),
+        [word, rest @ ..] if word.eq_ignore_ascii_case("list") => match rest {
+            [] => Ok(ReplCommand::List(None)),
+            [base] => Ok(ReplCommand::List(Some(base.to_uppercase()))),
+            _ => Err("expected list [BASE]".to_string()),
+        },
+        [amount @ .., from, to] if is_code(from) && is_code(to) && !amount.is_empty() => {
+            Ok(ReplCommand::Convert {
+                amount: parse_amount(&amount.concat())?,
+                from: from.to_uppercase(),
+                to: to.to_uppercase(),
+            })
+        }
+        _ => match parse_amount(&words.concat()) {
+            Ok(amount) => Ok(ReplCommand::Amount(amount)),
+            Err(_) => Err(format!(
+                "expected AMOUNT FROM TO, AMOUNT, swap, list, help or quit but got '{}'",
+                line.trim()
+            )),
+        },
+    }
+}
+
+fn is_code(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|c| c.is_ascii_alphabetic())
+}
@@ -0,0 +1,367 @@
+//! `serve`: a local HTTP API answering from one shared cache, so other tools on the machine
+//! need neither their own cache nor an API key.
+
+use crate::api::{offline_rates, ApiClient};
+use crate::cache::{prune_cache, save_pruned_cache_to, PrunePolicy};
+use crate::codes::validate_currency_code;
+use crate::config::CACHE_FLUSH_INTERVAL;
+use crate::error::{
+    CurrencyError, EXIT_FAILURE, EXIT_INVALID_INPUT, EXIT_NOT_CACHED, EXIT_UNKNOWN_CURRENCY,
+};
+use crate::models::{parse_amount, CacheItem, Rates, SortKey, SortOrder};
+use crate::output::{conversion_json, error_json, rates_json, OutputFormat};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fs,
+    future::Future,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, OwnedMutexGuard},
+};
+
+/// The endpoints, as `serve --help` and unknown paths list them.
+pub const ENDPOINTS: &str = "GET /convert?from=USD&to=PLN&amount=100, GET /rates/{base}";
+
+/// The state behind `serve`. The cache is only locked to read or store entries, never while
+/// a provider is asked, and requests fetching the same base take turns, so two at once make
+/// a single fetch.
+pub struct Server {
+    api: ApiClient,
+    cache: Mutex<HashMap<String, CacheItem>>,
+    /// One lock per base being fetched.
+    fetches: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    max_age: Duration,
+    format: OutputFormat,
+    cache_file: Option<PathBuf>,
+    offline: bool,
+    validate: bool,
+    prune: PrunePolicy,
+}
+
+impl Server {
+    /// A server fetching through `api`, starting from `cache` and treating latest rates as
+    /// fresh for `max_age`. Amounts are rounded as `format` says.
+    pub fn new(
+        api: ApiClient,
+        cache: HashMap<String, CacheItem>,
+        max_age: Duration,
+        format: OutputFormat,
+    ) -> Self {
+        Server {
+            api,
+            cache: Mutex::new(cache),
+            fetches: Mutex::new(HashMap::new()),
+            max_age,
+            format,
+            cache_file: None,
+            offline: false,
+            validate: true,
+            prune: PrunePolicy::NONE,
+        }
+    }
+
+    /// Merges the cache into the file at `path` every [`CACHE_FLUSH_INTERVAL`] and on
+    /// shutdown; without one nothing is written.
+    pub fn cache_file(mut self, path: Option<PathBuf>) -> Self {
+        self.cache_file = path;
+        self
+    }
+
+    /// Answers from the cache alone, however old, as `--offline` does.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// What writing the cache out drops; by default nothing.
+    pub fn prune(mut self, policy: PrunePolicy) -> Self {
+        self.prune = policy;
+        self
+    }
+
+    /// Whether currency codes are checked against ISO 4217 before anything is fetched.
+    pub fn validate(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
+
+    /// Serves connections from `listener` until `shutdown` completes, then writes the cache
+    /// out a last time.
+    pub async fn run(self: Arc<Self>, listener: TcpListener, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+        let mut flush = tokio::time::interval(CACHE_FLUSH_INTERVAL);
+        // The first tick is immediate, and nothing has changed yet.
+        flush.tick().await;
+        let mut saved = self.newest_entry().await;
+        loop {
+            tokio::select! {
+                () = &mut shutdown => break,
+                _ = flush.tick() => saved = self.flush(saved).await,
+                accepted = listener.accept() => {
+                    let (stream, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!("cannot accept a connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let server = Arc::clone(&self);
+                    tokio::spawn(async move {
+                        let service = service_fn(|request| {
+                            let server = Arc::clone(&server);
+                            async move { Ok::<_, Infallible>(server.respond(request).await) }
+                        });
+                        if let Err(e) = http1::Builder::new()
+                            .serve_connection(TokioIo::new(stream), service)
+                            .await
+                        {
+                            debug!("connection from {} failed: {}", peer, e);
+                        }
+                    });
+                }
+            }
+        }
+        info!("shutting down");
+        self.flush(saved).await;
+    }
+
+    /// Writes the cache out if an entry is newer than `saved`, returning the newest one.
+    async fn flush(&self, saved: Option<SystemTime>) -> Option<SystemTime> {
+        let newest = self.newest_entry().await;
+        let Some(path) = &self.cache_file else {
+            return newest;
+        };
+        if newest <= saved {
+            return saved;
+        }
+        // The entries in memory are pruned too, so a long-running server stays within the caps.
+        let cache = {
+            let mut cache = self.cache.lock().await;
+            prune_cache(&mut cache, &self.prune, SystemTime::now());
+            cache.clone()
+        };
+        let path = path.clone();
+        let policy = self.prune.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+            save_pruned_cache_to(&path, &cache, &policy)
+        })
+        .await
+        .expect("saving the cache doesn't panic");
+        match result {
+            Ok(_) => newest,
+            Err(e) => {
+                warn!("{}", e);
+                saved
+            }
+        }
+    }
+
+    async fn newest_entry(&self) -> Option<SystemTime> {
+        self.cache
+            .lock()
+            .await
+            .values()
+            .map(|item| item.timestamp)
+            .max()
+    }
+
+    async fn respond(&self, request: Request<Incoming>) -> Response<Full<Bytes>> {
+        let started = Instant::now();
+        let target = request.uri().to_string();
+        let (status, body) = if request.method() == Method::GET {
+            self.handle(&target).await
+        } else {
+            (
+                StatusCode::METHOD_NOT_ALLOWED,
+                error_json(EXIT_INVALID_INPUT, "only GET requests are served"),
+            )
+        };
+        info!(
+            "{} {} {} ({}ms)",
+            request.method(),
+            target,
+            status.as_u16(),
+            started.elapsed().as_millis()
+        );
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body.to_string())))
+            .expect("the response is valid")
+    }
+
+    /// The status and JSON body for a GET of `target`, a path with an optional query.
+    async fn handle(&self, target: &str) -> (StatusCode, Value) {
+        let url = match reqwest::Url::parse(&format!("http://localhost{}", target)) {
+            Ok(url) => url,
+            Err(e) => return bad_request(format!("invalid request target: {}", e)),
+        };
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let segments: Vec<&str> = url
+            .path_segments()
+            .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        let result = match segments.as_slice() {
+            ["convert"] => self.convert(&query).await,
+            ["rates", base] => self.rates(&base.to_uppercase()).await,
+            _ => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    error_json(
+                        EXIT_INVALID_INPUT,
+                        &format!("no endpoint at {}; try {}", url.path(), ENDPOINTS),
+                    ),
+                )
+            }
+        };
+        result.unwrap_or_else(|e| e)
+    }
+
+    async fn convert(
+        &self,
+        query: &HashMap<String, String>,
+    ) -> Result<(StatusCode, Value), (StatusCode, Value)> {
+        let code = |name: &str| match query.get(name).map(|code| code.trim().to_uppercase()) {
+            Some(code) if !code.is_empty() => self.checked(code),
+            _ => Err(bad_request(format!("missing query parameter '{}'", name))),
+        };
+        let (from, to) = (code("from")?, code("to")?);
+        let amount = match query.get("amount") {
+            Some(amount) => parse_amount(amount).map_err(bad_request)?,
+            None => Decimal::ONE,
+        };
+        let quote = {
+            let cache = self.cache.lock().await;
+            if self.offline {
+                Some(self.api.offline_rate(&from, &to, &cache, None))
+            } else {
+                self.api
+                    .cached_rate(&from, &to, &cache, self.max_age, None)
+                    .map(Ok)
+            }
+        };
+        let quote = match quote {
+            Some(quote) => quote,
+            None => {
+                let (_turn, mut cache) = self.fetch_turn(&from).await;
+                let quote = self
+                    .api
+                    .fetch_exchange_rate(&from, &to, &mut cache, self.max_age, None)
+                    .await;
+                self.store(cache).await;
+                quote
+            }
+        }
+        .map_err(|e| failure(&e))?;
+        let mut document = conversion_json(amount, &from, &to, &quote, &self.format);
+        if quote.stale || self.offline {
+            document["age_seconds"] = quote.age.as_secs().into();
+        }
+        Ok((StatusCode::OK, document))
+    }
+
+    async fn rates(&self, base: &str) -> Result<(StatusCode, Value), (StatusCode, Value)> {
+        let base = self.checked(base.to_string())?;
+        let rates = {
+            let cache = self.cache.lock().await;
+            match offline_rates(&base, &cache, None) {
+                Ok(item) if self.offline || item.age() < self.max_age => {
+                    Some(Ok(item.rates.clone()))
+                }
+                Err(e) if self.offline => Some(Err(e)),
+                _ => None,
+            }
+        };
+        let rates = match rates {
+            Some(rates) => rates,
+            None => {
+                let (_turn, mut cache) = self.fetch_turn(&base).await;
+                let rates = self
+                    .api
+                    .fetch_base_rates(&base, &mut cache, self.max_age, None)
+                    .await;
+                self.store(cache).await;
+                rates
+            }
+        }
+        .map_err(|e| failure(&e))?;
+        let rates = Rates::from_map(rates);
+        let entries = rates.sorted_entries(SortKey::Code, SortOrder::Asc);
+        Ok((StatusCode::OK, rates_json(&base, &entries)))
+    }
+
+    /// Waits for any other fetch of `base` to finish, then returns a guard to hold while
+    /// fetching and a copy of the cache to fetch into. A request that waited finds the
+    /// rates the one before it stored.
+    async fn fetch_turn(&self, base: &str) -> (OwnedMutexGuard<()>, HashMap<String, CacheItem>) {
+        let turn = Arc::clone(
+            self.fetches
+                .lock()
+                .await
+                .entry(base.to_string())
+                .or_default(),
+        );
+        let turn = turn.lock_owned().await;
+        (turn, self.cache.lock().await.clone())
+    }
+
+    /// Stores the entries of `fetched`, a copy of the cache, that are newer than the cache's.
+    async fn store(&self, fetched: HashMap<String, CacheItem>) {
+        let mut cache = self.cache.lock().await;
+        for (key, item) in fetched {
+            if cache
+                .get(&key)
+                .is_none_or(|cached| cached.timestamp < item.timestamp)
+            {
+                cache.insert(key, item);
+            }
+        }
+    }
+
+    fn checked(&self, code: String) -> Result<String, (StatusCode, Value)> {
+        match validate_currency_code(&code) {
+            Err(e) if self.validate => Err(failure(&e)),
+            _ => Ok(code),
+        }
+    }
+}
+
+fn bad_request(message: String) -> (StatusCode, Value) {
+    (
+        StatusCode::BAD_REQUEST,
+        error_json(EXIT_INVALID_INPUT, &message),
+    )
+}
+
+/// The status a failed lookup is answered with: 404 for a currency without a rate, 503 for
+/// one not cached while offline, 504 for a provider timeout and 502 for any other upstream
+/// failure, with the body of `--json` errors.
+fn failure(error: &CurrencyError) -> (StatusCode, Value) {
+    let exit_code = error.exit_code();
+    let status = match (error, exit_code) {
+        (_, EXIT_UNKNOWN_CURRENCY) => StatusCode::NOT_FOUND,
+        (_, EXIT_NOT_CACHED) => StatusCode::SERVICE_UNAVAILABLE,
+        (_, EXIT_FAILURE) => StatusCode::INTERNAL_SERVER_ERROR,
+        (CurrencyError::Timeout(_), _) => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+    (status, error_json(exit_code, &error.to_string()))
+}
@@ -0,0 +1,99 @@
+//! Aligned text tables with a header row, as `list` prints on a terminal.
+
+/// Which side of its column a cell is padded to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// Space between two columns.
+const GAP: &str = "  ";
+
+/// A table built a row at a time and laid out once every cell is known.
+#[derive(Debug, Default)]
+pub struct Table {
+    headers: Vec<(String, Align)>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn new(headers: &[(&str, Align)]) -> Self {
+        Table {
+            headers: headers
+                .iter()
+                .map(|(header, align)| (header.to_string(), *align))
+                .collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    /// Adds a row with one cell per header.
+    pub fn push(&mut self, row: Vec<String>) {
+        debug_assert_eq!(row.len(), self.headers.len());
+        self.rows.push(row);
+    }
+
+    /// Drops the column under `header`, if there is one.
+    pub fn remove_column(&mut self, header: &str) {
+        if let Some(index) = self.headers.iter().position(|(h, _)| h == header) {
+            self.headers.remove(index);
+            for row in &mut self.rows {
+                row.remove(index);
+            }
+        }
+    }
+
+    /// Columns the widest line takes.
+    pub fn width(&self) -> usize {
+        let widths = self.column_widths();
+        widths.iter().sum::<usize>() + GAP.len() * widths.len().saturating_sub(1)
+    }
+
+    /// The header line followed by the rows, each cell padded to its column. `style`
+    /// decorates a padded cell given its column and row (`None` for the header), which
+    /// leaves the alignment alone even when it adds escape codes.
+    pub fn lines(&self, style: impl Fn(usize, Option<usize>, String) -> String) -> Vec<String> {
+        let widths = self.column_widths();
+        let line = |cells: &[String], row: Option<usize>| {
+            let last = cells.len().saturating_sub(1);
+            cells
+                .iter()
+                .enumerate()
+                .map(|(column, cell)| {
+                    let width = widths[column];
+                    let padded = match self.headers[column].1 {
+                        // The last column needs no trailing spaces.
+                        Align::Left if column == last => cell.clone(),
+                        Align::Left => format!("{:<width$}", cell),
+                        Align::Right => format!("{:>width$}", cell),
+                    };
+                    style(column, row, padded)
+                })
+                .collect::<Vec<_>>()
+                .join(GAP)
+        };
+        let headers: Vec<String> = self.headers.iter().map(|(h, _)| h.clone()).collect();
+        std::iter::once(line(&headers, None))
+            .chain(
+                self.rows
+                    .iter()
+                    .enumerate()
+                    .map(|(index, row)| line(row, Some(index))),
+            )
+            .collect()
+    }
+
+    fn column_widths(&self) -> Vec<usize> {
+        (0..self.headers.len())
+            .map(|column| {
+                self.rows
+                    .iter()
+                    .map(|row| row[column].chars().count())
+                    .chain([self.headers[column].0.chars().count()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+}
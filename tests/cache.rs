@@ -0,0 +1,529 @@
+use chrono::NaiveDate;
+use currency::aliases::{parse_pair, Aliases};
+use currency::cache::{
+    cache_path, cache_stats, export_cache, import_cache, load_cache_from, migrate_cache,
+    parse_cache_export, prune_cache, remove_cache_file, remove_cached_base, remove_entry,
+    save_cache_to, save_pruned_cache_to, ImportMode, ImportSummary, PrunePolicy, PruneReport,
+};
+use currency::config::DEFAULT_PROVIDER;
+use currency::history::{
+    append_history, clear_history, history_path, read_history, HistoryFilter, HistoryRecord,
+};
+use currency::models::CODE_LIST_BASE;
+use currency::overrides::{load_overrides, save_overrides, RateOverride, RateOverrides};
+use currency::{load_cache, save_cache, CacheItem, CurrencyError};
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+fn temp_cache_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("currency-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+/// A cache entry of `base` at 4.0 PLN, for the day `date` or latest, stored at `timestamp`.
+fn entry(base: &str, date: Option<&str>, timestamp: SystemTime) -> (String, CacheItem) {
+    let mut item = CacheItem::new(
+        DEFAULT_PROVIDER,
+        base,
+        HashMap::from([("PLN".to_string(), 4.0)]),
+    );
+    item.date = date.map(str::to_string);
+    item.timestamp = timestamp;
+    (CacheItem::key(DEFAULT_PROVIDER, base, date), item)
+}
+
+#[test]
+fn test_flat_cache_migration() {
+    let flat = r#"{
+        "USD": {
+            "rates": {"EUR": 0.9},
+            "timestamp": {"secs_since_epoch": 1700000000, "nanos_since_epoch": 0}
+        }
+    }"#;
+    let cache = migrate_cache(serde_json::from_str(flat).unwrap());
+
+    let item = &cache[&CacheItem::key(DEFAULT_PROVIDER, "USD", None)];
+    assert_eq!(cache.len(), 1);
+    assert_eq!(item.base, "USD");
+    assert_eq!(item.provider, DEFAULT_PROVIDER);
+    assert_eq!(item.date, None);
+    assert_eq!(
+        (&item.effective_date, item.time_last_updated),
+        (&None, None)
+    );
+    assert_eq!(item.rates.get("EUR"), Some(&0.9));
+
+    // Entries already in the keyed format survive a second pass unchanged.
+    let cache = migrate_cache(cache);
+    assert!(cache.contains_key("exchangerate-api:USD:latest"));
+}
+
+#[test]
+fn test_cache_round_trip_is_atomic() {
+    let path = temp_cache_path("round_trip.json");
+    let mut cache = HashMap::new();
+    cache.insert(
+        CacheItem::key(DEFAULT_PROVIDER, "USD", None),
+        CacheItem::new(
+            DEFAULT_PROVIDER,
+            "USD",
+            HashMap::from([("EUR".to_string(), 0.9)]),
+        ),
+    );
+
+    save_cache_to(&path, &cache).unwrap();
+    let loaded = load_cache_from(&path).unwrap();
+    assert_eq!(loaded.len(), 1);
+    assert_eq!(
+        loaded["exchangerate-api:USD:latest"].rates.get("EUR"),
+        Some(&0.9)
+    );
+
+    let leftovers = fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy().ends_with(".tmp")
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+
+    let unwritable = path.with_file_name("missing-dir").join("rates.json");
+    assert!(matches!(
+        save_cache_to(&unwritable, &cache),
+        Err(CurrencyError::Cache(_))
+    ));
+}
+
+#[test]
+fn test_corrupt_cache_loads_empty() {
+    let path = temp_cache_path("corrupt.json");
+    fs::write(&path, r#"{"exchangerate-api:USD:latest": {"rates": {"EUR""#).unwrap();
+    assert!(load_cache_from(&path).unwrap().is_empty());
+
+    let missing = temp_cache_path("missing.json");
+    assert!(load_cache_from(&missing).unwrap().is_empty());
+}
+
+#[test]
+fn test_truncated_cache_is_moved_aside() {
+    let path = temp_cache_path("truncated.json");
+    let cache = HashMap::from([(
+        CacheItem::key(DEFAULT_PROVIDER, "USD", None),
+        CacheItem::new(
+            DEFAULT_PROVIDER,
+            "USD",
+            HashMap::from([("EUR".to_string(), 0.9), ("PLN".to_string(), 4.0)]),
+        ),
+    )]);
+    save_cache_to(&path, &cache).unwrap();
+
+    // A writer killed halfway through leaves only part of the JSON behind.
+    let contents = fs::read(&path).unwrap();
+    fs::write(&path, &contents[..contents.len() / 2]).unwrap();
+
+    // Reading leaves the file alone, as `cache info` and `cache show` only look.
+    assert!(load_cache_from(&path).unwrap().is_empty());
+    assert_eq!(fs::read(&path).unwrap(), &contents[..contents.len() / 2]);
+    let corrupt = path.with_file_name("truncated.json.corrupt");
+    assert!(!corrupt.exists());
+
+    // The next save moves it aside and starts a fresh cache in place.
+    save_cache_to(&path, &cache).unwrap();
+    assert_eq!(fs::read(&corrupt).unwrap(), &contents[..contents.len() / 2]);
+    assert_eq!(load_cache_from(&path).unwrap().len(), 1);
+}
+
+#[test]
+fn test_cache_file_override() {
+    let path = temp_cache_path("nested").join("dir").join("rates.json");
+    std::env::set_var("CURRENCY_CLI_CACHE_FILE", &path);
+    assert_eq!(cache_path(), path);
+
+    let cache = HashMap::from([(
+        CacheItem::key(DEFAULT_PROVIDER, "EUR", None),
+        CacheItem::new(
+            DEFAULT_PROVIDER,
+            "EUR",
+            HashMap::from([("PLN".to_string(), 4.3)]),
+        ),
+    )]);
+    save_cache(&cache).unwrap();
+    assert!(path.exists());
+    assert_eq!(
+        load_cache().unwrap()["exchangerate-api:EUR:latest"].rates["PLN"],
+        4.3
+    );
+}
+
+fn record(timestamp: &str, from: &str, to: &str) -> HistoryRecord {
+    HistoryRecord {
+        timestamp: timestamp.to_string(),
+        from: from.to_string(),
+        to: to.to_string(),
+        amount: "100".parse().unwrap(),
+        rate: 3.98,
+        converted: "398.00".parse().unwrap(),
+        fee_percent: Default::default(),
+        provider: DEFAULT_PROVIDER.to_string(),
+        cached: false,
+        pinned: false,
+    }
+}
+
+#[test]
+fn test_history_file() {
+    let path = temp_cache_path("history")
+        .join("missing")
+        .join("history.jsonl");
+    std::env::set_var("CURRENCY_CLI_HISTORY_FILE", &path);
+    assert_eq!(history_path(), path);
+    assert!(read_history(&path).unwrap().is_empty());
+
+    let first = record("2024-03-01T09:00:00+01:00", "USD", "PLN");
+    append_history(&path, std::slice::from_ref(&first)).unwrap();
+    fs::write(
+        &path,
+        format!("{}{{\"truncated\n", fs::read_to_string(&path).unwrap()),
+    )
+    .unwrap();
+    let later = [
+        record("2024-03-05T23:30:00-05:00", "EUR", "PLN"),
+        record("2024-03-10T12:00:00+00:00", "USD", "PLN"),
+    ];
+    append_history(&path, &later).unwrap();
+
+    // The corrupt line is skipped and the ones around it survive.
+    let history = read_history(&path).unwrap();
+    assert_eq!(history, [first.clone(), later[0].clone(), later[1].clone()]);
+    assert_eq!(later[0].date(), NaiveDate::from_ymd_opt(2024, 3, 5));
+
+    let usd_pln = HistoryFilter {
+        pair: Some(parse_pair("usd/pln", &Aliases::default()).unwrap()),
+        ..HistoryFilter::default()
+    };
+    assert_eq!(usd_pln.apply(history.clone()), [first, later[1].clone()]);
+    let recent = HistoryFilter {
+        last: Some(1),
+        since: NaiveDate::from_ymd_opt(2024, 3, 5),
+        ..HistoryFilter::default()
+    };
+    assert_eq!(recent.apply(history.clone()), [later[1].clone()]);
+    assert_eq!(HistoryFilter::default().apply(history).len(), 3);
+    assert!(parse_pair("USD", &Aliases::default()).is_err());
+    assert!(parse_pair("USD/", &Aliases::default()).is_err());
+
+    assert!(clear_history(&path).unwrap());
+    assert!(!clear_history(&path).unwrap());
+}
+
+#[test]
+fn test_overrides_file() {
+    let path = temp_cache_path("overrides")
+        .join("missing")
+        .join("rate_overrides.json");
+    assert_eq!(load_overrides(&path).unwrap(), RateOverrides::default());
+
+    let mut overrides = RateOverrides::default();
+    let quarterly = RateOverride {
+        rate: 4.35,
+        expires: Some("2024-03-31".to_string()),
+    };
+    overrides.set("EUR", "PLN", quarterly.clone());
+    overrides.set(
+        "USD",
+        "PLN",
+        RateOverride {
+            rate: 4.0,
+            expires: None,
+        },
+    );
+    save_overrides(&path, &overrides).unwrap();
+    let loaded = load_overrides(&path).unwrap();
+    assert_eq!(loaded, overrides);
+    assert_eq!(loaded.get("EUR", "PLN"), Some(&quarterly));
+    assert_eq!(
+        loaded.iter().map(|(pair, _)| pair).collect::<Vec<_>>(),
+        ["EUR/PLN", "USD/PLN"]
+    );
+
+    // A corrupt file is an error rather than silently losing the pins.
+    fs::write(&path, "{\"EUR/PLN\": 4.35").unwrap();
+    assert!(load_overrides(&path)
+        .unwrap_err()
+        .starts_with("invalid rate overrides"));
+
+    // Unsetting the last override removes the file.
+    assert!(overrides.unset("EUR", "PLN"));
+    assert!(!overrides.unset("EUR", "PLN"));
+    assert!(overrides.unset("USD", "PLN"));
+    save_overrides(&path, &overrides).unwrap();
+    assert!(!path.exists());
+    save_overrides(&path, &overrides).unwrap();
+}
+
+#[test]
+fn test_cache_stats_and_removal() {
+    let path = temp_cache_path("stats.json");
+    let old = SystemTime::now() - Duration::from_secs(7200);
+    let mut cache = HashMap::from([
+        entry("USD", None, SystemTime::now()),
+        entry("USD", Some("2023-01-15"), old),
+        entry("EUR", None, old),
+    ]);
+    save_cache_to(&path, &cache).unwrap();
+
+    let stats = cache_stats(&path).unwrap();
+    assert_eq!((stats.bases, stats.entries), (2, 3));
+    assert_eq!(stats.size, fs::metadata(&path).unwrap().len());
+    assert_eq!(stats.oldest, Some(old));
+
+    assert_eq!(remove_entry(&mut cache, "USD"), 2);
+    assert_eq!(remove_entry(&mut cache, "GBP"), 0);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(remove_cached_base(&path, "EUR").unwrap(), 1);
+    assert_eq!(cache_stats(&path).unwrap().entries, 2);
+
+    assert!(remove_cache_file(&path).unwrap());
+    assert!(!remove_cache_file(&path).unwrap());
+    let empty = cache_stats(&path).unwrap();
+    assert_eq!((empty.size, empty.entries, empty.oldest), (0, 0, None));
+}
+
+#[test]
+fn test_concurrent_saves_keep_every_update() {
+    let path = temp_cache_path("concurrent.json");
+    let bases = ["USD", "EUR", "GBP", "JPY", "CHF", "PLN", "CZK", "SEK"];
+
+    // Each writer saves its own snapshot, as separate runs started from an empty cache would.
+    let writers: Vec<_> = bases
+        .into_iter()
+        .map(|base| {
+            let path = path.clone();
+            thread::spawn(move || {
+                let cache = HashMap::from([(
+                    CacheItem::key(DEFAULT_PROVIDER, base, None),
+                    CacheItem::new(
+                        DEFAULT_PROVIDER,
+                        base,
+                        HashMap::from([("XAU".to_string(), 0.5)]),
+                    ),
+                )]);
+                save_cache_to(&path, &cache).unwrap();
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let cache = load_cache_from(&path).unwrap();
+    assert_eq!(cache.len(), bases.len());
+    for base in bases {
+        assert!(cache.contains_key(&CacheItem::key(DEFAULT_PROVIDER, base, None)));
+    }
+
+    // An older snapshot doesn't overwrite a newer entry for the same key.
+    let key = CacheItem::key(DEFAULT_PROVIDER, "USD", None);
+    let mut stale = cache[&key].clone();
+    stale.timestamp -= Duration::from_secs(60);
+    stale.rates.insert("XAU".to_string(), 9.0);
+    save_cache_to(&path, &HashMap::from([(key.clone(), stale)])).unwrap();
+    assert_eq!(load_cache_from(&path).unwrap()[&key].rates["XAU"], 0.5);
+}
+
+#[test]
+fn test_cache_export_and_import() {
+    let path = temp_cache_path("import.json");
+    let now = SystemTime::now();
+    // Rates tell apart which machine an entry came from.
+    let priced = |(key, mut item): (String, CacheItem), rate: f64| {
+        item.rates.insert("PLN".to_string(), rate);
+        (key, item)
+    };
+    let laptop = HashMap::from([
+        entry("USD", None, now - Duration::from_secs(60)),
+        priced(entry("EUR", None, now - Duration::from_secs(7200)), 4.3),
+        priced(entry("GBP", None, now), 5.1),
+    ]);
+
+    let exported = export_cache(&laptop, &["USD".to_string(), "EUR".to_string()]);
+    let imported = parse_cache_export(&exported, now).unwrap();
+    assert_eq!(imported.len(), 2);
+    assert_eq!(
+        imported[&CacheItem::key(DEFAULT_PROVIDER, "USD", None)].rates["PLN"],
+        4.0
+    );
+    let everything = parse_cache_export(&export_cache(&laptop, &[]), now).unwrap();
+    assert_eq!(everything.len(), 3);
+
+    // The offline machine has a newer EUR entry and an older USD one: newest wins per key.
+    let offline = HashMap::from([
+        priced(entry("USD", None, now - Duration::from_secs(3600)), 3.9),
+        priced(entry("EUR", None, now - Duration::from_secs(10)), 4.4),
+        priced(entry("CHF", None, now), 4.5),
+    ]);
+    save_cache_to(&path, &offline).unwrap();
+    assert_eq!(
+        import_cache(&path, imported.clone(), ImportMode::Merge).unwrap(),
+        ImportSummary {
+            imported: 1,
+            skipped: 1
+        }
+    );
+    let merged = load_cache_from(&path).unwrap();
+    let rate = |cache: &HashMap<String, CacheItem>, base| {
+        cache
+            .get(&CacheItem::key(DEFAULT_PROVIDER, base, None))
+            .map(|item| item.rates["PLN"])
+    };
+    assert_eq!(
+        ["USD", "EUR", "CHF"].map(|base| rate(&merged, base)),
+        [Some(4.0), Some(4.4), Some(4.5)]
+    );
+
+    import_cache(&path, imported, ImportMode::Replace).unwrap();
+    let replaced = load_cache_from(&path).unwrap();
+    assert_eq!(
+        ["USD", "EUR", "CHF"].map(|base| rate(&replaced, base)),
+        [Some(4.0), Some(4.3), None]
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_cache_import_rejects_malformed_files() {
+    let now = SystemTime::now();
+    let import = |contents: &str| match parse_cache_export(contents, now) {
+        Ok(_) => panic!("imported {}", contents),
+        Err(e) => e,
+    };
+    let timestamp = |secs: u64| {
+        format!(
+            r#"{{"secs_since_epoch": {}, "nanos_since_epoch": 0}}"#,
+            secs
+        )
+    };
+    let today = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    assert!(import("not json").starts_with("not a JSON cache export: "));
+    assert_eq!(
+        import("[1, 2]"),
+        "expected an object of cache entries keyed like \"exchangerate-api:USD:latest\""
+    );
+    assert_eq!(
+        import(r#"{"exchangerate-api:USD:latest": 4.0}"#),
+        "entry 'exchangerate-api:USD:latest' is not an object"
+    );
+    assert_eq!(
+        import(r#"{"exchangerate-api:USD:latest": {"timestamp": 1}}"#),
+        "entry 'exchangerate-api:USD:latest' has no rates"
+    );
+    assert_eq!(
+        import(r#"{"exchangerate-api:USD:latest": {"rates": [4.0]}}"#),
+        "entry 'exchangerate-api:USD:latest': rates must be an object"
+    );
+    for rate in ["\"4.0\"", "0", "-1", "null"] {
+        assert_eq!(
+            import(&format!(
+                r#"{{"exchangerate-api:USD:latest": {{"rates": {{"EUR": 0.9, "PLN": {}}}, "timestamp": {}}}}}"#,
+                rate,
+                timestamp(today)
+            )),
+            "entry 'exchangerate-api:USD:latest': the PLN rate is not a positive number"
+        );
+    }
+    assert!(import(
+        r#"{"exchangerate-api:USD:latest": {"rates": {"PLN": 4.0}, "timestamp": "yesterday"}}"#
+    )
+    .starts_with("entry 'exchangerate-api:USD:latest' doesn't match the cache format: "));
+    assert_eq!(
+        import(&format!(
+            r#"{{"exchangerate-api:USD:latest": {{"rates": {{"PLN": 4.0}}, "timestamp": {}}}}}"#,
+            timestamp(4102444800)
+        )),
+        "entry 'exchangerate-api:USD:latest' is timestamped 2100-01-01 00:00:00 UTC, in the future"
+    );
+
+    // A clock a minute ahead is fine, and an entry in the old flat format is migrated.
+    let imported = parse_cache_export(
+        &format!(
+            r#"{{"USD": {{"rates": {{"PLN": 4.0}}, "timestamp": {}}}}}"#,
+            timestamp(today + 60)
+        ),
+        now,
+    )
+    .unwrap();
+    assert_eq!(
+        imported[&CacheItem::key(DEFAULT_PROVIDER, "USD", None)].base,
+        "USD"
+    );
+}
+
+#[test]
+fn test_cache_pruning() {
+    let path = temp_cache_path("prune.json");
+    let now = SystemTime::now();
+    let days = |days: u64| now - Duration::from_secs(days * 24 * 3600);
+    let codes_key = CacheItem::key(DEFAULT_PROVIDER, CODE_LIST_BASE, None);
+    let mut cache = HashMap::from([
+        entry("USD", None, days(0)),
+        entry("EUR", None, days(1)),
+        entry("GBP", None, days(2)),
+        entry("CHF", None, days(3)),
+        entry("JPY", None, days(40)),
+        entry("USD", Some("2023-01-15"), days(90)),
+        entry("USD", Some("2023-01-16"), days(5)),
+        entry("EUR", Some("2023-01-15"), days(1)),
+    ]);
+    let mut codes = CacheItem::code_list(DEFAULT_PROVIDER, Default::default());
+    codes.timestamp = days(400);
+    cache.insert(codes_key.clone(), codes);
+    save_cache_to(&path, &cache).unwrap();
+
+    let policy = PrunePolicy {
+        max_age: Some(Duration::from_secs(30 * 24 * 3600)),
+        max_entries: 3,
+        max_historical_entries: 2,
+    };
+    let report = save_pruned_cache_to(&path, &HashMap::new(), &policy).unwrap();
+    let key = |base: &str, date: Option<&str>| CacheItem::key(DEFAULT_PROVIDER, base, date);
+    // JPY is past 30 days; CHF is the oldest of the four latest entries left; the historical
+    // USD entry from 90 days ago never expires but is the first over the cap of two.
+    assert_eq!(
+        report,
+        PruneReport {
+            expired: vec![key("JPY", None)],
+            evicted: vec![key("CHF", None), key("USD", Some("2023-01-15"))],
+        }
+    );
+    let mut kept: Vec<String> = load_cache_from(&path).unwrap().into_keys().collect();
+    kept.sort();
+    let mut expected = vec![
+        key("USD", None),
+        key("EUR", None),
+        key("GBP", None),
+        key("USD", Some("2023-01-16")),
+        key("EUR", Some("2023-01-15")),
+        codes_key.clone(),
+    ];
+    expected.sort();
+    assert_eq!(kept, expected);
+
+    // Pruning again finds nothing, and the empty policy never removes anything.
+    assert!(save_pruned_cache_to(&path, &HashMap::new(), &policy)
+        .unwrap()
+        .is_empty());
+    assert!(prune_cache(&mut cache, &PrunePolicy::NONE, now).is_empty());
+    assert_eq!(cache.len(), 9);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,966 @@
+use chrono::NaiveDate;
+use currency::api::{cached_code_list, quote_spread};
+use currency::cache::load_cache_from;
+use currency::config::{CACHE_DURATION, CRYPTO_CACHE_DURATION, DEFAULT_PROVIDER};
+use currency::error::{EXIT_API_ACCESS, EXIT_STALE, EXIT_UNKNOWN_CURRENCY};
+use currency::models::{RoundMode, CODE_LIST_BASE};
+use currency::numbers::NumberFormat;
+use currency::output::OutputFormat;
+use currency::provider::{CoinGecko, ExchangeRateApi, Frankfurter, OpenErApi};
+use currency::server::Server;
+use currency::{ApiClient, CacheItem, CurrencyError};
+use reqwest::StatusCode;
+use rust_decimal::Decimal;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const USD_RATES: &str = r#"{"base": "USD", "rates": {"USD": 1, "EUR": 0.92, "PLN": 3.98}}"#;
+
+/// A client asking only ExchangeRate-API at `server`, without retries.
+fn client(server: &MockServer) -> ApiClient {
+    ApiClient::default()
+        .retries(0)
+        .api_key("test-key")
+        .providers(vec![Arc::new(ExchangeRateApi::new(&server.uri()))])
+}
+
+#[tokio::test]
+async fn test_fetch_populates_cache() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest/USD"))
+        .and(query_param("access_key", "test-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(USD_RATES))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let api = client(&server);
+    let mut cache = HashMap::new();
+
+    let quote = api
+        .fetch_exchange_rate("USD", "EUR", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert_eq!(quote.rate, 0.92);
+    assert!(!quote.cached);
+    assert_eq!(quote.provider, DEFAULT_PROVIDER);
+
+    let item = &cache[&CacheItem::key(DEFAULT_PROVIDER, "USD", None)];
+    assert_eq!((item.base.as_str(), item.date.as_deref()), ("USD", None));
+    assert_eq!(item.provider, DEFAULT_PROVIDER);
+    assert_eq!(
+        item.rates,
+        HashMap::from([
+            ("USD".to_string(), 1.0),
+            ("EUR".to_string(), 0.92),
+            ("PLN".to_string(), 3.98),
+        ])
+    );
+
+    // The second conversion is answered from the cache; the mock expects a single request.
+    let quote = api
+        .fetch_exchange_rate("USD", "PLN", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert!(quote.cached);
+    assert_eq!(quote.rate, 3.98);
+}
+
+#[tokio::test]
+async fn test_listed_rates_come_from_the_cache() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest/USD"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(USD_RATES))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let api = client(&server);
+    let key = CacheItem::key(DEFAULT_PROVIDER, "USD", None);
+    let mut cached = CacheItem::new(
+        DEFAULT_PROVIDER,
+        "USD",
+        HashMap::from([("PLN".to_string(), 4.05)]),
+    );
+    cached.timestamp -= Duration::from_secs(600);
+    let mut cache = HashMap::from([(key.clone(), cached.clone())]);
+    let bases = ["USD".to_string()];
+
+    // Ten minutes old is still fresh, so nothing is requested.
+    let rates = api
+        .fetch_many_base_rates(&bases, &mut cache, CACHE_DURATION, None)
+        .await;
+    assert_eq!(rates[0].as_ref().unwrap()["PLN"], 4.05);
+    assert_eq!(cache[&key].timestamp, cached.timestamp);
+
+    // Once expired they are fetched again, the one request the mock expects, and the
+    // entry is replaced.
+    let rates = api
+        .fetch_many_base_rates(&bases, &mut cache, Duration::from_secs(300), None)
+        .await;
+    assert_eq!(rates[0].as_ref().unwrap()["PLN"], 3.98);
+    assert_eq!(cache[&key].rates["EUR"], 0.92);
+    assert!(cache[&key].timestamp > cached.timestamp);
+    assert!(cache[&key].age() < Duration::from_secs(60));
+}
+
+#[tokio::test]
+async fn test_exact_fetches_instead_of_deriving() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest/EUR"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string(r#"{"base": "EUR", "rates": {"EUR": 1, "PLN": 4.31}}"#),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    let mut cache = HashMap::new();
+    cache.insert(
+        CacheItem::key(DEFAULT_PROVIDER, "USD", None),
+        CacheItem::new(
+            DEFAULT_PROVIDER,
+            "USD",
+            HashMap::from([("EUR".to_string(), 0.92), ("PLN".to_string(), 3.98)]),
+        ),
+    );
+
+    // Without --exact the cached USD entry answers EUR->PLN, marked as derived.
+    let quote = client(&server)
+        .fetch_exchange_rate("EUR", "PLN", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert!(quote.cached);
+    assert_eq!(quote.derived_via.as_deref(), Some("USD"));
+
+    let quote = client(&server)
+        .exact(true)
+        .fetch_exchange_rate("EUR", "PLN", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert!(!quote.cached);
+    assert_eq!((quote.rate, quote.derived_via), (4.31, None));
+}
+
+/// The error for a USD->EUR conversion when ExchangeRate-API answers with `status` and
+/// `body`.
+async fn rejection(status: u16, body: &str) -> CurrencyError {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest/USD"))
+        .respond_with(ResponseTemplate::new(status).set_body_string(body))
+        .mount(&server)
+        .await;
+    client(&server)
+        .fetch_exchange_rate("USD", "EUR", &mut HashMap::new(), CACHE_DURATION, None)
+        .await
+        .unwrap_err()
+}
+
+#[tokio::test]
+async fn test_provider_error_bodies() {
+    let error = rejection(404, r#"{"result":"error","error-type":"unsupported-code"}"#).await;
+    assert!(matches!(&error, CurrencyError::UnsupportedCode(code) if code == "USD"));
+    assert_eq!(error.to_string(), "USD is not supported by the provider");
+    assert_eq!(error.exit_code(), EXIT_UNKNOWN_CURRENCY);
+
+    let error = rejection(403, r#"{"result":"error","error-type":"invalid-key"}"#).await;
+    assert!(matches!(error, CurrencyError::InvalidApiKey));
+    assert_eq!(error.exit_code(), EXIT_API_ACCESS);
+    let error = rejection(403, r#"{"result":"error","error-type":"inactive-account"}"#).await;
+    assert!(matches!(error, CurrencyError::InvalidApiKey));
+
+    let error = rejection(429, r#"{"result":"error","error-type":"quota-reached"}"#).await;
+    assert!(matches!(error, CurrencyError::TooManyRequests(None)));
+    let error = rejection(403, r#"{"result":"error","error-type":"quota-reached"}"#).await;
+    assert!(matches!(error, CurrencyError::RateLimited));
+    // Some errors arrive with a 200 status.
+    let error = rejection(200, r#"{"result":"error","error-type":"quota-reached"}"#).await;
+    assert!(matches!(error, CurrencyError::RateLimited));
+
+    let error = rejection(
+        400,
+        r#"{"result":"error","error-type":"malformed-request"}"#,
+    )
+    .await;
+    assert!(matches!(error, CurrencyError::MalformedRequest));
+
+    // Bodies without a known error-type keep the provider's message.
+    let error = rejection(401, r#"{"error":"Invalid authentication credentials"}"#).await;
+    assert_eq!(
+        error.to_string(),
+        "Invalid authentication credentials (401 Unauthorized)"
+    );
+    assert_eq!(error.exit_code(), EXIT_API_ACCESS);
+    let error = rejection(
+        404,
+        r#"{"error":{"code":"not_found","message":"no such base"}}"#,
+    )
+    .await;
+    assert_eq!(error.to_string(), "no such base (404 Not Found)");
+    let error = rejection(422, r#"{"message":"not found"}"#).await;
+    assert_eq!(error.to_string(), "not found (422 Unprocessable Entity)");
+
+    // Without a readable body the status is all there is.
+    let error = rejection(404, "<html>Not Found</html>").await;
+    assert!(matches!(
+        error,
+        CurrencyError::Status(StatusCode::NOT_FOUND)
+    ));
+    let error = rejection(403, "").await;
+    assert!(matches!(error, CurrencyError::RateLimited));
+}
+
+#[tokio::test]
+async fn test_several_targets_share_one_fetch() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest/USD"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(USD_RATES))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let mut cache = HashMap::new();
+
+    let quotes = client(&server)
+        .fetch_exchange_rates(
+            "USD",
+            &["EUR", "JPY", "PLN"],
+            &mut cache,
+            CACHE_DURATION,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(quotes[0].as_ref().unwrap().rate, 0.92);
+    assert_eq!(
+        quotes[1].as_ref().unwrap_err().to_string(),
+        "Rate for JPY not found in USD rates"
+    );
+    assert_eq!(quotes[2].as_ref().unwrap().rate, 3.98);
+}
+
+#[tokio::test]
+async fn test_historical_fetch() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/test-key/history/USD/2023/1/15"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(USD_RATES))
+        .mount(&server)
+        .await;
+    let mut cache = HashMap::new();
+    let date = NaiveDate::from_ymd_opt(2023, 1, 15).unwrap();
+
+    let rates = client(&server)
+        .fetch_base_rates("USD", &mut cache, CACHE_DURATION, Some(date))
+        .await
+        .unwrap();
+    assert_eq!(rates.get("EUR"), Some(&0.92));
+    let item = &cache[&CacheItem::key(DEFAULT_PROVIDER, "USD", Some("2023-01-15"))];
+    assert_eq!(item.date.as_deref(), Some("2023-01-15"));
+}
+
+#[tokio::test]
+async fn test_historical_rates_beyond_the_plan() {
+    let primary = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/test-key/history/USD/2023/1/16"))
+        .respond_with(
+            ResponseTemplate::new(403)
+                .set_body_string(r#"{"result": "error", "error-type": "plan-upgrade-required"}"#),
+        )
+        .expect(2)
+        .mount(&primary)
+        .await;
+    let fallback = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/2023-01-16"))
+        .and(query_param("from", "USD"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(
+                r#"{"base": "USD", "date": "2023-01-16", "rates": {"PLN": 4.35}}"#,
+            ),
+        )
+        .expect(1)
+        .mount(&fallback)
+        .await;
+    let date = NaiveDate::from_ymd_opt(2023, 1, 16).unwrap();
+
+    // A free ExchangeRate-API key has no history, so Frankfurter answers instead.
+    let api = client(&primary).providers(vec![
+        Arc::new(ExchangeRateApi::new(&primary.uri())),
+        Arc::new(Frankfurter::new(&fallback.uri())),
+    ]);
+    let quote = api
+        .fetch_exchange_rate(
+            "USD",
+            "PLN",
+            &mut HashMap::new(),
+            CACHE_DURATION,
+            Some(date),
+        )
+        .await
+        .unwrap();
+    assert_eq!((quote.rate, quote.provider.as_str()), (4.35, "frankfurter"));
+    // Without another provider the refusal is reported as an API access problem.
+    let error = client(&primary)
+        .fetch_base_rates("USD", &mut HashMap::new(), CACHE_DURATION, Some(date))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(error, CurrencyError::PlanUpgradeRequired));
+    assert_eq!(error.exit_code(), EXIT_API_ACCESS);
+}
+
+#[tokio::test]
+async fn test_historical_effective_date() {
+    let server = MockServer::start().await;
+    // 2023-01-15 is a Sunday; the provider answers with Friday's rates.
+    Mock::given(method("GET"))
+        .and(path("/test-key/history/USD/2023/1/15"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"result": "success", "year": 2023, "month": 1, "day": 13,
+                "conversion_rates": {"USD": 1, "PLN": 4.38}}"#,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let client = client(&server);
+    let mut cache = HashMap::new();
+    let date = NaiveDate::from_ymd_opt(2023, 1, 15).unwrap();
+
+    for cached in [false, true] {
+        let quote = client
+            .fetch_exchange_rate("USD", "PLN", &mut cache, CACHE_DURATION, Some(date))
+            .await
+            .unwrap();
+        assert_eq!(quote.cached, cached);
+        assert_eq!(quote.effective_date.as_deref(), Some("2023-01-13"));
+    }
+}
+
+#[tokio::test]
+async fn test_latest_rates_publication_date() {
+    let server = MockServer::start().await;
+    // Saturday 2024-05-04 00:00:02 UTC; the rates were last updated on that day.
+    Mock::given(method("GET"))
+        .and(path("/latest/USD"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"result": "success", "base_code": "USD", "time_last_update_unix": 1714780802,
+                "conversion_rates": {"USD": 1, "PLN": 4.02}}"#,
+        ))
+        .mount(&server)
+        .await;
+    // A response quoted against another base than the one asked for.
+    Mock::given(method("GET"))
+        .and(path("/latest/EUR"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(USD_RATES))
+        .mount(&server)
+        .await;
+    let api = client(&server);
+    let mut cache = HashMap::new();
+
+    let quote = api
+        .fetch_exchange_rate("USD", "PLN", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert_eq!(quote.effective_date.as_deref(), Some("2024-05-04"));
+    assert!(quote.predates(NaiveDate::from_ymd_opt(2024, 5, 5).unwrap()));
+    assert!(!quote.predates(NaiveDate::from_ymd_opt(2024, 5, 4).unwrap()));
+    let item = &cache[&CacheItem::key(DEFAULT_PROVIDER, "USD", None)];
+    assert_eq!(item.time_last_updated, Some(1714780802));
+
+    match api
+        .fetch_exchange_rate("EUR", "PLN", &mut cache, CACHE_DURATION, None)
+        .await
+    {
+        Err(CurrencyError::Provider(message)) => assert_eq!(
+            message,
+            "asked for EUR rates but the response is quoted against USD"
+        ),
+        other => panic!("expected a base mismatch, got {:?}", other),
+    }
+    assert!(!cache.keys().any(|key| key.contains(":EUR:")));
+}
+
+#[tokio::test]
+async fn test_forbidden_is_rate_limited() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+
+    let error = client(&server)
+        .fetch_all_exchange_rates("USD")
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(error, CurrencyError::RateLimited));
+    assert_eq!(error.to_string(), "API request limit exceeded");
+}
+
+#[tokio::test]
+async fn test_server_error_is_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let error = client(&server)
+        .retries(1)
+        .fetch_all_exchange_rates("USD")
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        CurrencyError::Status(StatusCode::INTERNAL_SERVER_ERROR)
+    ));
+    assert_eq!(
+        error.to_string(),
+        "Error fetching all exchange rates: 500 Internal Server Error"
+    );
+}
+
+#[tokio::test]
+async fn test_stale_fallback_and_strict() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    let mut expired = CacheItem::new(
+        DEFAULT_PROVIDER,
+        "USD",
+        HashMap::from([("PLN".to_string(), 3.98)]),
+    );
+    expired.timestamp -= Duration::from_secs(9 * 3600);
+    let mut cache = HashMap::from([(CacheItem::key(DEFAULT_PROVIDER, "USD", None), expired)]);
+
+    // The provider is down, so the nine-hour-old rate is served, marked as stale.
+    let quote = client(&server)
+        .fetch_exchange_rate("USD", "PLN", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert_eq!((quote.rate, quote.stale, quote.cached), (3.98, true, true));
+    assert_eq!(quote.age.as_secs() / 3600, 9);
+    let rates = client(&server)
+        .fetch_base_rates("USD", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert_eq!(rates["PLN"], 3.98);
+
+    // Under --strict the fetch fails instead, with its own exit status.
+    let strict = client(&server).strict(true);
+    let error = strict
+        .fetch_exchange_rate("USD", "PLN", &mut cache, CACHE_DURATION, None)
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(
+        &error,
+        CurrencyError::StaleRate { error, age }
+            if matches!(**error, CurrencyError::Status(StatusCode::INTERNAL_SERVER_ERROR))
+                && age.as_secs() / 3600 == 9
+    ));
+    assert_eq!(error.exit_code(), EXIT_STALE);
+    assert_eq!(
+        error.to_string(),
+        "Error fetching all exchange rates: 500 Internal Server Error; --strict refuses the cached rate from 9h 0m ago"
+    );
+    assert!(strict
+        .fetch_base_rates("USD", &mut cache, CACHE_DURATION, None)
+        .await
+        .is_err_and(|e| e.exit_code() == EXIT_STALE));
+
+    // Without a cached rate there is nothing to refuse, just the provider's error.
+    let error = strict
+        .fetch_exchange_rate("EUR", "PLN", &mut cache, CACHE_DURATION, None)
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        CurrencyError::Status(StatusCode::INTERNAL_SERVER_ERROR)
+    ));
+}
+
+#[tokio::test]
+async fn test_too_many_requests() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "120"))
+        .mount(&server)
+        .await;
+
+    let error = client(&server)
+        .fetch_all_exchange_rates("USD")
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error,
+        CurrencyError::TooManyRequests(Some(wait)) if wait == Duration::from_secs(120)
+    ));
+}
+
+#[tokio::test]
+async fn test_malformed_json() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"rates": {"EUR": "#))
+        .mount(&server)
+        .await;
+    let mut cache = HashMap::new();
+
+    let error = client(&server)
+        .fetch_exchange_rate("USD", "EUR", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, CurrencyError::Parse(_)));
+    assert!(error
+        .to_string()
+        .starts_with("invalid response from the provider: "));
+    assert!(cache.is_empty());
+}
+
+#[tokio::test]
+async fn test_fallback_to_next_provider() {
+    let primary = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&primary)
+        .await;
+    let fallback = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest/USD"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"result": "success", "base_code": "USD", "rates": {"EUR": 0.93}}"#,
+        ))
+        .mount(&fallback)
+        .await;
+    let api = client(&primary).providers(vec![
+        Arc::new(ExchangeRateApi::new(&primary.uri())),
+        Arc::new(OpenErApi::new(&fallback.uri())),
+    ]);
+    let mut cache = HashMap::new();
+
+    let quote = api
+        .fetch_exchange_rate("USD", "EUR", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert_eq!((quote.rate, quote.provider.as_str()), (0.93, "open-er-api"));
+    assert!(cache.contains_key(&CacheItem::key("open-er-api", "USD", None)));
+}
+
+#[tokio::test]
+async fn test_quote_across_providers() {
+    let primary = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest/USD"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(USD_RATES))
+        .mount(&primary)
+        .await;
+    let slow = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string(USD_RATES)
+                .set_delay(Duration::from_secs(5)),
+        )
+        .mount(&slow)
+        .await;
+    let ecb = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest"))
+        .and(query_param("from", "USD"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string(r#"{"base": "USD", "rates": {"EUR": 0.93, "PLN": 4.02}}"#),
+        )
+        .mount(&ecb)
+        .await;
+    let api = client(&primary).providers(vec![
+        Arc::new(ExchangeRateApi::new(&primary.uri())),
+        Arc::new(OpenErApi::new(&slow.uri())),
+        Arc::new(Frankfurter::new(&ecb.uri())),
+    ]);
+
+    // The slow provider times out without holding up the others.
+    let started = std::time::Instant::now();
+    let quotes = api
+        .quote_providers("USD", "PLN", None, Duration::from_millis(300))
+        .await;
+    assert!(started.elapsed() < Duration::from_secs(3));
+    let answers: Vec<(&str, Option<f64>)> = quotes
+        .iter()
+        .map(|quote| (quote.provider, quote.rate.as_ref().ok().copied()))
+        .collect();
+    assert_eq!(
+        answers,
+        [
+            ("exchangerate-api", Some(3.98)),
+            ("open-er-api", None),
+            ("frankfurter", Some(4.02)),
+        ]
+    );
+    assert!(matches!(quotes[1].rate, Err(CurrencyError::Timeout(_))));
+
+    let spread = quote_spread(&quotes).unwrap();
+    assert_eq!(spread.best, ("frankfurter", 4.02));
+    assert_eq!(spread.worst, ("exchangerate-api", 3.98));
+    assert!((spread.absolute - 0.04).abs() < 1e-9);
+    assert!((spread.percent - 1.0050251).abs() < 1e-6);
+    // With a single answer there is nothing to compare.
+    assert_eq!(quote_spread(&quotes[..2]), None);
+}
+
+#[tokio::test]
+async fn test_missing_api_key_is_caught_up_front() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(USD_RATES))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let api = client(&server).api_key("");
+
+    assert!(matches!(
+        api.require_api_key(),
+        Err(CurrencyError::MissingApiKey)
+    ));
+    let error = api
+        .fetch_exchange_rate("USD", "EUR", &mut HashMap::new(), CACHE_DURATION, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, CurrencyError::MissingApiKey));
+}
+
+#[tokio::test]
+async fn test_keyless_provider_skips_fallbacks_needing_a_key() {
+    let keyless = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&keyless)
+        .await;
+    let keyed = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(USD_RATES))
+        .expect(0)
+        .mount(&keyed)
+        .await;
+    let api = client(&keyed).api_key("").providers(vec![
+        Arc::new(OpenErApi::new(&keyless.uri())),
+        Arc::new(ExchangeRateApi::new(&keyed.uri())),
+    ]);
+
+    assert!(api.require_api_key().is_ok());
+    let error = api
+        .fetch_exchange_rate("USD", "EUR", &mut HashMap::new(), CACHE_DURATION, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        CurrencyError::Status(StatusCode::SERVICE_UNAVAILABLE)
+    ));
+}
+
+/// [`client`] with crypto assets priced by CoinGecko at `server` too.
+fn crypto_client(server: &MockServer) -> ApiClient {
+    client(server).crypto_provider(Arc::new(CoinGecko::new(&server.uri())))
+}
+
+#[tokio::test]
+async fn test_crypto_rate_combines_with_fiat_leg() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/simple/price"))
+        .and(query_param("ids", "bitcoin"))
+        .and(query_param("vs_currencies", "usd"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"bitcoin": {"usd": 65000}}"#))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/latest/USD"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(USD_RATES))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let api = crypto_client(&server);
+    let mut cache = HashMap::new();
+
+    let quote = api
+        .fetch_exchange_rate("BTC", "PLN", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert!((quote.rate - 65000.0 * 3.98).abs() < 1e-6);
+    assert!(!quote.cached);
+    assert_eq!(quote.provider, "coingecko and exchangerate-api");
+
+    // Both legs are cached now; the mocks expect a single request each.
+    let quote = api
+        .fetch_exchange_rate("USD", "BTC", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert_eq!(quote.rate, 1.0 / 65000.0);
+    assert!(quote.cached);
+    assert_eq!(quote.provider, "coingecko");
+}
+
+#[tokio::test]
+async fn test_crypto_prices_expire_sooner() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/simple/price"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"ethereum": {"usd": 3000}}"#))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let mut item = CacheItem::new(
+        "coingecko",
+        "ETH",
+        HashMap::from([("USD".to_string(), 2500.0)]),
+    );
+    item.timestamp -= CRYPTO_CACHE_DURATION * 2;
+    let mut cache = HashMap::from([(CacheItem::key("coingecko", "ETH", None), item)]);
+
+    let quote = crypto_client(&server)
+        .fetch_exchange_rate("ETH", "USD", &mut cache, CACHE_DURATION, None)
+        .await
+        .unwrap();
+    assert_eq!(quote.rate, 3000.0);
+    assert!(!quote.cached);
+}
+
+#[tokio::test]
+async fn test_unsupported_crypto_asset() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/simple/price"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .mount(&server)
+        .await;
+
+    let err = crypto_client(&server)
+        .fetch_exchange_rate("XMR", "USD", &mut HashMap::new(), CACHE_DURATION, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, CurrencyError::UnsupportedCrypto(_)));
+    assert_eq!(err.to_string(), "XMR is not a supported crypto asset");
+}
+
+#[tokio::test]
+async fn test_code_list_is_cached() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/test-key/codes"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"result": "success", "supported_codes": [["CHF", "Swiss Franc"], ["XOF", "CFA Franc BCEAO"]]}"#,
+        ))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let api = client(&server);
+    let mut cache = HashMap::new();
+
+    let item = api.fetch_code_list(&mut cache).await.unwrap();
+    assert_eq!(item.provider, DEFAULT_PROVIDER);
+    assert_eq!(item.names["XOF"], "CFA Franc BCEAO");
+    assert!(item.is_code_list() && item.rates.is_empty());
+    assert_eq!(cached_code_list(&cache).unwrap().names, item.names);
+    assert!(cache.contains_key(&CacheItem::key(DEFAULT_PROVIDER, CODE_LIST_BASE, None)));
+    // Answered from the cache the second time; the mock expects a single request.
+    assert_eq!(
+        api.fetch_code_list(&mut cache).await.unwrap().names.len(),
+        2
+    );
+    // The entry is no base whose rates a conversion could use.
+    assert!(api
+        .cached_rate("CHF", "XOF", &cache, CACHE_DURATION, None)
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_code_list_from_keyless_provider() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/currencies"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_string(r#"{"CHF": "Swiss Franc", "EUR": "Euro"}"#),
+        )
+        .mount(&server)
+        .await;
+    // Without a key ExchangeRate-API is skipped, and open.er-api publishes no list.
+    let api = ApiClient::default().retries(0).api_key("").providers(vec![
+        Arc::new(ExchangeRateApi::new(&server.uri())),
+        Arc::new(OpenErApi::new(&server.uri())),
+        Arc::new(Frankfurter::new(&server.uri())),
+    ]);
+    let mut cache = HashMap::new();
+    let item = api.fetch_code_list(&mut cache).await.unwrap();
+    assert_eq!(item.provider, "frankfurter");
+    assert_eq!(item.names["EUR"], "Euro");
+
+    let api = ApiClient::default()
+        .retries(0)
+        .providers(vec![Arc::new(OpenErApi::new(&server.uri()))]);
+    assert!(matches!(
+        api.fetch_code_list(&mut HashMap::new()).await,
+        Err(CurrencyError::Provider(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_serve_endpoints() {
+    let provider = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest/USD"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(USD_RATES))
+        .expect(1)
+        .mount(&provider)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/latest/EUR"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&provider)
+        .await;
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::PLAIN,
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    let cache_file = std::env::temp_dir()
+        .join(format!("currency-serve-{}", std::process::id()))
+        .join("rates_cache.json");
+    let server = Server::new(client(&provider), HashMap::new(), CACHE_DURATION, format)
+        .cache_file(Some(cache_file.clone()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let running = tokio::spawn(Arc::new(server).run(listener, async {
+        stopped.await.ok();
+    }));
+
+    let get = |target: &str| {
+        let url = format!("{}{}", url, target);
+        async move {
+            let response = reqwest::get(url).await.unwrap();
+            let status = response.status();
+            (status, response.json::<serde_json::Value>().await.unwrap())
+        }
+    };
+    let (status, body) = get("/convert?from=usd&to=PLN&amount=100").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        (body["rate"].as_f64(), body["converted"].as_f64()),
+        (Some(3.98), Some(398.0))
+    );
+    // Answered from the shared cache; the mock expects a single request.
+    let (status, body) = get("/rates/USD").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["rates"]["EUR"], 0.92);
+
+    for (target, expected, kind) in [
+        (
+            "/convert?from=USD&to=XYZ",
+            StatusCode::NOT_FOUND,
+            "unknown_currency",
+        ),
+        (
+            "/convert?from=USD&to=PLN&amount=-5",
+            StatusCode::BAD_REQUEST,
+            "invalid_input",
+        ),
+        ("/convert?to=PLN", StatusCode::BAD_REQUEST, "invalid_input"),
+        ("/rates/EUR", StatusCode::BAD_GATEWAY, "network"),
+        ("/history", StatusCode::NOT_FOUND, "invalid_input"),
+    ] {
+        let (status, body) = get(target).await;
+        assert_eq!(
+            (status, body["error"]["kind"].as_str()),
+            (expected, Some(kind)),
+            "{}",
+            target
+        );
+    }
+
+    // Shutting down persists what was fetched.
+    stop.send(()).unwrap();
+    running.await.unwrap();
+    let saved = load_cache_from(&cache_file).unwrap();
+    assert!(saved.contains_key(&CacheItem::key(DEFAULT_PROVIDER, "USD", None)));
+    std::fs::remove_dir_all(cache_file.parent().unwrap()).ok();
+}
+
+#[tokio::test]
+async fn test_serve_answers_from_the_cache_during_a_fetch() {
+    let provider = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/latest/GBP"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string(r#"{"base": "GBP", "rates": {"GBP": 1, "PLN": 5.04}}"#)
+                .set_delay(Duration::from_millis(1500)),
+        )
+        .expect(1)
+        .mount(&provider)
+        .await;
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::PLAIN,
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    let usd = CacheItem::new(
+        DEFAULT_PROVIDER,
+        "USD",
+        HashMap::from([("PLN".to_string(), 3.98)]),
+    );
+    let cache = HashMap::from([(CacheItem::key(DEFAULT_PROVIDER, "USD", None), usd)]);
+    let server = Server::new(client(&provider), cache, CACHE_DURATION, format);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let running = tokio::spawn(Arc::new(server).run(listener, async {
+        stopped.await.ok();
+    }));
+    let get = |target: &str| {
+        let url = format!("{}{}", url, target);
+        async move {
+            let response = reqwest::get(url).await.unwrap();
+            (
+                response.status(),
+                response.json::<serde_json::Value>().await.unwrap(),
+            )
+        }
+    };
+
+    // Two requests for GBP share the slow fetch, which the mock expects once.
+    let slow = tokio::spawn(get("/convert?from=GBP&to=PLN"));
+    let also_slow = tokio::spawn(get("/rates/GBP"));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // Meanwhile a cached pair is answered at once.
+    let started = std::time::Instant::now();
+    let (status, body) = get("/convert?from=USD&to=PLN").await;
+    assert_eq!(
+        (status, body["rate"].as_f64()),
+        (StatusCode::OK, Some(3.98))
+    );
+    assert!(started.elapsed() < Duration::from_millis(500));
+    assert_eq!(slow.await.unwrap().1["rate"], 5.04);
+    assert_eq!(also_slow.await.unwrap().1["rates"]["PLN"], 5.04);
+
+    stop.send(()).unwrap();
+    running.await.unwrap();
+}
@@ -0,0 +1,1044 @@
+use chrono::NaiveDate;
+use currency::api::ProviderQuote;
+use currency::chart::{render_chart, sparkline, summary, ChartStyle};
+use currency::codes::CURRENCY_CODES;
+use currency::currencies;
+use currency::error::EXIT_UNKNOWN_CURRENCY;
+use currency::history::HistoryRecord;
+use currency::models::{describe_age, required_amount, RoundMode, SortKey, SortOrder, Threshold};
+use currency::numbers::{strip_separators, NumberFormat};
+use currency::output::{
+    alert_line, alert_status_line, amount_table, change_json, change_lines, changes_json,
+    color_enabled, comparison_line, conversion_csv, conversion_csv_header, conversion_json,
+    conversion_line, csv_record, currencies_json, currency_lines, describe_source, error_json,
+    error_line, format_change, format_rate, hidden_line, history_line, inverse_line, inverse_rate,
+    limit_entries, list_entries, matrix_json, matrix_table, override_lines, quote_json,
+    quote_table, rate_changes, rate_lines, rate_table, rates_csv, rates_json, raw_line,
+    raw_required_line, required_json, required_line, sort_by_change, timeseries_csv, watch_line,
+    ColorChoice, ListColumn, OutputFormat, RateChanges, RawValue, LIST_COLUMNS, RATES_CSV_HEADER,
+    TIMESERIES_CSV_HEADER,
+};
+use currency::overrides::{RateOverride, RateOverrides};
+use currency::{CurrencyError, RateQuote, Rates};
+use rust_decimal::Decimal;
+use std::{collections::HashMap, time::Duration};
+
+fn dec(value: &str) -> Decimal {
+    value.parse().unwrap()
+}
+
+#[test]
+fn test_conversion_line() {
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::PLAIN,
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    assert_eq!(
+        conversion_line(dec("100"), "USD", "EUR", 0.9, &format),
+        "100 USD is 90.00 EUR at an exchange rate of 0.90"
+    );
+    // Crypto amounts keep enough decimals to show satoshis.
+    assert_eq!(
+        conversion_line(dec("100"), "USD", "BTC", 1.0 / 65000.0, &format),
+        "100 USD is 0.00153846 BTC at an exchange rate of 0.0000153846"
+    );
+}
+
+#[test]
+fn test_raw_line() {
+    assert_eq!(
+        raw_line(dec("49.99"), 3.98, Decimal::ZERO, RawValue::Amount),
+        "198.9602"
+    );
+    assert_eq!(
+        raw_line(dec("100"), 3.98, Decimal::ZERO, RawValue::Amount),
+        "398"
+    );
+    assert_eq!(raw_line(dec("100"), 4.0, dec("2"), RawValue::Amount), "392");
+    assert_eq!(
+        raw_line(dec("100"), 0.0000071, dec("2"), RawValue::Rate),
+        "0.0000071"
+    );
+}
+
+#[test]
+fn test_rate_formatting() {
+    assert_eq!(format_rate(0.0000071, 2), "0.0000071");
+    assert_eq!(format_rate(0.006634, 2), "0.006634");
+    assert_eq!(format_rate(1.0 / 3.0, 2), "0.333333");
+    assert_eq!(format_rate(15423.55, 2), "15423.55");
+    assert_eq!(format_rate(151.37, 0), "151.37");
+    assert_eq!(format_rate(1234567.891, 2), "1234567.89");
+    // Exactly representable rates gain no extra digits beyond the minimum.
+    assert_eq!(format_rate(0.5, 2), "0.50");
+    assert_eq!(format_rate(2.0, 2), "2.00");
+    assert_eq!(format_rate(2.0, 0), "2");
+    assert_eq!(format_rate(0.25, 4), "0.2500");
+    assert_eq!(format_rate(0.0, 2), "0.00");
+
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::PLAIN,
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    assert_eq!(
+        conversion_line(dec("100"), "JPY", "USD", 0.006634, &format),
+        "100 JPY is 0.66 USD at an exchange rate of 0.006634"
+    );
+}
+
+#[test]
+fn test_decimal_amounts() {
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::PLAIN,
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    assert_eq!(
+        conversion_line(dec("0.1"), "USD", "PLN", 3.0, &format),
+        "0.1 USD is 0.30 PLN at an exchange rate of 3.00"
+    );
+    assert_eq!(
+        conversion_line(dec("999999999.99"), "USD", "EUR", 1.2345, &format),
+        "999999999.99 USD is 1234499999.99 EUR at an exchange rate of 1.2345"
+    );
+    assert_eq!(
+        conversion_line(dec("1234.565"), "USD", "USD", 1.0, &format),
+        "1234.565 USD is 1234.57 USD at an exchange rate of 1.00"
+    );
+}
+
+#[test]
+fn test_json_output() {
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::PLAIN,
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    let quote = RateQuote {
+        rate: 0.9234,
+        age: Duration::ZERO,
+        cached: true,
+        stale: false,
+        provider: "frankfurter".to_string(),
+        derived_via: None,
+        effective_date: None,
+        pinned: false,
+    };
+    assert_eq!(
+        conversion_json(dec("100"), "USD", "EUR", &quote, &format).to_string(),
+        r#"{"from":"USD","to":"EUR","amount":100.0,"rate":0.9234,"inverse_rate":1.0829542993285683,"converted":92.34,"cached":true,"stale":false,"provider":"frankfurter"}"#
+    );
+    // A stale rate says how old it is.
+    let stale = RateQuote {
+        age: Duration::from_secs(9 * 3600),
+        stale: true,
+        ..quote.clone()
+    };
+    let document = conversion_json(dec("100"), "USD", "EUR", &stale, &format);
+    assert_eq!(document["stale"], true);
+    assert_eq!(document["age_seconds"], 32400);
+
+    // Rates keep the order they were sorted in.
+    let entries = [("USD".to_string(), 0.5), ("EUR".to_string(), 0.25)];
+    assert_eq!(
+        rates_json("PLN", &entries).to_string(),
+        r#"{"base":"PLN","rates":{"USD":0.5,"EUR":0.25}}"#
+    );
+    assert_eq!(
+        error_json(EXIT_UNKNOWN_CURRENCY, "unknown currency code 'XYZ'").to_string(),
+        r#"{"error":{"kind":"unknown_currency","message":"unknown currency code 'XYZ'"}}"#
+    );
+}
+
+#[test]
+fn test_list_entries_order() {
+    let rates = Rates::from_map(HashMap::from([
+        ("USD".to_string(), 1.0),
+        ("EUR".to_string(), 0.9),
+        ("PLN".to_string(), 4.0),
+    ]));
+    let codes = |entries: Vec<(String, f64)>| {
+        entries
+            .into_iter()
+            .map(|(code, _)| code)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    let only = vec!["USD".to_string(), "EUR".to_string()];
+    assert_eq!(codes(list_entries(&rates, None, &only)), "USD,EUR,PLN");
+    assert_eq!(codes(list_entries(&rates, None, &[])), "EUR,PLN,USD");
+    assert_eq!(
+        codes(list_entries(
+            &rates,
+            Some((SortKey::Rate, SortOrder::Desc)),
+            &only
+        )),
+        "PLN,USD,EUR"
+    );
+
+    let mut entries = list_entries(&rates, Some((SortKey::Rate, SortOrder::Asc)), &[]);
+    assert_eq!(limit_entries(&mut entries, 0), 0);
+    assert_eq!(limit_entries(&mut entries, 3), 0);
+    assert_eq!(limit_entries(&mut entries, 1), 2);
+    assert_eq!(codes(entries), "EUR");
+    assert_eq!(
+        hidden_line(152),
+        "… and 152 more, use --limit 0 to show all"
+    );
+}
+
+#[test]
+fn test_currency_names() {
+    let usd = currencies::lookup("USD").unwrap();
+    assert_eq!((usd.name, usd.symbol), ("US Dollar", "$"));
+    let pln = currencies::lookup("PLN").unwrap();
+    assert_eq!((pln.name, pln.symbol), ("Polish Złoty", "zł"));
+    assert_eq!(currencies::lookup("XAU"), None);
+    for code in CURRENCY_CODES {
+        assert!(currencies::lookup(code).is_some(), "{} has no name", code);
+    }
+
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: true,
+        numbers: NumberFormat::PLAIN,
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    assert_eq!(
+        conversion_line(dec("100"), "USD", "EUR", 0.9, &format),
+        "100 USD ($) is 90.00 EUR (€) at an exchange rate of 0.90"
+    );
+    assert_eq!(
+        conversion_line(dec("1"), "XAU", "EUR", 2500.0, &format),
+        "1 XAU is 2500.00 EUR (€) at an exchange rate of 2500.00"
+    );
+}
+
+#[test]
+fn test_comparison_with_cached_rate() {
+    let fresh = RateQuote {
+        rate: 1.01,
+        age: Duration::ZERO,
+        cached: false,
+        stale: false,
+        provider: String::new(),
+        derived_via: None,
+        effective_date: None,
+        pinned: false,
+    };
+    let previous = RateQuote {
+        rate: 1.0,
+        age: Duration::from_secs(3 * 3600),
+        cached: true,
+        stale: false,
+        provider: String::new(),
+        derived_via: None,
+        effective_date: None,
+        pinned: false,
+    };
+    assert_eq!(
+        comparison_line("USD", "EUR", &fresh, Some(&previous), false),
+        "USD/EUR moved +1.00% since last cached 3h 0m ago"
+    );
+    assert_eq!(
+        comparison_line("USD", "EUR", &fresh, None, false),
+        "No cached USD/EUR rate to compare against"
+    );
+}
+
+#[test]
+fn test_source_description() {
+    let mut quote = RateQuote {
+        rate: 1.0,
+        age: Duration::from_secs(125),
+        cached: true,
+        stale: false,
+        provider: String::new(),
+        derived_via: None,
+        effective_date: None,
+        pinned: false,
+    };
+    assert_eq!(describe_source(&quote), "cached rate, 2m 5s old");
+    quote.provider = "open-er-api".to_string();
+    assert_eq!(
+        describe_source(&quote),
+        "cached rate from open-er-api, 2m 5s old"
+    );
+    quote.stale = true;
+    assert_eq!(
+        describe_source(&quote),
+        "stale cached rate from open-er-api, 2m 5s old"
+    );
+    quote.stale = false;
+    quote.derived_via = Some("PLN".to_string());
+    assert_eq!(
+        describe_source(&quote),
+        "cached rate from open-er-api, derived via PLN, 2m 5s old"
+    );
+    quote.derived_via = None;
+    quote.effective_date = Some("2024-05-03".to_string());
+    assert_eq!(
+        describe_source(&quote),
+        "cached rate from open-er-api as of 2024-05-03, 2m 5s old"
+    );
+    quote.effective_date = None;
+    quote.derived_via = Some("PLN".to_string());
+    quote.pinned = true;
+    assert_eq!(describe_source(&quote), "pinned rate, derived via PLN");
+}
+
+#[test]
+fn test_override_lines() {
+    let today = NaiveDate::from_ymd_opt(2026, 10, 14).unwrap();
+    let mut overrides = RateOverrides::default();
+    assert!(override_lines(&overrides, today).is_empty());
+    overrides.set(
+        "EUR",
+        "PLN",
+        RateOverride::parse("4.35", Some("2026-12-31"), today).unwrap(),
+    );
+    overrides.set("USD", "PLN", RateOverride::parse("4", None, today).unwrap());
+    overrides.set(
+        "GBP",
+        "PLN",
+        RateOverride {
+            rate: 5.1,
+            expires: Some("2026-10-01".to_string()),
+        },
+    );
+    assert_eq!(
+        override_lines(&overrides, today),
+        [
+            "EUR/PLN  4.35  (until 2026-12-31)",
+            "GBP/PLN  5.1  (expired 2026-10-01)",
+            "USD/PLN  4  (until unset)"
+        ]
+    );
+}
+
+#[test]
+fn test_rate_lines() {
+    let entries = vec![("EUR".to_string(), 0.25), ("USD".to_string(), 1.0)];
+    assert_eq!(rate_lines(&entries, false, None), ["EUR: 0.25", "USD: 1"]);
+    assert_eq!(
+        rate_lines(&entries, true, None),
+        ["EUR: 0.25 (Euro, €)", "USD: 1 (US Dollar, $)"]
+    );
+}
+
+#[test]
+fn test_rate_table() {
+    let rates = Rates::from_map(HashMap::from([
+        ("EUR".to_string(), 0.92),
+        ("JPY".to_string(), 151.37),
+        ("PLN".to_string(), 3.98),
+        ("XAU".to_string(), 0.0),
+    ]));
+    let entries = list_entries(&rates, None, &[]);
+    assert_eq!(
+        rate_table(&entries, &LIST_COLUMNS, None, 80, false),
+        [
+            "Code  Name            Rate      1/Rate",
+            "EUR   Euro            0.92     1.08696",
+            "JPY   Japanese Yen  151.37  0.00660633",
+            "PLN   Polish Złoty    3.98    0.251256",
+            "XAU                      0           -",
+        ]
+    );
+    // Too narrow for the names, which go first.
+    assert_eq!(
+        rate_table(&entries, &LIST_COLUMNS, None, 30, false),
+        [
+            "Code    Rate      1/Rate",
+            "EUR     0.92     1.08696",
+            "JPY   151.37  0.00660633",
+            "PLN     3.98    0.251256",
+            "XAU        0           -",
+        ]
+    );
+    let columns: Vec<ListColumn> = ["rate", "code"].map(|c| c.parse().unwrap()).to_vec();
+    assert_eq!(
+        rate_table(&entries[..2], &columns, None, 80, false),
+        ["  Rate  Code", "  0.92  EUR", "151.37  JPY"]
+    );
+    assert!("symbol".parse::<ListColumn>().is_err());
+
+    let colored = rate_table(&entries, &LIST_COLUMNS, None, 80, true);
+    assert!(colored.iter().all(|line| line.contains('\u{1b}')));
+    assert_eq!(colored.len(), 5);
+}
+
+#[test]
+fn test_list_changes() {
+    let mut entries = vec![
+        ("EUR".to_string(), 0.92),
+        ("GBP".to_string(), 0.79),
+        ("JPY".to_string(), 151.37),
+        ("PLN".to_string(), 3.98),
+        ("XAU".to_string(), 0.0004),
+    ];
+    let previous = HashMap::from([
+        ("EUR".to_string(), 0.93),
+        ("GBP".to_string(), 0.79),
+        ("JPY".to_string(), 150.0),
+        ("XAU".to_string(), 0.0),
+    ]);
+    let changes = rate_changes(&entries, &previous);
+    assert!((changes["EUR"].unwrap() + 1.0752688).abs() < 1e-6);
+    assert_eq!(changes["GBP"], Some(0.0));
+    // Neither a missing nor a zero rate yields a number.
+    assert_eq!(changes["PLN"], None);
+    assert_eq!(changes["XAU"], None);
+
+    assert_eq!(format_change(changes["EUR"]), "-1.08%");
+    assert_eq!(format_change(changes["JPY"]), "+0.91%");
+    assert_eq!(format_change(changes["GBP"]), "+0.00%");
+    assert_eq!(format_change(None), "new");
+
+    let codes = |entries: &[(String, f64)]| {
+        entries
+            .iter()
+            .map(|(code, _)| code.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    // By size whichever way the rate moved, with the new currencies last either way.
+    sort_by_change(&mut entries, &changes, SortOrder::Desc);
+    assert_eq!(codes(&entries), "EUR,JPY,GBP,PLN,XAU");
+    sort_by_change(&mut entries, &changes, SortOrder::Asc);
+    assert_eq!(codes(&entries), "GBP,JPY,EUR,PLN,XAU");
+
+    let entries = &entries[1..4];
+    assert_eq!(
+        rate_table(entries, &[ListColumn::Code], Some(&changes), 80, false),
+        [
+            "Code  Change",
+            "JPY   +0.91%",
+            "EUR   -1.08%",
+            "PLN      new"
+        ]
+    );
+    let colored = rate_table(entries, &[ListColumn::Code], Some(&changes), 80, true);
+    assert!(colored[1].contains("\u{1b}[32m+0.91%"));
+    assert!(colored[2].contains("\u{1b}[31m-1.08%"));
+    assert_eq!(
+        rate_lines(entries, true, Some(&changes)),
+        [
+            "JPY: 151.37 +0.91% (Japanese Yen, ¥)",
+            "EUR: 0.92 -1.08% (Euro, €)",
+            "PLN: 3.98 new (Polish Złoty, zł)",
+        ]
+    );
+
+    assert_eq!(
+        changes_json(&entries[1..], &changes).to_string(),
+        r#"{"EUR":-1.0752688172043021,"PLN":null}"#
+    );
+    // Without the earlier rates every change is null, but the codes are all there.
+    assert_eq!(
+        changes_json(&entries[1..], &RateChanges::new()).to_string(),
+        r#"{"EUR":null,"PLN":null}"#
+    );
+    // A currency that is new, or not compared at all, leaves the column empty.
+    assert_eq!(
+        rates_csv("USD", &entries[1..], Some(&changes)),
+        ["USD,EUR,0.92,-1.0752688172043021", "USD,PLN,3.98,"]
+    );
+    assert_eq!(
+        rates_csv("USD", &entries[1..], Some(&HashMap::new())),
+        ["USD,EUR,0.92,", "USD,PLN,3.98,"]
+    );
+}
+
+#[test]
+fn test_quote_output() {
+    let quotes = vec![
+        ProviderQuote {
+            provider: "exchangerate-api",
+            rate: Ok(3.98),
+        },
+        ProviderQuote {
+            provider: "open-er-api",
+            rate: Err(CurrencyError::Timeout(Duration::from_secs(15))),
+        },
+        ProviderQuote {
+            provider: "frankfurter",
+            rate: Ok(4.02),
+        },
+    ];
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::PLAIN,
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    assert_eq!(
+        quote_table("USD", "PLN", None, &quotes, &format),
+        [
+            "Provider          Rate",
+            "exchangerate-api  3.98  worst",
+            "open-er-api          -  unavailable (request timed out after 15s)",
+            "frankfurter       4.02  best",
+            "Spread: 0.04 PLN (1.01%) between frankfurter and exchangerate-api",
+        ]
+    );
+    let amount = Some(Decimal::from(250));
+    assert_eq!(
+        quote_table("USD", "PLN", amount, &quotes, &format),
+        [
+            "Provider          Rate      PLN",
+            "exchangerate-api  3.98   995.00  worst",
+            "open-er-api          -        -  unavailable (request timed out after 15s)",
+            "frankfurter       4.02  1005.00  best",
+            "Spread: 0.04 PLN (1.01%) between frankfurter and exchangerate-api, 10.00 PLN on 250 USD",
+        ]
+    );
+    // One answer has no spread, best or worst.
+    assert_eq!(
+        quote_table("USD", "PLN", None, &quotes[..2], &format),
+        [
+            "Provider          Rate",
+            "exchangerate-api  3.98",
+            "open-er-api          -  unavailable (request timed out after 15s)",
+        ]
+    );
+
+    let document = quote_json("USD", "PLN", amount, &quotes, &format);
+    assert_eq!(document["amount"], 250.0);
+    assert_eq!(
+        document["providers"][0],
+        serde_json::json!({ "provider": "exchangerate-api", "rate": 3.98, "converted": 995.0 })
+    );
+    assert_eq!(document["providers"][1]["error"]["kind"], "network");
+    assert_eq!(document["summary"]["best"], "frankfurter");
+    assert_eq!(document["summary"]["worst"], "exchangerate-api");
+    assert_eq!(document["summary"]["converted_spread"], 10.0);
+    assert!(quote_json("USD", "PLN", None, &quotes[..2], &format)["summary"].is_null());
+}
+
+#[test]
+fn test_age_description() {
+    assert_eq!(describe_age(Duration::from_secs(42)), "42s");
+    assert_eq!(describe_age(Duration::from_secs(125)), "2m 5s");
+    assert_eq!(describe_age(Duration::from_secs(3 * 3600 + 60)), "3h 1m");
+    assert_eq!(describe_age(Duration::from_secs(2 * 86400 + 7200)), "2d 2h");
+}
+
+#[test]
+fn test_watch_line() {
+    let line = "1000 USD is 3980.00 PLN at an exchange rate of 3.98";
+    assert_eq!(
+        watch_line(line, "2024-03-01 12:00:00", None, 4, None, false),
+        "[2024-03-01 12:00:00] 1000 USD is 3980.00 PLN at an exchange rate of 3.98"
+    );
+    assert_eq!(
+        watch_line(line, "12:00:00", Some(0.0031), 4, None, false),
+        "[12:00:00] 1000 USD is 3980.00 PLN at an exchange rate of 3.98 (+0.0031 ▲)"
+    );
+    assert_eq!(
+        watch_line(line, "12:00:00", Some(-0.0012), 4, Some("12:01:00"), false),
+        "[12:00:00] 1000 USD is 3980.00 PLN at an exchange rate of 3.98 (-0.0012 ▼) [stale since 12:01:00]"
+    );
+    assert!(watch_line(line, "12:00:00", Some(0.0), 4, None, false).ends_with("(+0.0000 =)"));
+}
+
+#[test]
+fn test_alert_lines() {
+    let threshold = Threshold::new(Some(4.2), None).unwrap();
+    assert_eq!(
+        alert_status_line("2024-03-01 12:00:00", "EUR", "PLN", 4.2512, &threshold),
+        "[2024-03-01 12:00:00] EUR/PLN is 4.2512, alerting below 4.2"
+    );
+    assert_eq!(
+        alert_line("EUR", "PLN", 4.1987, "below 4.2", false),
+        "ALERT: EUR/PLN is 4.1987, below 4.2"
+    );
+    assert!(alert_line("EUR", "PLN", 4.1987, "below 4.2", true).contains('\u{1b}'));
+}
+
+#[test]
+fn test_change_lines() {
+    let quote = |rate: f64, effective_date: &str| RateQuote {
+        rate,
+        age: Duration::ZERO,
+        cached: false,
+        stale: false,
+        provider: String::new(),
+        derived_via: None,
+        effective_date: Some(effective_date.to_string()),
+        pinned: false,
+    };
+    let day = |day: u32| NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+    let start = quote(4.3395, "2024-03-01");
+    // 2024-03-09 is a Saturday, quoted at Friday's rate.
+    let end = quote(4.3123, "2024-03-08");
+
+    assert_eq!(
+        change_lines("EUR", "PLN", (day(1), &start), (day(9), &end), false),
+        vec![
+            "EUR/PLN",
+            "  2024-03-01  4.3395",
+            "  2024-03-09  4.3123 (rate of 2024-03-08)",
+            "  ▼ -0.0272 (-0.63%)",
+        ]
+    );
+    assert_eq!(
+        change_lines("EUR", "PLN", (day(9), &end), (day(1), &start), false)[3],
+        "  ▲ +0.0272 (+0.63%)"
+    );
+    assert_eq!(
+        change_lines("EUR", "PLN", (day(1), &start), (day(1), &start), false)[3],
+        "  = 0 (0.00%)"
+    );
+    assert!(
+        change_lines("EUR", "PLN", (day(1), &start), (day(9), &end), true)[3].contains('\u{1b}')
+    );
+
+    let json = change_json("EUR", "PLN", (day(1), &start), (day(9), &end));
+    assert_eq!(json["start"]["effective_date"], "2024-03-01");
+    assert_eq!(json["end"]["date"], "2024-03-09");
+    assert_eq!(json["end"]["effective_date"], "2024-03-08");
+    assert_eq!(json["end"]["rate"], 4.3123);
+    assert!((json["change_percent"].as_f64().unwrap() + 0.6268).abs() < 1e-4);
+}
+
+#[test]
+fn test_history_line() {
+    let mut record = HistoryRecord {
+        timestamp: "2024-03-01T13:00:00+01:00".to_string(),
+        from: "USD".to_string(),
+        to: "PLN".to_string(),
+        amount: dec("100"),
+        rate: 3.98,
+        converted: dec("398.00"),
+        fee_percent: Decimal::ZERO,
+        provider: "exchangerate-api".to_string(),
+        cached: false,
+        pinned: false,
+    };
+    assert_eq!(
+        history_line(&record),
+        "2024-03-01 13:00:00  100 USD = 398.00 PLN at 3.98 (exchangerate-api, live)"
+    );
+    record.fee_percent = dec("1.5");
+    record.converted = dec("392.03");
+    record.cached = true;
+    assert_eq!(
+        history_line(&record),
+        "2024-03-01 13:00:00  100 USD = 392.03 PLN at 3.98 after a 1.5% fee (exchangerate-api, cached)"
+    );
+}
+
+#[test]
+fn test_charts() {
+    let day = |day: u32| NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+    let series: Vec<(NaiveDate, f64)> = [4.0, 4.1, 4.2, 4.4, 4.7, 4.4]
+        .into_iter()
+        .enumerate()
+        .map(|(index, rate)| (day(index as u32 + 1), rate))
+        .collect();
+    assert_eq!(
+        render_chart(&series, ChartStyle::Spark, 80).unwrap(),
+        "2024-01-01 ▁▂▃▅█▅ 2024-01-06\n\
+         min 4  max 4.7  avg 4.3  4 → 4.4 (+10.00%)"
+    );
+    // A constant series draws flat through the middle instead of dividing by zero.
+    assert_eq!(sparkline(&[2.0, 2.0, 2.0]), "▅▅▅");
+    assert_eq!(summary(&[2.0, 2.0]), "min 2  max 2  avg 2  2 → 2 (+0.00%)");
+    assert_eq!(summary(&[0.0, 1.0]), "min 0  max 1  avg 0.5  0 → 1");
+
+    let plot = render_chart(&series, ChartStyle::Full, 80).unwrap();
+    let lines: Vec<&str> = plot.lines().collect();
+    assert_eq!(lines.len(), 11);
+    assert_eq!(lines[0], "4.7 |    *");
+    assert_eq!(lines[7], "  4 |*");
+    assert_eq!(lines[8], "    +------");
+
+    // Long series are averaged down to the width; too narrow a terminal gets no chart.
+    let long: Vec<(NaiveDate, f64)> = (1..=31).map(|d| (day(d), d as f64)).collect();
+    let spark = render_chart(&long, ChartStyle::Spark, 32).unwrap();
+    assert_eq!(spark.lines().next().unwrap().chars().count(), 32);
+    assert_eq!(render_chart(&long, ChartStyle::Spark, 31), None);
+    assert_eq!(render_chart(&long, ChartStyle::Full, 13), None);
+    assert_eq!(render_chart(&[], ChartStyle::Spark, 80), None);
+}
+
+#[test]
+fn test_inverse_line() {
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::default(),
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    assert_eq!(
+        inverse_line("USD", "PLN", 3.98, &format).as_deref(),
+        Some("1 PLN = 0.251256 USD")
+    );
+    assert_eq!(
+        inverse_line("USD", "JPY", 0.0066, &format).as_deref(),
+        Some("1 JPY = 151.515 USD")
+    );
+    assert_eq!(inverse_rate(4.0), Some(0.25));
+    for broken in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e-320] {
+        assert_eq!(inverse_rate(broken), None, "{}", broken);
+        assert_eq!(inverse_line("USD", "PLN", broken, &format), None);
+    }
+}
+
+#[test]
+fn test_color_output() {
+    assert!(color_enabled(ColorChoice::Always, false));
+    assert!(!color_enabled(ColorChoice::Never, true));
+    assert!(!color_enabled(ColorChoice::Auto, false));
+    assert_eq!("always".parse(), Ok(ColorChoice::Always));
+    assert!("sometimes".parse::<ColorChoice>().is_err());
+
+    let quote = |rate: f64| RateQuote {
+        rate,
+        age: Duration::from_secs(60),
+        cached: true,
+        stale: false,
+        provider: String::new(),
+        derived_via: None,
+        effective_date: None,
+        pinned: false,
+    };
+    let line = |color: bool| {
+        let format = OutputFormat {
+            precision: 2,
+            round_mode: RoundMode::Nearest,
+            with_names: false,
+            numbers: NumberFormat::default(),
+            fee: dec("2"),
+            color,
+        };
+        [
+            conversion_line(dec("100"), "USD", "EUR", 0.92, &format),
+            comparison_line("USD", "EUR", &quote(0.92), Some(&quote(0.9)), color),
+            watch_line("x", "12:00:00", Some(-0.01), 4, Some("12:01:00"), color),
+            error_line("Error", "boom", color),
+        ]
+    };
+    // The non-terminal path must stay free of escape codes so piped output is clean.
+    let plain = line(false);
+    assert!(plain.iter().all(|line| !line.contains('\u{1b}')));
+    assert_eq!(plain[3], "Error: boom");
+    let colored = line(true);
+    assert!(colored.iter().all(|line| line.contains('\u{1b}')));
+    assert!(colored[1].contains("\u{1b}[32m+2.22%"));
+    assert!(colored[2].contains("\u{1b}[31m(-0.0100 ▼)"));
+}
+
+#[test]
+fn test_number_styles() {
+    let style = |locale: &str| NumberFormat::for_locale(locale).unwrap();
+    assert_eq!(style("en-US").apply("1234567.89"), "1,234,567.89");
+    assert_eq!(style("en_GB.UTF-8").apply("-1234.5"), "-1,234.5");
+    assert_eq!(style("de-DE").apply("1234567.89"), "1.234.567,89");
+    assert_eq!(style("pl_PL.UTF-8").apply("1234567.89"), "1 234 567,89");
+    assert_eq!(style("fr").apply("999.5"), "999,5");
+    assert_eq!(style("de-CH").apply("1234567.89"), "1'234'567.89");
+    assert_eq!(style("C").apply("1234567.89"), "1234567.89");
+    assert_eq!(style("en").apply("123456"), "123,456");
+    assert_eq!(
+        NumberFormat::for_locale("xx-YY").unwrap_err(),
+        "unknown locale 'xx-YY', expected e.g. en-US, de-DE or pl-PL"
+    );
+
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: style("pl-PL"),
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    assert_eq!(
+        conversion_line(dec("1234567.89"), "USD", "PLN", 4.0, &format),
+        "1 234 567,89 USD is 4 938 271,56 PLN at an exchange rate of 4,00"
+    );
+    // JSON keeps plain numbers whatever the locale.
+    let quote = RateQuote {
+        rate: 4.0,
+        age: Duration::ZERO,
+        cached: false,
+        stale: false,
+        provider: String::new(),
+        derived_via: None,
+        effective_date: None,
+        pinned: false,
+    };
+    assert_eq!(
+        conversion_json(dec("1234567.89"), "USD", "PLN", &quote, &format)["converted"],
+        4938271.56
+    );
+}
+
+#[test]
+fn test_separator_stripping() {
+    assert_eq!(
+        strip_separators("1,234,567.89").as_deref(),
+        Some("1234567.89")
+    );
+    assert_eq!(
+        strip_separators("1.234.567,89").as_deref(),
+        Some("1234567.89")
+    );
+    assert_eq!(strip_separators("1,234").as_deref(), Some("1234"));
+    assert_eq!(strip_separators("12,50").as_deref(), Some("12.50"));
+    assert_eq!(strip_separators("1.234.567").as_deref(), Some("1234567"));
+    assert_eq!(strip_separators("1_000_000").as_deref(), Some("1000000"));
+    assert_eq!(strip_separators("1'234.5").as_deref(), Some("1234.5"));
+    assert_eq!(strip_separators("-1,000.5").as_deref(), Some("-1000.5"));
+    assert_eq!(strip_separators("1000"), None);
+    assert_eq!(strip_separators("1,2,3"), None);
+    assert_eq!(strip_separators("12,34.5"), None);
+    assert_eq!(strip_separators("1.2.3,4"), None);
+    assert_eq!(strip_separators("abc,5"), None);
+}
+
+#[test]
+fn test_conversion_with_fee() {
+    let format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::PLAIN,
+        fee: dec("3"),
+        color: false,
+    };
+    assert_eq!(
+        conversion_line(dec("100"), "USD", "PLN", 3.98, &format),
+        "100 USD is 398.00 PLN at an exchange rate of 3.98, 386.06 PLN after a 3% fee (fee: 11.94 PLN)"
+    );
+    let quote = RateQuote {
+        rate: 3.98,
+        age: Duration::ZERO,
+        cached: false,
+        stale: false,
+        provider: String::new(),
+        derived_via: None,
+        effective_date: None,
+        pinned: false,
+    };
+    let document = conversion_json(dec("100"), "USD", "PLN", &quote, &format);
+    assert_eq!(document["converted"], 398.0);
+    assert_eq!(document["converted_after_fee"], 386.06);
+    assert_eq!(document["fee"], 11.94);
+    assert_eq!(document["fee_percent"], 3.0);
+
+    // --invert solves for the USD needed to end up with 386.06 PLN after the fee.
+    let required = required_amount(dec("386.06"), 3.98, format.fee, 2, RoundMode::Nearest).unwrap();
+    assert_eq!(
+        required_line(dec("386.06"), &required, "USD", "PLN", 3.98, &format),
+        "You need 97.00 USD to get 386.06 PLN at an exchange rate of 3.98, 100.00 USD after a 3% fee (fee: 3.00 USD)"
+    );
+    let document = required_json(dec("386.06"), &required, "USD", "PLN", &quote, &format);
+    assert_eq!(document["solved_for"], "amount");
+    assert_eq!(
+        (document["amount"].as_f64(), document["converted"].as_f64()),
+        (Some(97.0), Some(386.06))
+    );
+    assert_eq!(
+        (
+            document["amount_with_fee"].as_f64(),
+            document["fee"].as_f64()
+        ),
+        (Some(100.0), Some(3.0))
+    );
+    assert_eq!(
+        raw_required_line(dec("386.06"), 3.98, format.fee, RawValue::Amount).as_deref(),
+        Ok("100")
+    );
+    assert!(raw_required_line(dec("1"), 0.0, format.fee, RawValue::Amount).is_err());
+}
+
+#[test]
+fn test_amount_table() {
+    let mut format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::PLAIN,
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    let amounts = [dec("10"), dec("50"), dec("100"), dec("500")];
+    assert_eq!(
+        amount_table(&amounts, "EUR", "PLN", 4.35, &format),
+        [
+            " 10 EUR →   43.50 PLN",
+            " 50 EUR →  217.50 PLN",
+            "100 EUR →  435.00 PLN",
+            "500 EUR → 2175.00 PLN",
+            "at an exchange rate of 4.35"
+        ]
+    );
+    format.fee = dec("2");
+    format.precision = 0;
+    format.numbers = NumberFormat::for_locale("en-US").unwrap();
+    assert_eq!(
+        amount_table(&[dec("100"), dec("1000")], "EUR", "PLN", 4.35, &format),
+        [
+            "  100 EUR →   426 PLN",
+            "1,000 EUR → 4,263 PLN",
+            "at an exchange rate of 4.35, after a 2% fee"
+        ]
+    );
+}
+
+#[test]
+fn test_currency_list() {
+    let names = currencies::iso_names();
+    let francs = currencies::matching(&names, "FRANC");
+    assert!(francs.contains(&("CHF", "Swiss Franc")));
+    assert!(francs.iter().all(|(_, name)| name.contains("Franc")));
+    // A filter matches codes as well as names.
+    assert_eq!(
+        currencies::matching(&names, "pln"),
+        [("PLN", "Polish Złoty")]
+    );
+    assert!(currencies::matching(&names, "doubloon").is_empty());
+
+    let entries = [("CHF", "Swiss Franc"), ("USDT", "Tether")];
+    assert_eq!(
+        currency_lines(&entries),
+        ["CHF   Swiss Franc", "USDT  Tether"]
+    );
+    assert_eq!(
+        currencies_json("ISO 4217", &entries[..1]),
+        serde_json::json!({
+            "source": "ISO 4217",
+            "currencies": [{ "code": "CHF", "name": "Swiss Franc" }],
+        })
+    );
+}
+
+#[test]
+fn test_matrix_output() {
+    let codes: Vec<String> = ["USD", "EUR", "JPY"].map(String::from).to_vec();
+    let cells = vec![
+        vec![Some(1.0), Some(0.92), Some(151.37)],
+        vec![Some(1.0 / 0.92), Some(1.0), None],
+        vec![Some(1.0 / 151.37), None, Some(1.0)],
+    ];
+    assert_eq!(
+        matrix_table(&codes, &cells, false),
+        [
+            "            USD     EUR       JPY",
+            "USD      1.0000  0.9200  151.3700",
+            "EUR     1.08696  1.0000         —",
+            "JPY  0.00660633       —    1.0000",
+        ]
+    );
+    let document = matrix_json(&codes, &cells);
+    assert_eq!(document["USD"]["JPY"], 151.37);
+    assert_eq!(document["EUR"]["EUR"], 1.0);
+    assert!(document["JPY"]["EUR"].is_null());
+}
+
+/// Reads one RFC 4180 record back into its fields.
+fn parse_csv_record(line: &str) -> Vec<String> {
+    let (mut fields, mut field, mut quoted) = (Vec::new(), String::new(), false);
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[test]
+fn test_csv_output() {
+    let fields = ["plain", "a,b", "say \"hi\"", "two\nlines", ""];
+    let record = csv_record(&fields);
+    assert_eq!(record, "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",");
+    assert_eq!(parse_csv_record(&record), fields);
+    assert_eq!(
+        parse_csv_record(&csv_record(&RATES_CSV_HEADER)),
+        RATES_CSV_HEADER
+    );
+
+    let entries = vec![("EUR".to_string(), 0.92), ("JPY".to_string(), 151.372)];
+    let rows: Vec<Vec<String>> = rates_csv("USD", &entries, None)
+        .iter()
+        .map(|row| parse_csv_record(row))
+        .collect();
+    assert_eq!(rows, [["USD", "EUR", "0.92"], ["USD", "JPY", "151.372"]]);
+
+    let day = |day| NaiveDate::from_ymd_opt(2024, 4, day).unwrap();
+    assert_eq!(
+        timeseries_csv(&[(day(1), 3.98), (day(2), 4.0125)]),
+        ["2024-04-01,3.98", "2024-04-02,4.0125"]
+    );
+    assert_eq!(csv_record(&TIMESERIES_CSV_HEADER), "date,rate");
+
+    // Numbers are never localized, whatever --locale asks for elsewhere.
+    let mut format = OutputFormat {
+        precision: 2,
+        round_mode: RoundMode::Nearest,
+        with_names: false,
+        numbers: NumberFormat::for_locale("de-DE").unwrap(),
+        fee: Decimal::ZERO,
+        color: false,
+    };
+    assert_eq!(
+        csv_record(&conversion_csv_header(&format)),
+        "amount,from,to,rate,converted"
+    );
+    let row = conversion_csv(dec("1234.5"), "USD", "PLN", 3.98, &format);
+    assert_eq!(row, "1234.5,USD,PLN,3.98,4913.31");
+    assert_eq!(
+        parse_csv_record(&row),
+        ["1234.5", "USD", "PLN", "3.98", "4913.31"]
+    );
+
+    format.fee = dec("2");
+    assert_eq!(
+        conversion_csv_header(&format),
+        [
+            "amount",
+            "from",
+            "to",
+            "rate",
+            "converted",
+            "fee_percent",
+            "converted_after_fee",
+            "fee"
+        ]
+    );
+    assert_eq!(
+        conversion_csv(dec("100"), "USD", "PLN", 3.98, &format),
+        "100,USD,PLN,3.98,398.00,2,390.04,7.96"
+    );
+}
@@ -0,0 +1,1191 @@
+use chrono::NaiveDate;
+use clap_complete::Shell;
+use currency::aliases::{parse_pair, with_aliases, Aliases};
+use currency::batch::{
+    parse_batch_line, parse_batch_line_with, parse_columns, Column, DEFAULT_COLUMNS,
+};
+use currency::cache::PrunePolicy;
+use currency::cli::{
+    build_cli, completion_script, parse_conversion_args, parse_conversion_args_with, ConversionArgs,
+};
+use currency::codes::{describe_unknown, suggestions, validate_currency_code};
+use currency::config::{
+    parse_duration, Config, Flags, Settings, CACHE_DURATION, CONFIG_TEMPLATE,
+    DEFAULT_BASE_CURRENCY, DEFAULT_PRECISION, DEFAULT_PROVIDER, DEFAULT_REQUEST_TIMEOUT,
+};
+use currency::error::EXIT_UNKNOWN_CURRENCY;
+use currency::models::{
+    apply_fee, check_nonzero, convert_amount, parse_amount, parse_date, parse_fee, required_amount,
+    round_amount, series_dates, Interval, RatesResponse, RoundMode, SortKey, SortOrder, Threshold,
+};
+use currency::repl::{parse_repl_line, ReplCommand};
+use currency::{CurrencyError, Rates};
+use rust_decimal::Decimal;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+fn dec(value: &str) -> Decimal {
+    value.parse().unwrap()
+}
+
+#[test]
+fn test_currency_code_validation() {
+    assert!(validate_currency_code("USD").is_ok());
+    assert!(validate_currency_code("PLN").is_ok());
+    assert!(validate_currency_code("eur").is_ok());
+    assert!(validate_currency_code("btc").is_ok());
+    assert!(validate_currency_code("DOGE").is_ok());
+    assert_eq!(
+        validate_currency_code("USDD").unwrap_err().to_string(),
+        "USDD is not a known currency code, did you mean USD or USDC or USDT?"
+    );
+    let error = validate_currency_code("XYZ").unwrap_err();
+    assert!(matches!(error, CurrencyError::UnknownCurrency(ref code) if code == "XYZ"));
+    assert_eq!(error.exit_code(), EXIT_UNKNOWN_CURRENCY);
+    assert_eq!(error.to_string(), "XYZ is not a known currency code");
+    for junk in ["US", "E", "EURO", "QQQQ", "XAU", ""] {
+        assert!(validate_currency_code(junk).is_err(), "{:?}", junk);
+    }
+}
+
+#[test]
+fn test_currency_code_suggestions() {
+    assert_eq!(suggestions("EEUR"), ["EUR"]);
+    assert_eq!(suggestions("eeur"), ["EUR"]);
+    // Swapped letters, with common currencies ranked ahead of equally close ones.
+    assert_eq!(suggestions("UDS"), ["USD", "UZS"]);
+    assert_eq!(suggestions("ERU"), ["EUR", "ERN", "MRU"]);
+    assert_eq!(suggestions("GPB"), ["GBP"]);
+    assert_eq!(suggestions("US"), ["USD", "UZS"]);
+    assert_eq!(suggestions("EURO"), ["EUR"]);
+    assert_eq!(suggestions("ETHH"), ["ETH"]);
+    assert!(suggestions("QQQQ").is_empty());
+    assert!(suggestions("ZZ").is_empty());
+    assert!(suggestions("EUR").contains(&"EUR"));
+    assert_eq!(
+        describe_unknown("EEUR"),
+        "EEUR is not a known currency code, did you mean EUR?"
+    );
+}
+
+#[test]
+fn test_amount_parsing() {
+    assert_eq!(parse_amount("100").unwrap(), dec("100"));
+    assert_eq!(parse_amount("12.5").unwrap(), dec("12.5"));
+    assert_eq!(
+        parse_amount("abc").unwrap_err(),
+        "amount 'abc' is not a valid number"
+    );
+    assert!(parse_amount("inf").is_err());
+    assert!(parse_amount("NaN").is_err());
+    // Kept exactly, digits and scale both.
+    assert_eq!(parse_amount("1234.565").unwrap().to_string(), "1234.565");
+    assert_eq!(parse_amount("1e3").unwrap(), dec("1000"));
+    assert_eq!(parse_amount("1,234,567.89").unwrap(), dec("1234567.89"));
+    assert_eq!(parse_amount("1.234,56").unwrap(), dec("1234.56"));
+}
+
+#[test]
+fn test_amount_forms() {
+    for (input, expected) in [
+        ("1,000.50", "1000.50"),
+        ("1 000,50", "1000.50"),
+        ("1.000,50", "1000.50"),
+        // A lone comma before three digits groups them; before any other count it is
+        // the decimal comma. A lone point is always the decimal point.
+        ("1,000", "1000"),
+        ("1,50", "1.50"),
+        ("1,5", "1.5"),
+        ("1.000", "1.000"),
+        ("1.000.000", "1000000"),
+        ("10k", "10000"),
+        ("10K", "10000"),
+        ("1.5m", "1500000"),
+        ("1,5k", "1500"),
+        ("2.5 k", "2500"),
+        ("$100", "100"),
+        ("€ 1.234,50", "1234.50"),
+        ("zł100", "100"),
+        ("R$10k", "10000"),
+        ("0", "0"),
+    ] {
+        assert_eq!(parse_amount(input), Ok(dec(expected)), "{}", input);
+    }
+    for (input, error) in [
+        ("-50", "amount '-50' must not be negative"),
+        ("-0.01", "amount '-0.01' must not be negative"),
+        ("$-5", "amount '$-5' must not be negative"),
+        ("NaN", "amount 'NaN' is not a finite number"),
+        ("inf", "amount 'inf' is not a finite number"),
+        ("-Infinity", "amount '-Infinity' is not a finite number"),
+        ("1e400", "amount '1e400' is too large"),
+        ("10x", "amount '10x' is not a valid number"),
+        ("k", "amount 'k' is not a valid number"),
+        ("1,2,3", "amount '1,2,3' is not a valid number"),
+    ] {
+        assert_eq!(parse_amount(input).unwrap_err(), error, "{}", input);
+    }
+
+    assert_eq!(
+        check_nonzero(dec("0"), false).unwrap_err(),
+        "the amount is zero; pass --allow-zero to convert it anyway"
+    );
+    assert_eq!(check_nonzero(dec("0.00"), true), Ok(dec("0")));
+    assert_eq!(check_nonzero(dec("5"), false), Ok(dec("5")));
+}
+
+#[test]
+fn test_amount_expressions() {
+    assert_eq!(parse_amount("12.5*3+4").unwrap(), dec("41.5"));
+    assert_eq!(parse_amount("2+3*4").unwrap(), dec("14"));
+    assert_eq!(parse_amount("(2+3)*4").unwrap(), dec("20"));
+    assert_eq!(parse_amount("10-4-3").unwrap(), dec("3"));
+    assert_eq!(parse_amount("100/4/5").unwrap(), dec("5"));
+    assert_eq!(parse_amount("-2*(1.5 - 2.5)").unwrap(), dec("2"));
+    assert_eq!(
+        parse_amount("-2*(1.5 + 0.5)").unwrap_err(),
+        "amount '-2*(1.5 + 0.5)' must not be negative"
+    );
+    assert_eq!(
+        parse_amount("12.5*").unwrap_err(),
+        "could not parse amount expression '12.5*'"
+    );
+    assert!(parse_amount("(1+2").is_err());
+    assert!(parse_amount("1/0").is_err());
+    assert!(parse_amount("1..2+3").is_err());
+    assert_eq!(parse_amount("0.1+0.2").unwrap().to_string(), "0.3");
+    assert_eq!(parse_amount("10/4").unwrap().to_string(), "2.5");
+}
+
+#[test]
+fn test_fee_parsing() {
+    assert_eq!(parse_fee("2.5").unwrap(), dec("2.5"));
+    assert_eq!(parse_fee(" 3% ").unwrap(), dec("3"));
+    assert_eq!(parse_fee("0").unwrap(), Decimal::ZERO);
+    assert_eq!(
+        parse_fee("-1").unwrap_err(),
+        "fee '-1' must not be negative"
+    );
+    assert_eq!(
+        parse_fee("100").unwrap_err(),
+        "fee '100' must be below 100%"
+    );
+    assert_eq!(
+        parse_fee("lots").unwrap_err(),
+        "fee 'lots' is not a percentage"
+    );
+}
+
+#[test]
+fn test_fee_applies_to_rate() {
+    let breakdown = |amount: &str, rate: f64, fee: &str, mode: RoundMode| {
+        let breakdown = apply_fee(dec(amount), rate, dec(fee), 2, mode);
+        (breakdown.mid_market, breakdown.after_fee, breakdown.fee)
+    };
+    let values = |mid: &str, after: &str, fee: &str| (dec(mid), dec(after), dec(fee));
+
+    // 2.5% off a rate of 4 is a rate of 3.9.
+    assert_eq!(
+        breakdown("100", 4.0, "2.5", RoundMode::Nearest),
+        values("400", "390", "10")
+    );
+    assert_eq!(
+        breakdown("100", 4.0, "0", RoundMode::Nearest),
+        values("400", "400", "0")
+    );
+    // Each result is rounded on its own; the fee is what separates the rounded results.
+    assert_eq!(
+        breakdown("1", 0.333, "1", RoundMode::Nearest),
+        values("0.33", "0.33", "0")
+    );
+    assert_eq!(
+        breakdown("10", 1.0, "0.05", RoundMode::Nearest),
+        values("10", "10", "0")
+    );
+    assert_eq!(
+        breakdown("10", 1.0, "0.05", RoundMode::Floor),
+        values("10", "9.99", "0.01")
+    );
+    // 0.875 after the fee sits exactly on the half.
+    assert_eq!(
+        breakdown("1", 1.0, "12.5", RoundMode::HalfEven),
+        values("1", "0.88", "0.12")
+    );
+}
+
+#[test]
+fn test_required_amount() {
+    let required = |target: &str, rate: f64, fee: &str, mode: RoundMode| {
+        required_amount(dec(target), rate, dec(fee), 2, mode)
+            .map(|required| (required.mid_market, required.with_fee, required.fee))
+    };
+    let values = |mid: &str, with_fee: &str, fee: &str| Ok((dec(mid), dec(with_fee), dec(fee)));
+
+    assert_eq!(
+        required("1000", 4.02, "0", RoundMode::Nearest),
+        values("248.76", "248.76", "0")
+    );
+    // A fee lowers the rate, so more of the source currency is needed, not less.
+    assert_eq!(
+        required("390", 4.0, "2.5", RoundMode::Nearest),
+        values("97.5", "100", "2.5")
+    );
+    assert_eq!(
+        required("1000", 4.02, "0", RoundMode::Ceil),
+        values("248.76", "248.76", "0")
+    );
+    assert_eq!(
+        required("1", 3.0, "0", RoundMode::Floor),
+        values("0.33", "0.33", "0")
+    );
+
+    for rate in [0.0, -4.0, f64::NAN, f64::INFINITY] {
+        assert_eq!(
+            required("100", rate, "0", RoundMode::Nearest),
+            Err(format!(
+                "cannot work out the amount needed at a rate of {}",
+                rate
+            ))
+        );
+    }
+    // Too small to divide by, rather than an infinite amount.
+    assert_eq!(
+        required("1000000", 1e-30, "0", RoundMode::Nearest),
+        Err(
+            "the rate 0.000000000000000000000000000001 is too small to work out the amount needed"
+                .to_string()
+        )
+    );
+}
+
+#[test]
+fn test_decimal_conversion() {
+    let converted = |amount: &str, rate: f64, precision: usize| {
+        round_amount(
+            convert_amount(dec(amount), rate),
+            precision,
+            RoundMode::Nearest,
+        )
+        .to_string()
+    };
+    // Values that come out wrong, or only right by luck, in f64.
+    assert_eq!(convert_amount(dec("0.1"), 3.0).to_string(), "0.3");
+    assert_eq!(converted("0.1", 3.0, 2), "0.3");
+    assert_eq!(converted("999999999.99", 1.2345, 2), "1234499999.99");
+    assert_eq!(
+        convert_amount(dec("999999999.99"), 1.2345).to_string(),
+        "1234499999.987655"
+    );
+    assert_eq!(converted("1234.565", 1.0, 2), "1234.57");
+    assert_eq!(converted("1.15", 100.0, 0), "115");
+    assert_eq!(converted("0.29", 100.0, 0), "29");
+}
+
+#[test]
+fn test_date_parsing() {
+    let today = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+    assert_eq!(
+        parse_date("2023-01-15", today).unwrap(),
+        NaiveDate::from_ymd_opt(2023, 1, 15).unwrap()
+    );
+    assert_eq!(parse_date("2024-03-01", today).unwrap(), today);
+    assert_eq!(
+        parse_date("2024-03-02", today).unwrap_err(),
+        "date 2024-03-02 is in the future"
+    );
+    assert_eq!(
+        parse_date("1989-12-31", today).unwrap_err(),
+        "date 1989-12-31 is before the earliest supported date 1990-01-01"
+    );
+    assert_eq!(
+        parse_date("15/01/2023", today).unwrap_err(),
+        "invalid date '15/01/2023', expected YYYY-MM-DD"
+    );
+    assert!(parse_date("2023-02-30", today).is_err());
+}
+
+#[test]
+fn test_alert_thresholds() {
+    let below = Threshold::new(Some(4.2), None).unwrap();
+    assert_eq!(below.crossed(4.25), None);
+    assert_eq!(below.crossed(4.2), None);
+    assert_eq!(below.crossed(4.19).as_deref(), Some("below 4.2"));
+    assert_eq!(below.to_string(), "below 4.2");
+
+    let band = Threshold::new(Some(4.2), Some(4.5)).unwrap();
+    assert_eq!(band.crossed(4.3), None);
+    assert_eq!(band.crossed(4.6).as_deref(), Some("above 4.5"));
+    assert_eq!(band.to_string(), "below 4.2 or above 4.5");
+
+    assert_eq!(
+        Threshold::new(None, None).unwrap_err(),
+        "alert needs --below or --above (or both)"
+    );
+    assert_eq!(
+        Threshold::new(Some(4.5), Some(4.2)).unwrap_err(),
+        "--below 4.5 must be less than --above 4.2, or every rate would trigger the alert"
+    );
+    assert!(Threshold::new(Some(4.2), Some(4.2)).is_err());
+    assert_eq!(
+        Threshold::new(None, Some(-1.0)).unwrap_err(),
+        "--above -1 is not a positive rate"
+    );
+    assert!(Threshold::new(Some(f64::NAN), None).is_err());
+}
+
+#[test]
+fn test_series_dates() {
+    let day = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").unwrap();
+    let days = |dates: Vec<NaiveDate>| {
+        dates
+            .iter()
+            .map(|date| date.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        days(series_dates(
+            day("2024-02-27"),
+            day("2024-03-01"),
+            Interval::Daily
+        )),
+        ["2024-02-27", "2024-02-28", "2024-02-29", "2024-03-01"]
+    );
+    assert_eq!(
+        days(series_dates(
+            day("2024-01-01"),
+            day("2024-01-20"),
+            Interval::Weekly
+        )),
+        ["2024-01-01", "2024-01-08", "2024-01-15"]
+    );
+    assert_eq!(
+        days(series_dates(
+            day("2024-01-31"),
+            day("2024-04-30"),
+            Interval::Monthly
+        )),
+        ["2024-01-31", "2024-02-29", "2024-03-31", "2024-04-30"]
+    );
+    assert!(series_dates(day("2024-01-02"), day("2024-01-01"), Interval::Daily).is_empty());
+    assert_eq!("weekly".parse::<Interval>(), Ok(Interval::Weekly));
+}
+
+#[test]
+fn test_batch_line_parsing() {
+    let row = parse_batch_line(" usd, EUR ,100").unwrap();
+    assert_eq!(
+        (row.from.as_str(), row.to.as_str(), row.amount),
+        ("USD", "EUR", dec("100"))
+    );
+
+    assert_eq!(
+        parse_batch_line("USD,EUR").unwrap_err(),
+        "expected FROM,TO,AMOUNT or AMOUNT FROM TO but got 'USD,EUR'"
+    );
+    assert_eq!(
+        parse_batch_line("USD,EUR,ten").unwrap_err(),
+        "amount 'ten' is not a valid number"
+    );
+
+    let row = parse_batch_line("12.5*2  pln usd").unwrap();
+    assert_eq!(
+        (row.from.as_str(), row.to.as_str(), row.amount),
+        ("PLN", "USD", dec("25"))
+    );
+    assert!(parse_batch_line("USD EUR").is_err());
+}
+
+#[test]
+fn test_batch_columns() {
+    let columns = parse_columns("date,amount,from,to,").unwrap();
+    assert_eq!(
+        columns,
+        [
+            Column::Ignored,
+            Column::Amount,
+            Column::From,
+            Column::To,
+            Column::Ignored
+        ]
+    );
+    let row = parse_batch_line_with(r#"2024-03-01,"42.10",eur,pln,lunch"#, &columns).unwrap();
+    assert_eq!(
+        (row.from.as_str(), row.to.as_str(), row.amount),
+        ("EUR", "PLN", dec("42.10"))
+    );
+    assert_eq!(
+        parse_batch_line_with("2024-03-01,42.10,EUR", &columns).unwrap_err(),
+        "expected _,AMOUNT,FROM,TO,_ or AMOUNT FROM TO but got '2024-03-01,42.10,EUR'"
+    );
+    assert_eq!(
+        parse_columns("from,amount").unwrap_err(),
+        "invalid columns 'from,amount': 'to' must appear exactly once"
+    );
+    assert_eq!(parse_columns("from,to,amount").unwrap(), DEFAULT_COLUMNS);
+}
+
+#[test]
+fn test_conversion_args() {
+    let usd_eur = ConversionArgs {
+        from: "USD".to_string(),
+        to: "EUR".to_string(),
+        amounts: vec![dec("100")],
+        from_default: false,
+    };
+    for args in [
+        &["USD", "EUR", "100"][..],
+        &["100", "usd", "to", "eur"],
+        &["usd", "100", "eur"],
+        &["100", "USD", "->", "EUR"],
+        &["100 USD in EUR"],
+    ] {
+        assert_eq!(
+            parse_conversion_args(args, None).unwrap(),
+            usd_eur,
+            "{:?}",
+            args
+        );
+    }
+    assert_eq!(
+        parse_conversion_args(&["eur", "100"], Some("USD")).unwrap(),
+        ConversionArgs {
+            from_default: true,
+            ..usd_eur
+        }
+    );
+    assert_eq!(
+        parse_conversion_args(&["PLN", "eur,usd", "2*250"], None)
+            .unwrap()
+            .to,
+        "EUR,USD"
+    );
+
+    assert_eq!(
+        parse_conversion_args(&["USD", "EUR", "ten"], None).unwrap_err(),
+        "amount 'ten' is not a valid number"
+    );
+    assert_eq!(
+        parse_conversion_args(&["USD", "EUR", "1 000,50"], None)
+            .unwrap()
+            .amounts,
+        [dec("1000.50")]
+    );
+    assert!(parse_conversion_args(&["EUR", "100"], None)
+        .unwrap_err()
+        .starts_with("no default source currency is configured"));
+    assert_eq!(
+        parse_conversion_args(&["100", "200", "USD"], None).unwrap_err(),
+        "more than one amount (100, 200); expected a conversion such as `USD EUR 100`, \
+         `100 USD to EUR`, `USD 100 EUR`, or `EUR 100` with a default base currency"
+    );
+    assert!(parse_conversion_args(&["USD", "EUR", "GBP", "1"], None)
+        .unwrap_err()
+        .starts_with("too many currencies (USD, EUR, GBP)"));
+    assert!(parse_conversion_args(&["100", "to"], None)
+        .unwrap_err()
+        .starts_with("no currency given"));
+}
+
+#[test]
+fn test_several_amounts() {
+    let amounts = |args: &[&str], given: &[Decimal]| {
+        parse_conversion_args_with(args, None, given).map(|args| args.amounts)
+    };
+    assert_eq!(
+        amounts(&["EUR", "PLN", "10,50,100,500"], &[]),
+        Ok(vec![dec("10"), dec("50"), dec("100"), dec("500")])
+    );
+    // One number if it reads as one, decimal comma and grouped thousands alike.
+    assert_eq!(
+        amounts(&["EUR", "PLN", "10,50"], &[]),
+        Ok(vec![dec("10.50")])
+    );
+    assert_eq!(
+        amounts(&["EUR", "PLN", "1,000"], &[]),
+        Ok(vec![dec("1000")])
+    );
+    assert_eq!(
+        amounts(&["EUR", "PLN", "10k,1.5m"], &[]),
+        Ok(vec![dec("10000"), dec("1500000")])
+    );
+    assert_eq!(
+        amounts(&["EUR", "PLN", "10,x,5"], &[]).unwrap_err(),
+        "amount 'x' is not a valid number"
+    );
+    assert_eq!(
+        amounts(&["EUR", "PLN"], &[dec("10"), dec("50")]),
+        Ok(vec![dec("10"), dec("50")])
+    );
+    assert_eq!(
+        amounts(&["EUR", "PLN", "5"], &[dec("10")]).unwrap_err(),
+        "an amount (5) and --amount given; pass the amounts one way or the other"
+    );
+    assert_eq!(
+        amounts(&["EUR", "PLN,USD", "10,50,100"], &[]).unwrap_err(),
+        "several amounts need a single target currency; convert them to one currency at a time"
+    );
+    assert!(amounts(&["EUR", "PLN,USD", "100"], &[]).is_ok());
+}
+
+#[test]
+fn test_cli_definition() {
+    build_cli().debug_assert();
+    let help = build_cli().render_help().to_string();
+    assert!(help.contains("Exit codes:"));
+    assert!(help.contains("6  --offline and the rate is not cached"));
+    assert!(help.contains("7  --strict and only an expired cached rate was available"));
+    let matches = build_cli()
+        .try_get_matches_from(["currency", "list", "USD", "--strict"])
+        .unwrap();
+    assert!(matches.get_flag("STRICT"));
+    assert!(build_cli()
+        .try_get_matches_from(["currency", "-q", "-v", "list"])
+        .is_err());
+}
+
+#[test]
+fn test_completion_scripts() {
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+        let script = completion_script(shell);
+        for word in [
+            "list",
+            "timeseries",
+            "batch",
+            "interactive",
+            "cache",
+            "config",
+            "completions",
+            "--precision",
+        ] {
+            assert!(script.contains(word), "{} script lacks {}", shell, word);
+        }
+        // Currency arguments complete to the known codes.
+        for code in ["USD", "PLN", "BTC"] {
+            assert!(script.contains(code), "{} script lacks {}", shell, code);
+        }
+    }
+}
+
+#[test]
+fn test_repl_line_parsing() {
+    assert_eq!(
+        parse_repl_line("100 usd eur").unwrap(),
+        ReplCommand::Convert {
+            amount: dec("100"),
+            from: "USD".to_string(),
+            to: "EUR".to_string(),
+        }
+    );
+    assert_eq!(
+        parse_repl_line("12.5 * 3 USD EUR").unwrap(),
+        ReplCommand::Convert {
+            amount: dec("37.5"),
+            from: "USD".to_string(),
+            to: "EUR".to_string(),
+        }
+    );
+    assert_eq!(
+        parse_repl_line(" 250 ").unwrap(),
+        ReplCommand::Amount(dec("250"))
+    );
+    assert_eq!(
+        parse_repl_line("list pln").unwrap(),
+        ReplCommand::List(Some("PLN".to_string()))
+    );
+    assert_eq!(parse_repl_line("list").unwrap(), ReplCommand::List(None));
+    assert_eq!(parse_repl_line("SWAP").unwrap(), ReplCommand::Swap);
+    assert_eq!(parse_repl_line("exit").unwrap(), ReplCommand::Quit);
+    assert_eq!(parse_repl_line("").unwrap(), ReplCommand::Empty);
+    assert_eq!(
+        parse_repl_line("USD EUR").unwrap_err(),
+        "expected AMOUNT FROM TO, AMOUNT, swap, list, help or quit but got 'USD EUR'"
+    );
+    assert!(parse_repl_line("ten USD EUR").is_err());
+}
+
+#[test]
+fn test_config_parsing() {
+    let config = Config::parse(
+        r#"
+        base_currency = "USD"
+        precision = 4
+        max_age = 600
+        api_key = "secret"
+        default_fee = 2.5
+        favorites = ["usd", "EUR"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.default_base.as_deref(), Some("USD"));
+    assert_eq!(config.precision, Some(4));
+    assert_eq!(config.max_age, Some(600));
+    assert_eq!(config.api_key.as_deref(), Some("secret"));
+    assert_eq!(config.fee, Some(2.5));
+    assert_eq!(
+        config.favorites,
+        Some(vec!["usd".to_string(), "EUR".to_string()])
+    );
+    assert_eq!(config.provider, None);
+    assert!(config.unknown.is_empty());
+
+    assert_eq!(Config::parse("").unwrap(), Config::default());
+    assert!(Config::parse("precision = \"two\"").is_err());
+    assert!(Config::load(Some("/nonexistent/currency_cli/config.toml")).is_err());
+}
+
+#[test]
+fn test_config_unknown_keys_are_kept_aside() {
+    let config =
+        Config::parse("precision = 3\ntheme = \"dark\"\n[colors]\nup = \"green\"").unwrap();
+    assert_eq!(config.precision, Some(3));
+    assert_eq!(
+        config.unknown.keys().collect::<Vec<_>>(),
+        ["colors", "theme"]
+    );
+
+    let path =
+        std::env::temp_dir().join(format!("currency_cli_config_{}.toml", std::process::id()));
+    std::fs::write(&path, "precision = 3\ntheme = \"dark\"\n").unwrap();
+    let loaded = Config::load(path.to_str());
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap().precision, Some(3));
+}
+
+#[test]
+fn test_config_template() {
+    assert_eq!(Config::parse(CONFIG_TEMPLATE).unwrap(), Config::default());
+    // Every commented-out key is valid once uncommented.
+    let uncommented: String = CONFIG_TEMPLATE
+        .lines()
+        .map(|line| match line.strip_prefix("# ") {
+            Some(setting) if setting.contains(" = ") || setting.starts_with('[') => setting,
+            _ => line,
+        })
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let config = Config::parse(&uncommented).unwrap();
+    assert!(config.unknown.is_empty());
+    let settings = Settings::resolve(&Flags::default(), &config, no_env).unwrap();
+    assert_eq!(settings.default_base, "PLN");
+    assert_eq!(settings.cache_ttl, Duration::from_secs(3600));
+    assert_eq!(settings.favorites, ["USD", "EUR", "GBP"]);
+    assert_eq!(settings.aliases.resolve("Home").unwrap(), "PLN");
+    assert_eq!(
+        settings.pairs["trip"],
+        ("EUR".to_string(), "PLN".to_string())
+    );
+}
+
+fn no_env(_: &str) -> Option<String> {
+    None
+}
+
+#[test]
+fn test_settings_defaults() {
+    let settings = Settings::resolve(&Flags::default(), &Config::default(), no_env).unwrap();
+    assert_eq!(
+        settings,
+        Settings {
+            api_key: None,
+            provider: DEFAULT_PROVIDER.to_string(),
+            default_base: DEFAULT_BASE_CURRENCY.to_string(),
+            default_base_source: "the built-in default",
+            cache_ttl: CACHE_DURATION,
+            prune: PrunePolicy {
+                max_age: Some(Duration::from_secs(30 * 24 * 3600)),
+                max_entries: 200,
+                max_historical_entries: 5000,
+            },
+            precision: DEFAULT_PRECISION,
+            round_mode: RoundMode::Nearest,
+            fee: Decimal::ZERO,
+            favorites: Vec::new(),
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            history: true,
+            aliases: Aliases::default(),
+            pairs: BTreeMap::new(),
+        }
+    );
+}
+
+#[test]
+fn test_settings_precedence() {
+    let config = Config::parse(
+        r#"
+        api_key = "from-config"
+        provider = "frankfurter"
+        default_base = "eur"
+        cache_ttl = "15m"
+        cache_max_entries = 20
+        max_historical_entries = 0
+        cache_prune_ttls = 4
+        precision = 4
+        rounding = "half-even"
+        fee = 1.5
+        history = true
+        "#,
+    )
+    .unwrap();
+    let env = |name: &str| match name {
+        "API_KEY" => Some("from-env".to_string()),
+        "DEFAULT_BASE_CURRENCY" => Some("gbp".to_string()),
+        "CACHE_TTL" => Some("30s".to_string()),
+        "REQUEST_TIMEOUT" => Some("20".to_string()),
+        _ => None,
+    };
+    let flags = Flags {
+        api_key: Some("from-flag".to_string()),
+        provider: Some("open-er-api".to_string()),
+        base: Some("usd".to_string()),
+        cache_ttl: Some(Duration::from_secs(5)),
+        precision: Some(1),
+        round_mode: Some(RoundMode::Ceil),
+        fee: Some("3%".to_string()),
+        timeout: Some(Duration::from_secs(2)),
+        no_history: true,
+    };
+
+    // Flags beat everything.
+    let settings = Settings::resolve(&flags, &config, env).unwrap();
+    assert_eq!(settings.api_key.as_deref(), Some("from-flag"));
+    assert_eq!(settings.provider, "open-er-api");
+    assert_eq!(
+        (settings.default_base.as_str(), settings.default_base_source),
+        ("USD", "--base")
+    );
+    assert_eq!(settings.cache_ttl, Duration::from_secs(5));
+    // Except for pruning, which a short --cache-ttl for one run mustn't hasten.
+    assert_eq!(settings.prune.max_age, Some(Duration::from_secs(120)));
+    assert_eq!(settings.precision, 1);
+    assert_eq!(settings.round_mode, RoundMode::Ceil);
+    assert_eq!(settings.fee, dec("3"));
+    assert_eq!(settings.timeout, Duration::from_secs(2));
+    assert!(!settings.history);
+
+    // Env vars beat the config file.
+    let settings = Settings::resolve(&Flags::default(), &config, env).unwrap();
+    assert_eq!(settings.api_key.as_deref(), Some("from-env"));
+    assert_eq!(
+        (settings.default_base.as_str(), settings.default_base_source),
+        ("GBP", "DEFAULT_BASE_CURRENCY")
+    );
+    assert_eq!(settings.cache_ttl, Duration::from_secs(30));
+    assert_eq!(settings.timeout, Duration::from_secs(20));
+
+    // The config file beats the built-in defaults; empty env vars count as unset.
+    let settings =
+        Settings::resolve(&Flags::default(), &config, |_| Some(" ".to_string())).unwrap();
+    assert_eq!(settings.api_key.as_deref(), Some("from-config"));
+    assert_eq!(settings.provider, "frankfurter");
+    assert_eq!(
+        (settings.default_base.as_str(), settings.default_base_source),
+        ("EUR", "default_base in the config file")
+    );
+    assert_eq!(settings.cache_ttl, Duration::from_secs(900));
+    assert_eq!(
+        settings.prune,
+        PrunePolicy {
+            max_age: Some(Duration::from_secs(3600)),
+            max_entries: 20,
+            max_historical_entries: 0,
+        }
+    );
+    assert_eq!(settings.precision, 4);
+    assert_eq!(settings.round_mode, RoundMode::HalfEven);
+    assert_eq!(settings.fee, dec("1.5"));
+    assert_eq!(settings.timeout, DEFAULT_REQUEST_TIMEOUT);
+    assert!(settings.history);
+    let blank_env = |_: &str| Some(" ".to_string());
+    let flagged = Settings::resolve(&flags, &config, blank_env).unwrap();
+    assert_eq!(flagged.cache_ttl, Duration::from_secs(5));
+    assert_eq!(flagged.prune, settings.prune);
+    let quiet = Config::parse("history = false").unwrap();
+    assert!(
+        !Settings::resolve(&Flags::default(), &quiet, no_env)
+            .unwrap()
+            .history
+    );
+
+    // The older seconds-based forms still count, behind their duration forms.
+    let legacy = Config::parse("max_age = 600").unwrap();
+    let env = |name: &str| (name == "CACHE_DURATION").then(|| "120".to_string());
+    let settings = Settings::resolve(&Flags::default(), &legacy, env).unwrap();
+    assert_eq!(settings.cache_ttl, Duration::from_secs(120));
+    let settings = Settings::resolve(&Flags::default(), &legacy, no_env).unwrap();
+    assert_eq!(settings.cache_ttl, Duration::from_secs(600));
+}
+
+#[test]
+fn test_settings_errors() {
+    let resolve = |flags: &Flags, config: &str, env: &dyn Fn(&str) -> Option<String>| {
+        Settings::resolve(flags, &Config::parse(config).unwrap(), env).unwrap_err()
+    };
+    assert!(resolve(&Flags::default(), "provider = \"nope\"", &no_env)
+        .starts_with("unknown provider 'nope'"));
+    assert_eq!(
+        resolve(&Flags::default(), "", &|name: &str| (name == "CACHE_TTL")
+            .then(|| "soon".to_string())),
+        "CACHE_TTL: invalid duration 'soon', expected a number with an optional s, m, h or d suffix (e.g. 15m)"
+    );
+    let zero_timeout = Flags {
+        timeout: Some(Duration::ZERO),
+        ..Flags::default()
+    };
+    assert_eq!(
+        resolve(&zero_timeout, "", &no_env),
+        "the request timeout must be greater than zero"
+    );
+    assert_eq!(
+        resolve(&Flags::default(), "fee = 120", &no_env),
+        "fee: fee '120' must be below 100%"
+    );
+    assert!(
+        resolve(&Flags::default(), "round_mode = \"bankers\"", &no_env)
+            .starts_with("round_mode: unknown rounding mode 'bankers'")
+    );
+    assert_eq!(
+        resolve(&Flags::default(), "[aliases]\neur = \"PLN\"", &no_env),
+        "aliases: alias 'eur' collides with the currency code EUR; pick another name"
+    );
+    assert_eq!(
+        resolve(&Flags::default(), "[pairs]\ntrip = \"EUR-PLN\"", &no_env),
+        "pairs: trip: invalid pair 'EUR-PLN', expected FROM/TO, e.g. EUR/PLN"
+    );
+}
+
+#[test]
+fn test_aliases() {
+    let table = |entries: &[(&str, &str)]| -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, target)| (name.to_string(), target.to_string()))
+            .collect()
+    };
+    let aliases = Aliases::new(&table(&[
+        ("home", "PLN"),
+        ("US", "usd"),
+        ("casa", "Home"),
+        ("hogar", "casa"),
+    ]))
+    .unwrap();
+    // Names are matched in any case, and an alias may name another one.
+    assert_eq!(aliases.resolve("HOME").unwrap(), "PLN");
+    assert_eq!(aliases.resolve(" us ").unwrap(), "USD");
+    assert_eq!(aliases.resolve("hogar").unwrap(), "PLN");
+    // Anything else is taken for a code, to be validated like any other.
+    assert_eq!(aliases.resolve("eur").unwrap(), "EUR");
+    assert_eq!(aliases.resolve("nowhere").unwrap(), "NOWHERE");
+    assert_eq!(Aliases::default().resolve("pln").unwrap(), "PLN");
+
+    assert_eq!(
+        Aliases::new(&table(&[("usd", "PLN")])).unwrap_err(),
+        "alias 'usd' collides with the currency code USD; pick another name"
+    );
+    assert_eq!(
+        Aliases::new(&table(&[("btc", "USD")])).unwrap_err(),
+        "alias 'btc' collides with the currency code BTC; pick another name"
+    );
+    assert_eq!(
+        Aliases::new(&table(&[("home", "PLN"), ("Home", "EUR")])).unwrap_err(),
+        "alias 'home' is defined twice"
+    );
+    assert_eq!(
+        Aliases::new(&table(&[("home", "poland")])).unwrap_err(),
+        "alias 'home' leads to 'poland', which is neither a currency code nor an alias"
+    );
+    assert_eq!(
+        Aliases::new(&table(&[("a", "b"), ("b", "c"), ("c", "A")])).unwrap_err(),
+        "alias 'a' loops back on itself: a -> b -> c -> a"
+    );
+    assert!(Aliases::new(&table(&[("my_home", "PLN")]))
+        .unwrap_err()
+        .starts_with("invalid alias name 'my_home'"));
+    assert!(Aliases::new(&table(&[("in", "PLN")]))
+        .unwrap_err()
+        .contains("filler word"));
+
+    // Changes keep the aliases valid or are refused.
+    let mut edited = aliases.clone();
+    edited.set("Trip", "casa").unwrap();
+    assert_eq!(edited.get("trip"), Some("casa"));
+    edited.set("trip", "eur").unwrap();
+    assert_eq!(edited.get("trip"), Some("EUR"));
+    assert!(edited.set("gbp", "GBP").is_err());
+    assert!(edited.remove("casa").is_err());
+    assert_eq!(edited.remove("hogar"), Ok(true));
+    assert_eq!(edited.remove("hogar"), Ok(false));
+
+    assert_eq!(
+        parse_pair("eur/casa", &aliases).unwrap(),
+        ("EUR".to_string(), "PLN".to_string())
+    );
+    assert!(parse_pair("EUR/", &aliases).is_err());
+
+    // Batch rows and interactive lines read them too.
+    let row = parse_batch_line("casa,us,12.50")
+        .unwrap()
+        .resolve(&aliases)
+        .unwrap();
+    assert_eq!((row.from.as_str(), row.to.as_str()), ("PLN", "USD"));
+    let row = parse_batch_line("100 home eur")
+        .unwrap()
+        .resolve(&aliases)
+        .unwrap();
+    assert_eq!((row.from.as_str(), row.to.as_str()), ("PLN", "EUR"));
+    assert_eq!(
+        parse_repl_line("100 home us")
+            .unwrap()
+            .resolve(&aliases)
+            .unwrap(),
+        ReplCommand::Convert {
+            amount: dec("100"),
+            from: "PLN".to_string(),
+            to: "USD".to_string(),
+        }
+    );
+    assert_eq!(
+        parse_repl_line("list hogar")
+            .unwrap()
+            .resolve(&aliases)
+            .unwrap(),
+        ReplCommand::List(Some("PLN".to_string()))
+    );
+    assert_eq!(
+        parse_repl_line("swap").unwrap().resolve(&aliases).unwrap(),
+        ReplCommand::Swap
+    );
+}
+
+#[test]
+fn test_aliases_in_config_file() {
+    let aliases = |entries: &[(&str, &str)]| {
+        let table: BTreeMap<String, String> = entries
+            .iter()
+            .map(|(name, target)| (name.to_string(), target.to_string()))
+            .collect();
+        Aliases::new(&table).unwrap()
+    };
+    let contents = "# my settings\nprecision = 3\n\n[aliases]\nhome = \"PLN\" # where I live\n\n[pairs]\ntrip = \"EUR/home\"\n";
+    // The table is rewritten in place, and the rest of the file kept as it was.
+    assert_eq!(
+        with_aliases(contents, &aliases(&[("home", "PLN"), ("us", "USD")])).unwrap(),
+        "# my settings\nprecision = 3\n\n[aliases]\nhome = \"PLN\"\nus = \"USD\"\n\n[pairs]\ntrip = \"EUR/home\"\n"
+    );
+    assert_eq!(
+        with_aliases(contents, &Aliases::default()).unwrap(),
+        "# my settings\nprecision = 3\n\n[pairs]\ntrip = \"EUR/home\"\n"
+    );
+    // Without one it goes at the end, and an empty file gets just the table.
+    assert_eq!(
+        with_aliases("precision = 3\n", &aliases(&[("home", "PLN")])).unwrap(),
+        "precision = 3\n\n[aliases]\nhome = \"PLN\"\n"
+    );
+    assert_eq!(
+        with_aliases("", &aliases(&[("home", "PLN")])).unwrap(),
+        "[aliases]\nhome = \"PLN\"\n"
+    );
+    assert_eq!(with_aliases("", &Aliases::default()).unwrap(), "");
+    // An inline table can't be edited line by line.
+    assert!(with_aliases("aliases = { home = \"PLN\" }\n", &aliases(&[("us", "USD")])).is_err());
+}
+
+#[test]
+fn test_duration_parsing() {
+    assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+    assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(900));
+    assert_eq!(parse_duration("6h").unwrap(), Duration::from_secs(6 * 3600));
+    assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+    assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+    assert_eq!(parse_duration("0").unwrap(), Duration::ZERO);
+    assert_eq!(
+        parse_duration("15x").unwrap_err(),
+        "invalid duration '15x', expected a number with an optional s, m, h or d suffix (e.g. 15m)"
+    );
+    assert!(parse_duration("m").is_err());
+    assert!(parse_duration("-5m").is_err());
+    assert!(parse_duration("").is_err());
+}
+
+#[test]
+fn test_open_er_api_envelope() {
+    let body = r#"{
+        "result": "success",
+        "provider": "https://www.exchangerate-api.com",
+        "time_last_update_unix": 1712102551,
+        "base_code": "USD",
+        "rates": {"USD": 1, "EUR": 0.926, "PLN": 3.98}
+    }"#;
+    let response: RatesResponse = serde_json::from_str(body).unwrap();
+    let rates = Rates::try_from(response).unwrap();
+    assert_eq!(rates.rates.len(), 3);
+    assert_eq!(rates.rates.get("EUR"), Some(&0.926));
+
+    let renamed: RatesResponse =
+        serde_json::from_str(r#"{"result": "success", "conversion_rates": {"EUR": 0.9}}"#).unwrap();
+    assert_eq!(
+        Rates::try_from(renamed).unwrap().rates.get("EUR"),
+        Some(&0.9)
+    );
+
+    let error: RatesResponse =
+        serde_json::from_str(r#"{"result": "error", "error-type": "unsupported-code"}"#).unwrap();
+    assert_eq!(
+        Rates::try_from(error).err().unwrap(),
+        "Provider returned an error: unsupported-code"
+    );
+}
+
+#[test]
+fn test_rates_effective_date() {
+    let effective = |body: &str| {
+        let response: RatesResponse = serde_json::from_str(body).unwrap();
+        Rates::try_from(response).unwrap().effective_date
+    };
+    assert_eq!(
+        effective(r#"{"date": "2024-03-01", "rates": {"PLN": 4.31}}"#).as_deref(),
+        Some("2024-03-01")
+    );
+    assert_eq!(
+        effective(r#"{"year": 2024, "month": 3, "day": 1, "conversion_rates": {"PLN": 4.31}}"#)
+            .as_deref(),
+        Some("2024-03-01")
+    );
+    assert_eq!(
+        effective(r#"{"year": 2024, "month": 2, "day": 30, "rates": {}}"#),
+        None
+    );
+    assert_eq!(effective(r#"{"rates": {"PLN": 4.31}}"#), None);
+}
+
+#[test]
+fn test_round_modes() {
+    // 0.125, 0.375 and 1.005 sit exactly on the half at two decimals.
+    assert_eq!(
+        round_amount(dec("0.125"), 2, RoundMode::Nearest),
+        dec("0.13")
+    );
+    assert_eq!(
+        round_amount(dec("0.125"), 2, RoundMode::HalfEven),
+        dec("0.12")
+    );
+    assert_eq!(
+        round_amount(dec("0.375"), 2, RoundMode::Nearest),
+        dec("0.38")
+    );
+    assert_eq!(
+        round_amount(dec("0.375"), 2, RoundMode::HalfEven),
+        dec("0.38")
+    );
+    assert_eq!(
+        round_amount(dec("1.005"), 2, RoundMode::Nearest),
+        dec("1.01")
+    );
+    assert_eq!(round_amount(dec("1.005"), 2, RoundMode::HalfEven), dec("1"));
+
+    assert_eq!(round_amount(dec("2.341"), 2, RoundMode::Ceil), dec("2.35"));
+    assert_eq!(round_amount(dec("2.349"), 2, RoundMode::Floor), dec("2.34"));
+    assert_eq!(round_amount(dec("2.5"), 0, RoundMode::HalfEven), dec("2"));
+    assert_eq!("half-even".parse::<RoundMode>(), Ok(RoundMode::HalfEven));
+    assert_eq!("half-up".parse::<RoundMode>(), Ok(RoundMode::Nearest));
+    assert_eq!("up".parse::<RoundMode>(), Ok(RoundMode::Ceil));
+    assert_eq!("down".parse::<RoundMode>(), Ok(RoundMode::Floor));
+    assert_eq!(
+        "sideways".parse::<RoundMode>(),
+        Err("unknown rounding mode 'sideways', expected one of nearest, half-up, half-even, floor, down, ceil, up".to_string())
+    );
+}
+
+#[test]
+fn test_round_mode_boundaries() {
+    // Each row is half-up, half-even, up and down.
+    let cases = [
+        ("1.005", ["1.01", "1", "1.01", "1"]),
+        ("1.015", ["1.02", "1.02", "1.02", "1.01"]),
+        ("2.115", ["2.12", "2.12", "2.12", "2.11"]),
+        ("2.125", ["2.13", "2.12", "2.13", "2.12"]),
+        ("0.001", ["0", "0", "0.01", "0"]),
+        ("3.10", ["3.1", "3.1", "3.1", "3.1"]),
+    ];
+    let modes = ["half-up", "half-even", "up", "down"].map(|mode| mode.parse().unwrap());
+    for (value, expected) in cases {
+        for (mode, expected) in modes.into_iter().zip(expected) {
+            assert_eq!(
+                round_amount(dec(value), 2, mode),
+                dec(expected),
+                "{} rounded {:?}",
+                value,
+                mode
+            );
+        }
+    }
+
+    // The fee-adjusted amount is rounded on its own: 1 at a rate of 1 less 1.5% is 0.985.
+    let after_fee = modes.map(|mode| apply_fee(dec("1"), 1.0, dec("1.5"), 2, mode).after_fee);
+    assert_eq!(after_fee, ["0.99", "0.98", "0.99", "0.98"].map(dec));
+    let fees = modes.map(|mode| apply_fee(dec("1"), 1.0, dec("1.5"), 2, mode).fee);
+    assert_eq!(fees, ["0.01", "0.02", "0.01", "0.02"].map(dec));
+}
+
+#[test]
+fn test_rates_filtering() {
+    let rates = Rates {
+        provider: "frankfurter".to_string(),
+        ..Rates::from_map(HashMap::from([
+            ("USD".to_string(), 1.0),
+            ("EUR".to_string(), 0.92),
+            ("GBP".to_string(), 0.79),
+        ]))
+    };
+    let codes = |rates: Rates| {
+        let mut codes: Vec<String> = rates.rates.into_keys().collect();
+        codes.sort();
+        codes.join(",")
+    };
+
+    let only = rates.filtered(&["GBP", "XYZ", "EUR"]);
+    assert_eq!(only.provider, "frankfurter");
+    assert_eq!(codes(only), "EUR,GBP");
+    assert_eq!(codes(rates.excluding(&["USD", "XYZ"])), "EUR,GBP");
+    assert_eq!(codes(rates.filtered(&[])), "");
+    assert_eq!(codes(rates.excluding(&[])), "EUR,GBP,USD");
+    assert_eq!(rates.missing(&["XYZ", "EUR", "ABC"]), vec!["XYZ", "ABC"]);
+}
+
+#[test]
+fn test_rates_sorting() {
+    let rates = Rates::from_map(HashMap::from([
+        ("USD".to_string(), 1.0),
+        ("EUR".to_string(), 0.9),
+        ("PLN".to_string(), 4.0),
+        ("CHF".to_string(), 0.9),
+        ("XAU".to_string(), f64::NAN),
+        ("ZWL".to_string(), 0.0),
+        ("ABC".to_string(), -0.0),
+    ]));
+    let codes = |sort: &str, order: &str| {
+        rates
+            .sorted_entries(sort.parse().unwrap(), order.parse().unwrap())
+            .into_iter()
+            .map(|(code, _)| code)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    assert_eq!(codes("code", "asc"), "ABC,CHF,EUR,PLN,USD,XAU,ZWL");
+    assert_eq!(codes("code", "desc"), "ZWL,XAU,USD,PLN,EUR,CHF,ABC");
+    // Ties (CHF/EUR, and both zeros) stay in code order; NaN is last either way.
+    assert_eq!(codes("rate", "asc"), "ABC,ZWL,CHF,EUR,USD,PLN,XAU");
+    assert_eq!(codes("rate", "desc"), "PLN,USD,CHF,EUR,ABC,ZWL,XAU");
+    assert_eq!(codes("value", "desc"), codes("rate", "desc"));
+
+    assert_eq!(SortKey::Code.default_order(), SortOrder::Asc);
+    assert_eq!(SortKey::Rate.default_order(), SortOrder::Desc);
+    assert_eq!(SortKey::Change.default_order(), SortOrder::Desc);
+    // The changes aren't in the rates, so they sort later; until then rows keep code order.
+    assert_eq!(codes("change", "desc"), codes("code", "asc"));
+    assert!("size".parse::<SortKey>().is_err());
+    assert!("up".parse::<SortOrder>().is_err());
+}